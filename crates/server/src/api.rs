@@ -1,103 +1,2600 @@
 use axum::{
     Json, Router,
-    extract::{Path, Query, State},
-    routing::{delete, get, post},
+    body::Body,
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::{from_fn, from_fn_with_state},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{MethodRouter, delete, get, patch, post},
 };
+use encoding_rs::Encoding;
+use futures::{Stream, StreamExt, stream::BoxStream};
+use serde::Serialize;
 use shared::{
     abstract_trait::UserServiceTrait,
+    config::{BulkFailureStatus, SlowConsumerPolicy},
+    csv_import::ImportReport,
     database::SharedState,
     domain::{
-        ApiResponse, ApiResponsePagination, CreateUserRequest, FindAllUserRequest, KafkaEvent,
-        SearchQuery, UpdateUserRequest, UserResponse,
+        ApiResponse, ApiResponsePagination, BulkUpsertResult, ChangesQuery, CreateUserRequest,
+        CsvDialect, DlqQuery, EmailChangeConfirm, EmailChangeRequest, EmailChangeToken,
+        EmailDomainCount, ExportFilter, ExternalUserResponse, FieldsQuery, FindAllUserRequest,
+        ImportProgress, KafkaEvent, MergeUsersRequest, RetryCounts, SearchQuery, SimilarUser,
+        TimeseriesQuery, UpdateUserRequest, User, UserResponse,
     },
+    edit_lock::EditLock,
     errors::AppError,
-    service::UserServiceImpl,
+    feature_flags::{Feature, FeatureFlags},
+    kafka::dlq::DlqEnvelope,
+    metrics::MetricsSnapshot,
+    schema::{REQUEST_SCHEMAS, request_schema},
+    service::{UserServiceImpl, write_csv},
+    shutdown::ShutdownSignal,
+    stats::{StatsBucket, parse_window},
+};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{
+    io::AsyncReadExt,
+    sync::broadcast::{self, error::RecvError},
+    time::{Instant, timeout_at},
+};
+
+use crate::{
+    extract::{AdminGuard, BulkJson, LenientJson, LockOwner, ValidJson, ValidQuery},
+    middleware::{
+        JobLimiter, RouteRateLimiter, SlowRequestLayer, SubscriberLimiter, drain, feature_disabled,
+        job_limit, json_format, retry_after, route_rate_limit, subscriber_limit, timezone,
+        track_metrics, trailing_slash,
+    },
 };
-use std::sync::Arc;
+
+/// Serializes a page of users, refusing it if it exceeds
+/// `max_list_response_bytes`.
+fn capped_json<T: Serialize>(state: &SharedState, value: &T) -> Result<Response, AppError> {
+    let body = serde_json::to_vec(value).map_err(|e| AppError::Internal(e.to_string()))?;
+    let max = state.config.max_list_response_bytes;
+    if max > 0 && body.len() > max {
+        return Err(AppError::PayloadTooLarge(format!(
+            "Response of {} bytes exceeds maximum of {max}; request a smaller page_size",
+            body.len()
+        )));
+    }
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
 
 async fn get_users(
     State(state): State<SharedState>,
-    Query(req): Query<FindAllUserRequest>,
-) -> Result<Json<ApiResponsePagination<Vec<UserResponse>>>, AppError> {
-    Ok(Json(state.get_users(req).await?))
+    ValidQuery(req): ValidQuery<FindAllUserRequest>,
+) -> Result<Response, AppError> {
+    capped_json(&state, &state.get_users(req).await?)
+}
+
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+/// `HEAD /users`: the total for the same `search`/`search_field` filter as
+/// the listing, in `X-Total-Count`, with no body.
+async fn count_users(
+    State(state): State<SharedState>,
+    ValidQuery(req): ValidQuery<FindAllUserRequest>,
+) -> Result<Response, AppError> {
+    let total = state.count_users(&req).await?;
+    Ok([(TOTAL_COUNT_HEADER, total.to_string())].into_response())
+}
+
+async fn get_changes(
+    State(state): State<SharedState>,
+    ValidQuery(query): ValidQuery<ChangesQuery>,
+) -> Result<Response, AppError> {
+    capped_json(&state, &state.get_changes(query).await?)
+}
+
+async fn get_external_users(
+    State(state): State<SharedState>,
+    ValidQuery(req): ValidQuery<FindAllUserRequest>,
+) -> Result<Response, AppError> {
+    let resp = state.get_users(req).await?;
+    let external: ApiResponsePagination<Vec<ExternalUserResponse>> = ApiResponsePagination {
+        success: resp.success,
+        data: resp.data.into_iter().map(Into::into).collect(),
+        page: resp.page,
+        page_size: resp.page_size,
+        total: resp.total,
+    };
+    capped_json(&state, &external)
+}
+
+async fn get_external_user_by_id(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<ExternalUserResponse>>, AppError> {
+    match state.find_by_id(&id).await? {
+        Some(resp) => Ok(Json(ApiResponse {
+            success: resp.success,
+            data: resp.data.into(),
+        })),
+        None => Err(AppError::UserNotFound),
+    }
 }
 
 async fn create_user(
     State(state): State<SharedState>,
-    Json(req): Json<CreateUserRequest>,
+    ValidJson(req): ValidJson<CreateUserRequest>,
 ) -> Result<Json<ApiResponse<UserResponse>>, AppError> {
     Ok(Json(state.create_user(&req).await?))
 }
 
+/// Always answers with the per-item report; `bulk_failure_status` picks the
+/// status when some elements failed.
+async fn bulk_create_users(
+    State(state): State<SharedState>,
+    BulkJson(req): BulkJson<CreateUserRequest>,
+) -> Result<Response, AppError> {
+    let report = state.bulk_create_users(req).await?;
+    let status = match state.config.bulk_failure_status {
+        _ if report.failed == 0 => StatusCode::OK,
+        BulkFailureStatus::Ok => StatusCode::OK,
+        BulkFailureStatus::MultiStatus => StatusCode::MULTI_STATUS,
+        BulkFailureStatus::Unprocessable => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    Ok((
+        status,
+        Json(ApiResponse {
+            success: report.failed == 0,
+            data: report,
+        }),
+    )
+        .into_response())
+}
+
+async fn bulk_upsert_users(
+    State(state): State<SharedState>,
+    LockOwner(owner): LockOwner,
+    BulkJson(req): BulkJson<CreateUserRequest>,
+) -> Result<Json<ApiResponse<BulkUpsertResult>>, AppError> {
+    Ok(Json(state.bulk_upsert_users(req, owner.as_deref()).await?))
+}
+
+/// Takes a JSON array of ids, capped at `max_bulk_size`.
+async fn bulk_get_users(
+    State(state): State<SharedState>,
+    BulkJson(ids): BulkJson<String>,
+) -> Result<Json<ApiResponse<Vec<UserResponse>>>, AppError> {
+    Ok(Json(state.find_by_ids(ids).await?))
+}
+
 async fn get_user_by_id(
     State(state): State<SharedState>,
     Path(id): Path<String>,
-) -> Result<Json<ApiResponse<UserResponse>>, AppError> {
-    match state.find_by_id(&id).await? {
+    ValidQuery(query): ValidQuery<FieldsQuery>,
+) -> Result<Response, AppError> {
+    let fields = query
+        .selected(&UserResponse::FIELDS)
+        .map_err(AppError::ValidationError)?;
+    let resp = state.find_by_id(&id).await?.ok_or(AppError::UserNotFound)?;
+    let etag = resp.data.etag.clone();
+    match fields {
+        Some(fields) => Ok(with_etag(&etag, Json(project(&resp, &fields)?))),
+        None => Ok(with_etag(&etag, Json(resp))),
+    }
+}
+
+/// Drops every field of `data` not listed in `fields`.
+fn project<T: Serialize>(
+    resp: &ApiResponse<T>,
+    fields: &[String],
+) -> Result<serde_json::Value, AppError> {
+    let mut value = serde_json::to_value(resp).map_err(|e| AppError::Internal(e.to_string()))?;
+    if let Some(data) = value.get_mut("data").and_then(|data| data.as_object_mut()) {
+        data.retain(|name, _| fields.contains(name));
+    }
+    Ok(value)
+}
+
+async fn update_user(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    LockOwner(owner): LockOwner,
+    headers: HeaderMap,
+    ValidJson(req): ValidJson<UpdateUserRequest>,
+) -> Result<Response, AppError> {
+    match state
+        .update_user(&id, &req, owner.as_deref(), if_match(&headers))
+        .await?
+    {
+        Some(resp) => Ok(with_etag(&resp.data.etag.clone(), Json(resp))),
+        None => Err(AppError::UserNotFound),
+    }
+}
+
+/// The raw `If-Match` value, compared by the repository atomically with
+/// the write.
+fn if_match(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::IF_MATCH)
+        .map(|value| value.to_str().unwrap_or_default())
+}
+
+fn with_etag(etag: &str, body: impl IntoResponse) -> Response {
+    let mut response = body.into_response();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+async fn update_user_by_email(
+    State(state): State<SharedState>,
+    Path(email): Path<String>,
+    LockOwner(owner): LockOwner,
+    headers: HeaderMap,
+    ValidJson(req): ValidJson<UpdateUserRequest>,
+) -> Result<Response, AppError> {
+    match state
+        .update_user_by_email(&email, &req, owner.as_deref(), if_match(&headers))
+        .await?
+    {
+        Some(resp) => Ok(with_etag(&resp.data.etag.clone(), Json(resp))),
+        None => Err(AppError::UserNotFound),
+    }
+}
+
+async fn find_similar(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<SimilarUser>>>, AppError> {
+    match state.find_similar(&id).await? {
         Some(resp) => Ok(Json(resp)),
         None => Err(AppError::UserNotFound),
     }
 }
 
-async fn update_user(
+async fn lock_user(
     State(state): State<SharedState>,
     Path(id): Path<String>,
-    Json(req): Json<UpdateUserRequest>,
-) -> Result<Json<ApiResponse<UserResponse>>, AppError> {
-    match state.update_user(&id, &req).await? {
+    owner: LockOwner,
+) -> Result<Json<ApiResponse<EditLock>>, AppError> {
+    match state.lock_user(&id, &owner.required()?).await? {
+        Some(resp) => Ok(Json(resp)),
+        None => Err(AppError::UserNotFound),
+    }
+}
+
+async fn unlock_user(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    owner: LockOwner,
+) -> Result<Json<ApiResponse<()>>, AppError> {
+    Ok(Json(state.unlock_user(&id, &owner.required()?).await?))
+}
+
+async fn request_email_change(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    ValidJson(body): ValidJson<EmailChangeRequest>,
+) -> Result<Json<ApiResponse<EmailChangeToken>>, AppError> {
+    match state.request_email_change(&id, &body).await? {
         Some(resp) => Ok(Json(resp)),
         None => Err(AppError::UserNotFound),
     }
 }
 
+async fn confirm_email_change(
+    State(state): State<SharedState>,
+    LockOwner(owner): LockOwner,
+    LenientJson(body): LenientJson<EmailChangeConfirm>,
+) -> Result<Json<ApiResponse<UserResponse>>, AppError> {
+    Ok(Json(
+        state
+            .confirm_email_change(&body.token, owner.as_deref())
+            .await?,
+    ))
+}
+
 async fn delete_user(
     State(state): State<SharedState>,
     Path(email): Path<String>,
-) -> Result<Json<ApiResponse<()>>, AppError> {
-    Ok(Json(state.delete_user(&email).await?))
+    LockOwner(owner): LockOwner,
+) -> Result<Response, AppError> {
+    let result = state.delete_user(&email, owner.as_deref()).await?;
+    delete_response(&state, result)
+}
+
+async fn delete_user_by_id(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    LockOwner(owner): LockOwner,
+) -> Result<Response, AppError> {
+    let result = state.delete_user_by_id(&id, owner.as_deref()).await?;
+    delete_response(&state, result)
+}
+
+fn delete_response(
+    state: &SharedState,
+    result: Option<ApiResponse<()>>,
+) -> Result<Response, AppError> {
+    match result {
+        Some(resp) => Ok(Json(resp).into_response()),
+        None if state.config.delete_idempotent => Ok(StatusCode::NO_CONTENT.into_response()),
+        None => Err(AppError::UserNotFound),
+    }
 }
 
 async fn search_users(
     State(state): State<SharedState>,
-    Query(query): Query<SearchQuery>,
-) -> Result<Json<ApiResponsePagination<Vec<UserResponse>>>, AppError> {
-    let req = FindAllUserRequest {
-        page: 1,
-        page_size: 100,
-        search: Some(query.q),
-    };
-    Ok(Json(state.get_users(req).await?))
+    ValidQuery(query): ValidQuery<SearchQuery>,
+) -> Result<Response, AppError> {
+    capped_json(&state, &state.search_users(query).await?)
 }
 
-async fn export_csv(State(state): State<SharedState>) -> Result<String, AppError> {
-    let event = KafkaEvent::ExportCsv {
-        path: "data.csv".to_string(),
-    };
-    state
-        .send_kafka_event(&event)
+/// Queues an export job and answers with its id and one-time download link.
+async fn export_csv(
+    State(state): State<SharedState>,
+    ValidQuery(filter): ValidQuery<ExportFilter>,
+    ValidQuery(dialect): ValidQuery<CsvDialect>,
+) -> Result<Response, AppError> {
+    dialect.columns().map_err(AppError::ValidationError)?;
+    queue_export(&state, &filter, dialect).await
+}
+
+async fn download_csv(
+    State(state): State<SharedState>,
+    ValidQuery(filter): ValidQuery<ExportFilter>,
+    ValidQuery(dialect): ValidQuery<CsvDialect>,
+) -> Result<Response, AppError> {
+    dialect.columns().map_err(AppError::ValidationError)?;
+    if state.export_too_large().await? {
+        return queue_export(&state, &filter, dialect).await;
+    }
+    let deadline = Instant::now() + Duration::from_secs(state.config.export_timeout_secs);
+    let users = timeout_at(deadline, state.export_users(&filter))
         .await
-        .map_err(|e| AppError::Internal(format!("Kafka send failed: {}", e)))?;
-    Ok("📨 Export job queued via Kafka".to_string())
+        .map_err(|_| AppError::Timeout("Export timed out".to_string()))??;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"users_export.csv\"",
+            ),
+        ],
+        Body::from_stream(csv_stream(users, dialect, deadline)),
+    )
+        .into_response())
+}
+
+/// Answers with `202 Accepted` and the queued job, for `POST /users/export`
+/// and for inline exports that are too large. Jobs always produce CSV,
+/// whichever format was requested.
+async fn queue_export(
+    state: &SharedState,
+    filter: &ExportFilter,
+    dialect: CsvDialect,
+) -> Result<Response, AppError> {
+    let job = state.queue_export(filter, dialect).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse {
+            success: true,
+            data: job,
+        }),
+    )
+        .into_response())
+}
+
+const EXPORT_CHUNK_SIZE: usize = 500;
+
+/// Streams users as CSV chunks. If the deadline passes mid-stream the body
+/// ends with an error, so the client sees a truncated response instead of a
+/// silently incomplete file.
+fn csv_stream(
+    users: Vec<User>,
+    dialect: CsvDialect,
+    deadline: Instant,
+) -> impl Stream<Item = Result<Vec<u8>, std::io::Error>> {
+    futures::stream::unfold(
+        (users, 0, dialect),
+        move |(users, offset, dialect)| async move {
+            if offset >= users.len() {
+                return None;
+            }
+            if Instant::now() >= deadline {
+                let err = std::io::Error::new(std::io::ErrorKind::TimedOut, "Export timed out");
+                return Some((Err(err), (users, usize::MAX, dialect)));
+            }
+
+            let end = (offset + EXPORT_CHUNK_SIZE).min(users.len());
+            let chunk = write_csv(&users[offset..end], offset == 0, &dialect)
+                .map_err(|e| std::io::Error::other(e.to_string()));
+            let next = if chunk.is_ok() { end } else { usize::MAX };
+            Some((chunk, (users, next, dialect)))
+        },
+    )
+}
+
+async fn download_json(
+    State(state): State<SharedState>,
+    ValidQuery(filter): ValidQuery<ExportFilter>,
+) -> Result<Response, AppError> {
+    if state.export_too_large().await? {
+        let dialect = CsvDialect {
+            bom: Some(state.config.csv_bom),
+            ..CsvDialect::default()
+        };
+        return queue_export(&state, &filter, dialect).await;
+    }
+    let deadline = Instant::now() + Duration::from_secs(state.config.export_timeout_secs);
+    let users = state.stream_users(filter);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"users_export.json\"",
+            ),
+        ],
+        Body::from_stream(json_array_stream(users, deadline)),
+    )
+        .into_response())
+}
+
+/// Streams users as a single JSON array, up to `EXPORT_CHUNK_SIZE` elements
+/// at a time. The opening bracket goes out with the first chunk and the
+/// closing one on its own at the end, so an empty export is still a valid
+/// `[]`.
+fn json_array_stream(
+    users: BoxStream<'static, Result<User, AppError>>,
+    deadline: Instant,
+) -> impl Stream<Item = Result<Vec<u8>, std::io::Error>> {
+    let batches = users.ready_chunks(EXPORT_CHUNK_SIZE);
+    futures::stream::unfold(
+        (batches, Some(true)),
+        move |(mut batches, first)| async move {
+            let first = first?;
+            if Instant::now() >= deadline {
+                let err = std::io::Error::new(std::io::ErrorKind::TimedOut, "Export timed out");
+                return Some((Err(err), (batches, None)));
+            }
+
+            let mut chunk = Vec::new();
+            if first {
+                chunk.push(b'[');
+            }
+            let Some(batch) = batches.next().await else {
+                chunk.push(b']');
+                return Some((Ok(chunk), (batches, None)));
+            };
+            for (i, user) in batch.into_iter().enumerate() {
+                let written = user
+                    .map_err(|e| std::io::Error::other(e.to_string()))
+                    .and_then(|user| {
+                        if !first || i > 0 {
+                            chunk.push(b',');
+                        }
+                        serde_json::to_writer(&mut chunk, &user).map_err(std::io::Error::other)
+                    });
+                if let Err(e) = written {
+                    return Some((Err(e), (batches, None)));
+                }
+            }
+            Some((Ok(chunk), (batches, Some(false))))
+        },
+    )
+}
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serves a finished export job. The token is used up before the file is
+/// opened, so a second request gets `404` even if the first one is still
+/// streaming.
+async fn download_export(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+) -> Result<Response, AppError> {
+    let path = state.take_download(&token).await?;
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let filename = std::path::Path::new(&path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("users_export.csv")
+        .to_string();
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/csv; charset=utf-8"),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&format!("attachment; filename=\"{filename}\""))
+                    .map_err(|e| AppError::Internal(e.to_string()))?,
+            ),
+        ],
+        Body::from_stream(file_stream(file)),
+    )
+        .into_response())
+}
+
+fn file_stream(file: tokio::fs::File) -> impl Stream<Item = Result<Vec<u8>, std::io::Error>> {
+    futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; DOWNLOAD_CHUNK_SIZE];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some((Ok(chunk), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
 }
 
 async fn import_csv(State(state): State<SharedState>) -> Result<String, AppError> {
     let event = KafkaEvent::ImportCsv {
         path: "users_export.csv".to_string(),
     };
-    state
-        .send_kafka_event(&event)
-        .await
-        .map_err(|e| AppError::Internal(format!("Kafka send failed: {}", e)))?;
+    state.send_kafka_event(&event).await?;
     Ok("📨 Import job queued via Kafka".to_string())
 }
 
-pub fn user_routes(state: Arc<UserServiceImpl>) -> Router {
-    Router::new()
-        .route("/users", get(get_users).post(create_user))
-        .route("/users/{id}", get(get_user_by_id).put(update_user))
-        .route("/users/email/{email}", delete(delete_user))
+async fn read_csv_upload(multipart: &mut Multipart) -> Result<Vec<u8>, AppError> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::ValidationError(e.body_text()))?
+    {
+        if field.name() == Some("file") {
+            let content_type = field.content_type().map(str::to_owned);
+            let file_name = field.file_name().map(str::to_owned);
+            let bytes = field
+                .bytes()
+                .await
+                .map_err(|e| AppError::ValidationError(e.body_text()))?;
+            ensure_csv_upload(content_type.as_deref(), file_name.as_deref(), &bytes)?;
+            return Ok(bytes.to_vec());
+        }
+    }
+    Err(AppError::ValidationError(
+        "Missing `file` field".to_string(),
+    ))
+}
+
+async fn validate_csv_upload(
+    State(state): State<SharedState>,
+    mut multipart: Multipart,
+) -> Result<Json<ApiResponse<ImportReport>>, AppError> {
+    let contents = read_csv_upload(&mut multipart).await?;
+    Ok(Json(ApiResponse {
+        success: true,
+        data: state.validate_import(contents).await?,
+    }))
+}
+
+/// Progress updates buffered per import stream before a slow client lags.
+const PROGRESS_BUFFER: usize = 16;
+
+async fn upload_csv(
+    State(state): State<SharedState>,
+    mut multipart: Multipart,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let contents = read_csv_upload(&mut multipart).await?;
+
+    let (tx, rx) = broadcast::channel(PROGRESS_BUFFER);
+    let service = state.clone();
+    tokio::spawn(async move {
+        let outcome = match service.import_csv_bytes(contents, Some(tx.clone())).await {
+            Ok(imported) => ImportProgress::Completed { imported },
+            Err(e) => ImportProgress::Failed {
+                error: e.to_string(),
+            },
+        };
+        let _ = tx.send(outcome);
+    });
+
+    let stream = progress_stream(rx, state.config.slow_consumer_policy)
+        .map(|progress| Event::default().json_data(progress));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Progress updates from `rx` until the import ends, with lag handled per
+/// `policy`.
+fn progress_stream(
+    rx: broadcast::Receiver<ImportProgress>,
+    policy: SlowConsumerPolicy,
+) -> impl Stream<Item = ImportProgress> {
+    futures::stream::unfold(rx, move |mut rx| async move {
+        let progress = match rx.recv().await {
+            Ok(progress) => progress,
+            Err(RecvError::Lagged(missed)) if policy == SlowConsumerPolicy::DropLagged => {
+                ImportProgress::Lagged { missed }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                eprintln!(
+                    "🐌 Dropping import progress stream {} events behind",
+                    missed
+                );
+                return None;
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        Some((progress, rx))
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    commit: &'static str,
+    built_at: String,
+}
+
+/// Build metadata baked in by `build.rs`.
+async fn get_version() -> Json<ApiResponse<VersionInfo>> {
+    let built_at = env!("BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|ts| ts.to_rfc3339())
+        .unwrap_or_default();
+
+    Json(ApiResponse {
+        success: true,
+        data: VersionInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("GIT_COMMIT"),
+            built_at,
+        },
+    })
+}
+
+/// JSON Schema of a request body by name, so clients can validate before
+/// sending. Unknown names list the available ones.
+async fn get_request_schema(Path(name): Path<String>) -> Result<Json<serde_json::Value>, AppError> {
+    request_schema(&name).map(Json).ok_or_else(|| {
+        AppError::NotFound(format!(
+            "Unknown schema {name}; available: {}",
+            REQUEST_SCHEMAS.join(", ")
+        ))
+    })
+}
+
+/// Readiness probe for load balancers. Once shutdown begins the drain
+/// middleware answers it with `503` like every other route.
+async fn get_ready() -> Json<ApiResponse<&'static str>> {
+    Json(ApiResponse {
+        success: true,
+        data: "ready",
+    })
+}
+
+async fn get_stats_timeseries(
+    State(state): State<SharedState>,
+    ValidQuery(query): ValidQuery<TimeseriesQuery>,
+) -> Result<Json<ApiResponse<Vec<StatsBucket>>>, AppError> {
+    let window = parse_window(query.window.as_deref().unwrap_or("1h"))?;
+    Ok(Json(ApiResponse {
+        success: true,
+        data: state.get_stats_timeseries(window).await,
+    }))
+}
+
+/// Every counter, gauge and latency percentile as one JSON document, for
+/// dashboards that do not scrape Prometheus.
+async fn get_stats_json(
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<MetricsSnapshot>>, AppError> {
+    Ok(Json(ApiResponse {
+        success: true,
+        data: state.metrics_snapshot().await?,
+    }))
+}
+
+/// Retry counters per operation, for alerting on a failing dependency.
+async fn get_retry_stats(
+    State(state): State<SharedState>,
+) -> Json<ApiResponse<BTreeMap<&'static str, RetryCounts>>> {
+    Json(ApiResponse {
+        success: true,
+        data: state.get_retry_counts(),
+    })
+}
+
+async fn clear_users(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<usize>>, AppError> {
+    Ok(Json(state.clear_users().await?))
+}
+
+/// The complete domain `User`, for debugging what is actually stored.
+async fn get_raw_user(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<User>>, AppError> {
+    let user = state.find_raw(&id).await?.ok_or(AppError::UserNotFound)?;
+    Ok(Json(ApiResponse {
+        success: true,
+        data: user,
+    }))
+}
+
+async fn merge_users(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+    LockOwner(owner): LockOwner,
+    ValidJson(req): ValidJson<MergeUsersRequest>,
+) -> Result<Json<ApiResponse<UserResponse>>, AppError> {
+    Ok(Json(state.merge_users(&req, owner.as_deref()).await?))
+}
+
+/// Dead-lettered events, read with a throwaway consumer group so nothing
+/// is committed.
+async fn get_dlq(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+    ValidQuery(query): ValidQuery<DlqQuery>,
+) -> Result<Json<ApiResponse<Vec<DlqEnvelope>>>, AppError> {
+    let limit = query.limit.unwrap_or(state.config.dlq_peek_limit);
+    Ok(Json(ApiResponse {
+        success: true,
+        data: state.peek_dlq(limit).await?,
+    }))
+}
+
+async fn route_not_found() -> AppError {
+    AppError::RouteNotFound
+}
+
+async fn method_not_allowed() -> AppError {
+    AppError::MethodNotAllowed
+}
+
+async fn email_domain_counts(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<Vec<EmailDomainCount>>>, AppError> {
+    Ok(Json(state.email_domain_counts().await?))
+}
+
+const CSV_CONTENT_TYPES: &[&str] = &[
+    "text/csv",
+    "text/plain",
+    "application/csv",
+    "application/vnd.ms-excel",
+];
+
+/// Rejects uploads that are clearly not CSV. The declared content type or
+/// the `.csv` extension must match, and the first bytes must look like text.
+fn ensure_csv_upload(
+    content_type: Option<&str>,
+    file_name: Option<&str>,
+    bytes: &[u8],
+) -> Result<(), AppError> {
+    let declared_csv = content_type
+        .map(|ct| {
+            let mime = ct.split(';').next().unwrap_or_default().trim();
+            CSV_CONTENT_TYPES.contains(&mime.to_lowercase().as_str())
+        })
+        .unwrap_or(false);
+    let named_csv = file_name
+        .map(|name| name.to_lowercase().ends_with(".csv"))
+        .unwrap_or(false);
+
+    let head = &bytes[..bytes.len().min(512)];
+    // Text in other encodings is transcoded on import, so only reject what
+    // looks binary. UTF-16 is full of NUL bytes and must carry a BOM.
+    let looks_textual = Encoding::for_bom(head).is_some() || !head.contains(&0);
+
+    if !(declared_csv || named_csv) || !looks_textual {
+        return Err(AppError::ValidationError("Expected CSV file".to_string()));
+    }
+    Ok(())
+}
+
+/// Leaves `route` as is while `feature` is enabled; otherwise every method
+/// on it answers `403 Forbidden`.
+fn gated(
+    flags: &FeatureFlags,
+    feature: Feature,
+    route: MethodRouter<SharedState>,
+) -> MethodRouter<SharedState> {
+    if flags.is_enabled(feature) {
+        route
+    } else {
+        route.route_layer(from_fn_with_state(feature, feature_disabled))
+    }
+}
+
+/// Counts `route` against the caller's import/export job limit.
+fn job(jobs: &Arc<JobLimiter>, route: MethodRouter<SharedState>) -> MethodRouter<SharedState> {
+    route.route_layer(from_fn_with_state(jobs.clone(), job_limit))
+}
+
+/// Counts `route`'s event stream against `max_sse_subscribers`.
+fn subscription(
+    subscribers: &Arc<SubscriberLimiter>,
+    route: MethodRouter<SharedState>,
+) -> MethodRouter<SharedState> {
+    route.route_layer(from_fn_with_state(subscribers.clone(), subscriber_limit))
+}
+
+/// `shutdown` flips the router into draining mode; see [`drain`].
+pub fn user_routes(state: Arc<UserServiceImpl>, shutdown: ShutdownSignal) -> Router {
+    let flags = &state.config.feature_flags;
+    let jobs = &Arc::new(JobLimiter::new(state.config.max_jobs_per_api_key));
+    let subscribers = &Arc::new(SubscriberLimiter::new(state.config.max_sse_subscribers));
+    let routes = Router::new()
+        .route("/users", get(get_users).head(count_users).post(create_user))
+        .route(
+            "/users/{id}",
+            get(get_user_by_id)
+                .put(update_user)
+                .patch(update_user)
+                .merge(gated(flags, Feature::Delete, delete(delete_user_by_id))),
+        )
+        .route("/users/{id}/similar", get(find_similar))
+        .route(
+            "/users/{id}/lock",
+            gated(flags, Feature::Locks, post(lock_user).delete(unlock_user)),
+        )
+        .route(
+            "/users/{id}/email-change",
+            gated(flags, Feature::EmailChange, post(request_email_change)),
+        )
+        .route(
+            "/users/email-change/confirm",
+            gated(flags, Feature::EmailChange, post(confirm_email_change)),
+        )
+        .route(
+            "/users/email/{email}",
+            patch(update_user_by_email).merge(gated(flags, Feature::Delete, delete(delete_user))),
+        )
+        .route(
+            "/users/bulk",
+            gated(flags, Feature::Bulk, post(bulk_create_users)),
+        )
+        .route(
+            "/users/bulk-upsert",
+            gated(flags, Feature::Bulk, post(bulk_upsert_users)),
+        )
+        .route("/users/bulk-get", post(bulk_get_users))
         .route("/users/search", get(search_users))
-        .route("/users/export", post(export_csv))
-        .route("/users/import", post(import_csv))
-        .with_state(state)
+        .route("/users/changes", get(get_changes))
+        .route(
+            "/users/export",
+            gated(flags, Feature::Export, job(jobs, post(export_csv))),
+        )
+        .route(
+            "/users/export.csv",
+            gated(flags, Feature::Export, job(jobs, get(download_csv))),
+        )
+        .route(
+            "/users/export.json",
+            gated(flags, Feature::Export, job(jobs, get(download_json))),
+        )
+        .route(
+            "/downloads/{token}",
+            gated(flags, Feature::Export, job(jobs, get(download_export))),
+        )
+        .route(
+            "/users/import",
+            gated(flags, Feature::Import, job(jobs, post(import_csv))),
+        )
+        .route(
+            "/users/import/upload",
+            gated(
+                flags,
+                Feature::Import,
+                job(jobs, subscription(subscribers, post(upload_csv))),
+            ),
+        )
+        .route(
+            "/users/import/validate",
+            gated(flags, Feature::Import, job(jobs, post(validate_csv_upload))),
+        )
+        .route("/external/users", get(get_external_users))
+        .route("/external/users/{id}", get(get_external_user_by_id))
+        .route("/stats/timeseries", get(get_stats_timeseries))
+        .route("/stats/retries", get(get_retry_stats))
+        .route("/stats/json", get(get_stats_json))
+        .route("/version", get(get_version))
+        .route("/ready", get(get_ready))
+        .route("/schema/{name}", get(get_request_schema))
+        .route("/admin/users", delete(clear_users))
+        .route("/admin/email-domains", get(email_domain_counts))
+        .route("/admin/users/{id}/raw", get(get_raw_user))
+        .route("/admin/users/merge", post(merge_users))
+        .route("/admin/dlq", get(get_dlq))
+        .route_layer(from_fn_with_state(
+            Arc::new(RouteRateLimiter::new(
+                state.config.route_rate_limits.clone(),
+            )),
+            route_rate_limit,
+        ))
+        .fallback(route_not_found)
+        .method_not_allowed_fallback(method_not_allowed)
+        .layer(from_fn(timezone))
+        .layer(from_fn_with_state(state.clone(), json_format))
+        .with_state(state.clone());
+
+    // Path normalization has to happen before routing, so it wraps the whole
+    // route table as a fallback service.
+    Router::new()
+        .fallback_service(routes)
+        .layer(from_fn_with_state(state.clone(), trailing_slash))
+        .layer(from_fn_with_state(state.clone(), retry_after))
+        .layer(from_fn_with_state(state.clone(), track_metrics))
+        .layer(SlowRequestLayer::new(Duration::from_millis(
+            state.config.slow_request_ms,
+        )))
+        .layer(from_fn_with_state(
+            (
+                shutdown,
+                Duration::from_secs(state.config.shutdown_grace_secs),
+            ),
+            drain,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, to_bytes},
+        http::Request,
+    };
+    use chrono::{DateTime, Utc};
+    use rdkafka::mocking::MockCluster;
+    use shared::{
+        abstract_trait::UserRepositoryTrait,
+        config::{AppConfig, TrailingSlash},
+        context::ServiceBuilder,
+        domain::{AgeFormat, SearchField},
+        kafka::producer::KafkaEventProducer,
+        repository::InMemoryUserRepository,
+        shutdown,
+        validation::FieldLimits,
+    };
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    struct TestApp {
+        router: Router,
+        trigger: shutdown::ShutdownTrigger,
+    }
+
+    impl TestApp {
+        fn new() -> Self {
+            Self::with_config(AppConfig::default())
+        }
+
+        fn with_config(config: AppConfig) -> Self {
+            Self::from_builder(ServiceBuilder::new(config))
+        }
+
+        fn with_repo(config: AppConfig, repo: Arc<dyn UserRepositoryTrait>) -> Self {
+            Self::from_builder(ServiceBuilder::new(config).repository(repo))
+        }
+
+        fn from_builder(builder: ServiceBuilder) -> Self {
+            Self::with_state(builder.without_kafka().build().service)
+        }
+
+        fn with_state(state: SharedState) -> Self {
+            let (trigger, signal) = shutdown::channel();
+            Self {
+                router: user_routes(state, signal),
+                trigger,
+            }
+        }
+
+        async fn send(
+            &self,
+            method: &str,
+            uri: &str,
+            headers: &[(&str, &str)],
+            body: Option<serde_json::Value>,
+        ) -> (StatusCode, HeaderMap, serde_json::Value) {
+            let mut request = Request::builder().method(method).uri(uri);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let request = match body {
+                Some(body) => request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string())),
+                None => request.body(Body::empty()),
+            }
+            .unwrap();
+            let (status, headers, bytes) = self.call(request).await;
+            let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+            (status, headers, json)
+        }
+
+        /// POSTs `body` as-is, with `content_type` if given. Returns the
+        /// status and the body as text.
+        async fn post_raw(
+            &self,
+            uri: &str,
+            content_type: Option<&str>,
+            body: &str,
+        ) -> (StatusCode, String) {
+            let mut request = Request::builder().method("POST").uri(uri);
+            if let Some(content_type) = content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+            let request = request.body(Body::from(body.to_string())).unwrap();
+            let (status, _, bytes) = self.call(request).await;
+            (status, String::from_utf8_lossy(&bytes).into_owned())
+        }
+
+        /// Sends `contents` as the `file` part of a multipart form.
+        async fn upload(
+            &self,
+            uri: &str,
+            file_name: &str,
+            content_type: &str,
+            contents: &[u8],
+        ) -> (StatusCode, Vec<u8>) {
+            let boundary = "test-boundary";
+            let mut body = format!(
+                "--{boundary}\r\ncontent-disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\ncontent-type: {content_type}\r\n\r\n"
+            )
+            .into_bytes();
+            body.extend_from_slice(contents);
+            body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+            let request = Request::post(uri)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={boundary}"),
+                )
+                .body(Body::from(body))
+                .unwrap();
+            let (status, _, bytes) = self.call(request).await;
+            (status, bytes)
+        }
+
+        async fn call(&self, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+            let response = self.router.clone().oneshot(request).await.unwrap();
+            let (parts, body) = response.into_parts();
+            let bytes = to_bytes(body, usize::MAX).await.unwrap();
+            (parts.status, parts.headers, bytes.to_vec())
+        }
+
+        async fn create(&self, name: &str, email: &str) -> (String, String) {
+            let (status, _, body) = self
+                .send(
+                    "POST",
+                    "/users",
+                    &[],
+                    Some(serde_json::json!({ "name": name, "email": email, "age": 30 })),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let id = body["data"]["id"].as_str().unwrap().to_string();
+            let etag = self.etag_of(&id).await;
+            (id, etag)
+        }
+
+        async fn etag_of(&self, id: &str) -> String {
+            let (_, headers, _) = self.send("GET", &format!("/users/{id}"), &[], None).await;
+            headers[header::ETAG].to_str().unwrap().to_string()
+        }
+    }
+
+    /// Delegates to an in-memory repository, but takes `delay` to answer a
+    /// listing, as a remote backend under load would, and fails the first
+    /// `failing_reads` listings outright. With a `gate`, each listing
+    /// announces itself and then waits to be released.
+    struct SlowRepo {
+        inner: InMemoryUserRepository,
+        delay: Duration,
+        failing_reads: AtomicU32,
+        gate: Option<Arc<Gate>>,
+    }
+
+    #[derive(Default)]
+    struct Gate {
+        entered: tokio::sync::Notify,
+        release: tokio::sync::Notify,
+    }
+
+    #[async_trait::async_trait]
+    impl UserRepositoryTrait for SlowRepo {
+        async fn find_all(
+            &self,
+            page: i32,
+            page_size: i32,
+            search: Option<String>,
+            search_field: SearchField,
+        ) -> Result<(Vec<User>, i64), AppError> {
+            if let Some(gate) = &self.gate {
+                gate.entered.notify_one();
+                gate.release.notified().await;
+            }
+            tokio::time::sleep(self.delay).await;
+            let failing =
+                self.failing_reads
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+            if failing.is_ok() {
+                return Err(AppError::Internal("backend unavailable".to_string()));
+            }
+            self.inner
+                .find_all(page, page_size, search, search_field)
+                .await
+        }
+        async fn find_updated_between(
+            &self,
+            since: Option<DateTime<Utc>>,
+            until: Option<DateTime<Utc>>,
+            page: i32,
+            page_size: i32,
+        ) -> Result<(Vec<User>, i64), AppError> {
+            self.inner
+                .find_updated_between(since, until, page, page_size)
+                .await
+        }
+        async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError> {
+            self.inner.find_by_email_exists(email).await
+        }
+        async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError> {
+            self.inner.create_user(input).await
+        }
+        async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+            self.inner.find_by_email(email).await
+        }
+        async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError> {
+            self.inner.find_by_id(id).await
+        }
+        async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError> {
+            self.inner.update_user(input, id).await
+        }
+        async fn update_by_email(
+            &self,
+            input: &UpdateUserRequest,
+            email: &str,
+        ) -> Result<User, AppError> {
+            self.inner.update_by_email(input, email).await
+        }
+        async fn delete_user(&self, email: &str) -> Result<(), AppError> {
+            self.inner.delete_user(email).await
+        }
+        async fn delete_by_id(&self, id: &str) -> Result<(), AppError> {
+            self.inner.delete_by_id(id).await
+        }
+        async fn clear(&self) -> Result<usize, AppError> {
+            self.inner.clear().await
+        }
+        async fn count(&self) -> Result<usize, AppError> {
+            self.inner.count().await
+        }
+        fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>> {
+            self.inner.stream_all()
+        }
+    }
+
+    fn rename(name: &str) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "name": name }))
+    }
+
+    #[tokio::test]
+    async fn update_with_matching_if_match_returns_the_new_etag() {
+        let app = TestApp::new();
+        let (id, etag) = app.create("Ann", "ann@example.com").await;
+
+        let (status, headers, body) = app
+            .send(
+                "PUT",
+                &format!("/users/{id}"),
+                &[("if-match", &etag)],
+                rename("Ann B"),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let new_etag = headers[header::ETAG].to_str().unwrap();
+        assert_ne!(new_etag, etag);
+        assert_eq!(new_etag, app.etag_of(&id).await);
+    }
+
+    #[tokio::test]
+    async fn update_with_stale_if_match_is_rejected() {
+        let app = TestApp::new();
+        let (id, stale) = app.create("Ann", "ann@example.com").await;
+        app.send("PATCH", &format!("/users/{id}"), &[], rename("Ann B"))
+            .await;
+
+        let (status, _, _) = app
+            .send(
+                "PATCH",
+                &format!("/users/{id}"),
+                &[("if-match", &stale)],
+                rename("Ann C"),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn update_by_email_honors_if_match() {
+        let app = TestApp::new();
+        let (id, etag) = app.create("Ann", "ann@example.com").await;
+        let uri = "/users/email/ann@example.com";
+
+        let (status, _, _) = app
+            .send("PATCH", uri, &[("if-match", "\"0\"")], rename("Ann B"))
+            .await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+        let (status, headers, _) = app
+            .send("PATCH", uri, &[("if-match", &etag)], rename("Ann B"))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::ETAG].to_str().unwrap(),
+            app.etag_of(&id).await
+        );
+    }
+
+    #[tokio::test]
+    async fn if_match_on_a_missing_user_is_a_failed_precondition() {
+        let app = TestApp::new();
+
+        for uri in ["/users/missing", "/users/email/nobody@example.com"] {
+            let (status, _, _) = app
+                .send("PATCH", uri, &[("if-match", "*")], rename("Ann"))
+                .await;
+            assert_eq!(status, StatusCode::PRECONDITION_FAILED, "{uri}");
+
+            let (status, _, _) = app.send("PATCH", uri, &[], rename("Ann")).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn search_pages_are_capped_by_max_search_results() {
+        let app = TestApp::with_config(AppConfig {
+            max_search_results: 2,
+            ..AppConfig::default()
+        });
+        for i in 0..3 {
+            app.create(&format!("Ann {i}"), &format!("ann{i}@example.com"))
+                .await;
+        }
+
+        let (status, _, first) = app
+            .send("GET", "/users/search?q=ann&page_size=50", &[], None)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+        assert_eq!(first["page_size"], 2);
+        assert_eq!(first["data"].as_array().unwrap().len(), 2);
+        assert_eq!(first["total"], 3);
+        assert_eq!(first["truncated"], true);
+
+        for page in [2, 3] {
+            let (status, _, past) = app
+                .send(
+                    "GET",
+                    &format!("/users/search?q=ann&page={page}"),
+                    &[],
+                    None,
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{past}");
+            assert_eq!(past["data"].as_array().unwrap().len(), 0, "{page}");
+            assert_eq!(past["total"], 3);
+        }
+
+        let (_, _, small) = app
+            .send("GET", "/users/search?q=ann&page=2&page_size=1", &[], None)
+            .await;
+        assert_eq!(small["data"].as_array().unwrap().len(), 1);
+        let (_, _, past) = app
+            .send("GET", "/users/search?q=ann&page=3&page_size=1", &[], None)
+            .await;
+        assert_eq!(past["data"].as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_missing_user_is_404_by_default() {
+        let app = TestApp::new();
+
+        for uri in ["/users/email/nobody@example.com", "/users/no-such-id"] {
+            let (status, _, _) = app.send("DELETE", uri, &[], None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn deleting_a_missing_user_is_204_when_idempotent() {
+        let app = TestApp::with_config(AppConfig {
+            delete_idempotent: true,
+            ..AppConfig::default()
+        });
+
+        for uri in ["/users/email/nobody@example.com", "/users/no-such-id"] {
+            let (status, _, _) = app.send("DELETE", uri, &[], None).await;
+            assert_eq!(status, StatusCode::NO_CONTENT, "{uri}");
+        }
+    }
+
+    const ANN: &str = r#"{"name":"Ann","email":"ann@example.com","age":30}"#;
+
+    #[tokio::test]
+    async fn json_without_a_json_content_type_is_accepted_when_lenient() {
+        let app = TestApp::new();
+
+        let (status, body) = app.post_raw("/users", None, ANN).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let bob = ANN.replace("ann@", "bob@");
+        let (status, body) = app.post_raw("/users", Some("text/plain"), &bob).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+
+    #[tokio::test]
+    async fn wrong_content_type_is_rejected() {
+        let app = TestApp::new();
+
+        let (status, body) = app.post_raw("/users", Some("application/xml"), ANN).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Unsupported Content-Type"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn missing_content_type_is_rejected_when_strict() {
+        let app = TestApp::with_config(AppConfig {
+            lenient_content_type: false,
+            ..AppConfig::default()
+        });
+
+        for content_type in [None, Some("text/plain")] {
+            let (status, body) = app.post_raw("/users", content_type, ANN).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{content_type:?}");
+            assert!(body.contains("Unsupported Content-Type"), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_plain_text_body_is_a_validation_error() {
+        let app = TestApp::new();
+
+        let (status, body) = app.post_raw("/users", Some("text/plain"), "name=Ann").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Invalid JSON body"), "{body}");
+    }
+
+    fn admin_config() -> AppConfig {
+        AppConfig {
+            admin_enabled: true,
+            admin_token: Some("secret".to_string()),
+            ..AppConfig::default()
+        }
+    }
+
+    const ADMIN: (&str, &str) = ("authorization", "Bearer secret");
+
+    #[tokio::test]
+    async fn clearing_users_empties_the_listing() {
+        let app = TestApp::with_config(admin_config());
+        app.create("Ann", "ann@example.com").await;
+        app.create("Bob", "bob@example.com").await;
+
+        let (status, _, body) = app.send("DELETE", "/admin/users", &[ADMIN], None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"], 2);
+
+        let (_, _, listing) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(listing["total"], 0);
+        assert_eq!(listing["data"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn clearing_users_needs_admin_enabled_and_the_token() {
+        let app = TestApp::new();
+        let (status, _, _) = app.send("DELETE", "/admin/users", &[], None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let app = TestApp::with_config(admin_config());
+        app.create("Ann", "ann@example.com").await;
+        let (status, _, _) = app.send("DELETE", "/admin/users", &[], None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (_, _, listing) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(listing["total"], 1);
+
+        let app = TestApp::with_config(AppConfig {
+            admin_token: None,
+            ..admin_config()
+        });
+        let (status, _, _) = app.send("DELETE", "/admin/users", &[ADMIN], None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn age_is_a_number_by_default() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (_, _, body) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+
+        assert_eq!(body["data"]["age"], serde_json::json!(30));
+    }
+
+    #[tokio::test]
+    async fn age_is_a_string_when_configured() {
+        let app = TestApp::with_config(AppConfig {
+            age_format: AgeFormat::String,
+            ..AppConfig::default()
+        });
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (_, _, body) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+        assert_eq!(body["data"]["age"], serde_json::json!("30"));
+
+        let (_, _, listing) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(listing["data"][0]["age"], serde_json::json!("30"));
+    }
+
+    #[tokio::test]
+    async fn slow_export_read_times_out() {
+        let repo = Arc::new(SlowRepo {
+            inner: InMemoryUserRepository::new(),
+            delay: Duration::from_secs(3),
+            failing_reads: AtomicU32::new(0),
+            gate: None,
+        });
+        let app = TestApp::with_repo(
+            AppConfig {
+                export_timeout_secs: 1,
+                ..AppConfig::default()
+            },
+            repo,
+        );
+        app.create("Ann", "ann@example.com").await;
+
+        let started = std::time::Instant::now();
+        let (status, _, _) = app.send("GET", "/users/export.csv", &[], None).await;
+
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn export_within_the_timeout_streams_the_csv() {
+        let repo = Arc::new(SlowRepo {
+            inner: InMemoryUserRepository::new(),
+            delay: Duration::from_millis(50),
+            failing_reads: AtomicU32::new(0),
+            gate: None,
+        });
+        let app = TestApp::with_repo(
+            AppConfig {
+                export_timeout_secs: 1,
+                ..AppConfig::default()
+            },
+            repo,
+        );
+        app.create("Ann", "ann@example.com").await;
+
+        let request = Request::get("/users/export.csv")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = app.call(request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(String::from_utf8(body).unwrap().contains("ann@example.com"));
+    }
+
+    fn error_fields(body: &serde_json::Value) -> Vec<&str> {
+        body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn every_invalid_field_is_reported_at_once() {
+        let app = TestApp::new();
+
+        let (status, _, body) = app
+            .send(
+                "POST",
+                "/users",
+                &[],
+                Some(serde_json::json!({ "name": " ", "email": "not-an-email", "age": 30 })),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&body), vec!["name", "email"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_update_reports_every_field() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (status, _, body) = app
+            .send(
+                "PATCH",
+                &format!("/users/{id}"),
+                &[],
+                Some(serde_json::json!({ "name": "", "email": "nope" })),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&body), vec!["name", "email"]);
+    }
+
+    #[tokio::test]
+    async fn search_field_name_ignores_email_matches() {
+        let app = TestApp::new();
+        app.create("Ann", "jdoe@example.com").await;
+
+        let (_, _, by_name) = app
+            .send("GET", "/users?search=jdoe&search_field=name", &[], None)
+            .await;
+        let (_, _, by_email) = app
+            .send("GET", "/users?search=jdoe&search_field=email", &[], None)
+            .await;
+
+        assert_eq!(by_name["total"], 0);
+        assert_eq!(by_email["total"], 1);
+    }
+
+    fn batch(size: usize) -> Option<serde_json::Value> {
+        let users: Vec<_> = (0..size)
+            .map(|i| {
+                serde_json::json!({ "name": format!("User {i}"), "email": format!("user{i}@example.com"), "age": 30 })
+            })
+            .collect();
+        Some(serde_json::Value::Array(users))
+    }
+
+    #[tokio::test]
+    async fn bulk_create_enforces_max_bulk_size() {
+        let app = TestApp::with_config(AppConfig {
+            max_bulk_size: 3,
+            ..AppConfig::default()
+        });
+
+        let (status, _, _) = app.send("POST", "/users/bulk", &[], batch(4)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let (_, _, listing) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(listing["total"], 0);
+
+        let (status, _, body) = app.send("POST", "/users/bulk", &[], batch(3)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["created"], 3);
+    }
+
+    #[tokio::test]
+    async fn non_numeric_page_is_a_clear_validation_error() {
+        let app = TestApp::new();
+
+        let request = Request::get("/users?page=abc").body(Body::empty()).unwrap();
+        let (status, _, body) = app.call(request).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("Invalid query parameters"), "{body}");
+        assert!(body.contains("page"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn pagination_defaults_and_clamps_apply() {
+        let app = TestApp::new();
+
+        let (status, _, body) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["page"], 1);
+        assert_eq!(body["page_size"], 10);
+
+        let (_, _, body) = app
+            .send("GET", "/users?page=0&page_size=100000", &[], None)
+            .await;
+        assert_eq!(body["page"], 1);
+        assert_eq!(body["page_size"], 100);
+    }
+
+    const USERS_CSV: &[u8] =
+        b"id,name,email,age,created_at,updated_at\n,Ann,ann@example.com,30,,\n";
+
+    #[tokio::test]
+    async fn csv_uploads_are_accepted() {
+        let app = TestApp::new();
+
+        for (file_name, content_type) in [("users.csv", "text/csv"), ("users.txt", "text/plain")] {
+            let (status, body) = app
+                .upload("/users/import/validate", file_name, content_type, USERS_CSV)
+                .await;
+            assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+            let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(report["data"]["valid"], 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn binary_uploads_are_rejected() {
+        let app = TestApp::new();
+        let blob = b"%PDF-1.7\n\x00\x01\x02binary";
+
+        for (file_name, content_type) in [
+            ("report.pdf", "application/pdf"),
+            // Named and declared as CSV, but the bytes give it away.
+            ("users.csv", "text/csv"),
+        ] {
+            let (status, body) = app
+                .upload("/users/import/validate", file_name, content_type, blob)
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{file_name}");
+            assert!(String::from_utf8_lossy(&body).contains("Expected CSV file"));
+        }
+    }
+
+    #[tokio::test]
+    async fn update_by_email_changes_the_user() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (status, _, body) = app
+            .send(
+                "PATCH",
+                "/users/email/Ann@Example.com",
+                &[],
+                Some(serde_json::json!({ "name": "Ann B", "age": 41 })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["id"], id.as_str());
+
+        let (_, _, stored) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+        assert_eq!(stored["data"]["name"], "Ann B");
+        assert_eq!(stored["data"]["age"], 41);
+    }
+
+    #[tokio::test]
+    async fn update_by_unknown_email_is_404() {
+        let app = TestApp::new();
+
+        let (status, _, _) = app
+            .send("PATCH", "/users/email/nobody@example.com", &[], rename("X"))
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn empty_string_update_is_rejected_and_stores_nothing() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (status, _, body) = app
+            .send(
+                "PATCH",
+                &format!("/users/{id}"),
+                &[],
+                Some(serde_json::json!({ "email": "" })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            body["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .contains("cannot be cleared")
+        );
+
+        let (_, _, stored) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+        assert_eq!(stored["data"]["email"], "ann@example.com");
+    }
+
+    #[tokio::test]
+    async fn validate_upload_reports_every_problem_without_inserting() {
+        let app = TestApp::new();
+        let csv = "id,name,email,age,created_at,updated_at\n\
+                   ,Ann,ann@example.com,30,,\n\
+                   ,,no-at-sign,abc,,\n\
+                   ,Old,old@example.com,200,,\n\
+                   ,Short,short@example.com\n";
+
+        let (status, body) = app
+            .upload(
+                "/users/import/validate",
+                "users.csv",
+                "text/csv",
+                csv.as_bytes(),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let report = &report["data"];
+        assert_eq!(report["valid"], 1);
+        assert_eq!(report["invalid"], 3);
+        let errors: Vec<(u64, &str)> = report["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| (e["line"].as_u64().unwrap(), e["field"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (3, "name"),
+                (3, "email"),
+                (3, "age"),
+                (4, "age"),
+                (5, "row")
+            ]
+        );
+        let (_, _, listing) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(listing["total"], 0);
+    }
+
+    #[tokio::test]
+    async fn unknown_path_gets_the_json_error_envelope() {
+        let app = TestApp::new();
+
+        let (status, _, body) = app.send("GET", "/no/such/path", &[], None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "NOT_FOUND");
+        assert!(body["error"]["message"].is_string());
+    }
+
+    #[tokio::test]
+    async fn wrong_method_gets_the_json_error_envelope() {
+        let app = TestApp::new();
+
+        let (status, _, body) = app.send("PUT", "/users", &[], None).await;
+
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "METHOD_NOT_ALLOWED");
+    }
+
+    fn trailing_slash_app(mode: TrailingSlash) -> TestApp {
+        TestApp::with_config(AppConfig {
+            trailing_slash: mode,
+            ..AppConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_matches_the_route_by_default() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        for uri in [
+            "/users",
+            "/users/",
+            &format!("/users/{id}"),
+            &format!("/users/{id}/"),
+        ] {
+            let (status, _, _) = app.send("GET", uri, &[], None).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_redirects_when_configured() {
+        let app = trailing_slash_app(TrailingSlash::Redirect);
+
+        let (status, _, _) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, headers, _) = app.send("GET", "/users/?page=2", &[], None).await;
+        assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(headers[header::LOCATION], "/users?page=2");
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_is_not_found_when_strict() {
+        let app = trailing_slash_app(TrailingSlash::Strict);
+
+        let (status, _, _) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, _) = app.send("GET", "/users/", &[], None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn out_of_range_age_is_a_field_error() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        for age in [300, -1] {
+            let (status, _, body) = app
+                .send(
+                    "POST",
+                    "/users",
+                    &[],
+                    Some(serde_json::json!({ "name": "Bob", "email": "bob@example.com", "age": age })),
+                )
+                .await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{age}");
+            assert_eq!(body["errors"][0]["field"], "age");
+            assert_eq!(
+                body["errors"][0]["message"],
+                "Age must be between 0 and 150"
+            );
+
+            let (status, _, body) = app
+                .send(
+                    "PATCH",
+                    &format!("/users/{id}"),
+                    &[],
+                    Some(serde_json::json!({ "age": age })),
+                )
+                .await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{age}");
+            assert_eq!(body["errors"][0]["field"], "age");
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_range_age_in_a_bulk_item_names_the_item() {
+        let app = TestApp::new();
+        let items = serde_json::json!([
+            { "name": "Ann", "email": "ann@example.com", "age": 30 },
+            { "name": "Bob", "email": "bob@example.com", "age": 300 },
+        ]);
+
+        let (status, _, body) = app.send("POST", "/users/bulk", &[], Some(items)).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        assert_eq!(body["errors"][0]["field"], "items[1].age");
+        assert_eq!(
+            body["errors"][0]["message"],
+            "Age must be between 0 and 150"
+        );
+        let (_, _, listing) = app.send("GET", "/users", &[], None).await;
+        assert_eq!(listing["total"], 0);
+    }
+
+    async fn export_json(app: &TestApp) -> Vec<serde_json::Value> {
+        let request = Request::get("/users/export.json")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = app.call(request).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice(&body).expect("export is a valid JSON array")
+    }
+
+    #[tokio::test]
+    async fn json_export_of_no_users_is_an_empty_array() {
+        let app = TestApp::new();
+
+        let request = Request::get("/users/export.json")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = app.call(request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"[]");
+    }
+
+    #[tokio::test]
+    async fn json_export_of_one_user_is_a_one_element_array() {
+        let app = TestApp::new();
+        app.create("Ann", "ann@example.com").await;
+
+        let users = export_json(&app).await;
+
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0]["email"], "ann@example.com");
+    }
+
+    #[tokio::test]
+    async fn json_export_separates_users_across_chunks() {
+        let app = TestApp::new();
+        let (status, _, _) = app
+            .send("POST", "/users/bulk", &[], batch(EXPORT_CHUNK_SIZE + 1))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let users = export_json(&app).await;
+
+        assert_eq!(users.len(), EXPORT_CHUNK_SIZE + 1);
+    }
+
+    #[tokio::test]
+    async fn queueing_jobs_without_a_producer_is_503() {
+        let app = TestApp::new();
+
+        for uri in ["/users/export", "/users/import"] {
+            let (status, body) = app.post_raw(uri, None, "").await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+            assert!(body.contains("not enabled"), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_kafka_delivery_is_500() {
+        // Nothing listens on this port, so delivery times out.
+        let producer = KafkaEventProducer::new("127.0.0.1:1", "jobs");
+        let state = ServiceBuilder::new(AppConfig::default())
+            .kafka_producer(Some(Arc::new(producer)))
+            .build()
+            .service;
+        let app = TestApp::with_state(state);
+
+        let (status, body) = app.post_raw("/users/import", None, "").await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        // The delivery error itself is logged, not leaked to the client.
+        assert!(!body.contains("not enabled"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn each_route_has_its_own_rate_limit() {
+        let app = TestApp::with_config(AppConfig {
+            route_rate_limits: [
+                ("/users/search".to_string(), 1),
+                ("/users/{id}".to_string(), 100),
+            ]
+            .into_iter()
+            .collect(),
+            ..AppConfig::default()
+        });
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (first, _, _) = app.send("GET", "/users/search?q=ann", &[], None).await;
+        let (second, headers, _) = app.send("GET", "/users/search?q=ann", &[], None).await;
+        assert_eq!(first, StatusCode::OK);
+        assert_eq!(second, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key(header::RETRY_AFTER));
+
+        for _ in 0..10 {
+            let (status, _, _) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn over_length_name_is_rejected_while_parsing() {
+        let app = TestApp::with_config(AppConfig {
+            field_limits: FieldLimits {
+                max_name_len: 5,
+                ..FieldLimits::default()
+            },
+            ..AppConfig::default()
+        });
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+        let long = r#"{"name":"Annabelle","email":"bel@example.com","age":30}"#;
+
+        let (status, body) = app.post_raw("/users", Some("application/json"), long).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error_fields(&body), vec!["name"]);
+        assert_eq!(
+            body["errors"][0]["message"],
+            "name must be at most 5 characters"
+        );
+
+        let rename = serde_json::json!({ "name": "Annabelle" });
+        let (status, _, body) = app
+            .send("PATCH", &format!("/users/{id}"), &[], Some(rename))
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&body), vec!["name"]);
+    }
+
+    #[tokio::test]
+    async fn external_routes_rename_name_and_email() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (status, _, one) = app
+            .send("GET", &format!("/external/users/{id}"), &[], None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            one["data"],
+            serde_json::json!({
+                "id": id,
+                "user_name": "Ann",
+                "email_address": "ann@example.com",
+                "age": 30,
+            })
+        );
+
+        let (_, _, listing) = app.send("GET", "/external/users", &[], None).await;
+        assert_eq!(listing["data"][0], one["data"]);
+
+        let (_, _, canonical) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+        assert_eq!(canonical["data"]["name"], "Ann");
+        assert!(canonical["data"].get("user_name").is_none());
+    }
+
+    #[tokio::test]
+    async fn csv_download_takes_its_dialect_from_the_query() {
+        let app = TestApp::new();
+        app.create("Ann", "ann@example.com").await;
+
+        let request =
+            Request::get("/users/export.csv?quote_style=always&terminator=crlf&columns=name,email")
+                .body(Body::empty())
+                .unwrap();
+        let (status, _, body) = app.call(request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "\"name\",\"email\"\r\n\"Ann\",\"ann@example.com\"\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn version_reports_the_crate_version_and_build() {
+        let app = TestApp::new();
+
+        let (status, _, body) = app.send("GET", "/version", &[], None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!body["data"]["commit"].as_str().unwrap().is_empty());
+        let built_at = body["data"]["built_at"].as_str().unwrap();
+        assert!(DateTime::parse_from_rfc3339(built_at).is_ok(), "{built_at}");
+    }
+
+    #[tokio::test]
+    async fn unknown_fields_are_ignored_unless_strict() {
+        let body = || {
+            Some(
+                serde_json::json!({ "name": "Ann", "email": "ann@example.com", "age": 30, "nickname": "annie" }),
+            )
+        };
+
+        let lenient = TestApp::new();
+        let (status, _, created) = lenient.send("POST", "/users", &[], body()).await;
+        assert_eq!(status, StatusCode::OK, "{created}");
+
+        let strict = TestApp::with_config(AppConfig {
+            strict_json: true,
+            ..AppConfig::default()
+        });
+        let (status, _, rejected) = strict.send("POST", "/users", &[], body()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&rejected), vec!["nickname"]);
+    }
+
+    #[tokio::test]
+    async fn strict_update_names_the_typo_field() {
+        let app = TestApp::with_config(AppConfig {
+            strict_json: true,
+            ..AppConfig::default()
+        });
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (status, _, body) = app
+            .send(
+                "PATCH",
+                &format!("/users/{id}"),
+                &[],
+                Some(serde_json::json!({ "e-mail": "new@example.com" })),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&body), vec!["e-mail"]);
+        assert_eq!(body["errors"][0]["message"], "Unknown field");
+    }
+
+    #[tokio::test]
+    async fn strict_bulk_names_unknown_fields_per_item_and_keeps_the_size_limit() {
+        let app = TestApp::with_config(AppConfig {
+            strict_json: true,
+            max_bulk_size: 3,
+            ..AppConfig::default()
+        });
+        let items = serde_json::json!([
+            { "name": "Ann", "email": "ann@example.com", "age": 30 },
+            { "name": "Bob", "email": "bob@example.com", "age": 30, "nick": "bobby", "role": "x" },
+        ]);
+
+        let (status, _, body) = app.send("POST", "/users/bulk", &[], Some(items)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        assert_eq!(error_fields(&body), vec!["items[1].nick", "items[1].role"]);
+
+        let mut oversized = batch(4).unwrap();
+        oversized[3]["nick"] = "late".into();
+        let (status, _, _) = app.send("POST", "/users/bulk", &[], Some(oversized)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn export_over_the_limit_is_queued_as_a_job() {
+        let cluster = MockCluster::new(1).unwrap();
+        let producer = KafkaEventProducer::new(&cluster.bootstrap_servers(), "jobs");
+        let state = ServiceBuilder::new(AppConfig {
+            max_sync_export_rows: 1,
+            ..AppConfig::default()
+        })
+        .kafka_producer(Some(Arc::new(producer)))
+        .build()
+        .service;
+        let app = TestApp::with_state(state);
+        app.create("Ann", "ann@example.com").await;
+
+        let request = Request::get("/users/export.csv")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, inline) = app.call(request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            String::from_utf8(inline)
+                .unwrap()
+                .contains("ann@example.com")
+        );
+
+        app.create("Bob", "bob@example.com").await;
+        for uri in ["/users/export.csv", "/users/export.json"] {
+            let (status, _, body) = app.send("GET", uri, &[], None).await;
+            assert_eq!(status, StatusCode::ACCEPTED, "{uri}");
+            let job_id = body["data"]["job_id"].as_str().unwrap();
+            assert!(uuid::Uuid::parse_str(job_id).is_ok(), "{job_id}");
+            assert!(
+                body["data"]["download_url"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("/downloads/")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn export_endpoint_returns_a_job_with_a_download_link() {
+        let cluster = MockCluster::new(1).unwrap();
+        let producer = KafkaEventProducer::new(&cluster.bootstrap_servers(), "jobs");
+        let state = ServiceBuilder::new(AppConfig::default())
+            .kafka_producer(Some(Arc::new(producer)))
+            .build()
+            .service;
+        let app = TestApp::with_state(state);
+
+        let mut links = Vec::new();
+        for _ in 0..2 {
+            let (status, _, body) = app.send("POST", "/users/export", &[], None).await;
+            assert_eq!(status, StatusCode::ACCEPTED, "{body}");
+            let job_id = body["data"]["job_id"].as_str().unwrap();
+            assert!(uuid::Uuid::parse_str(job_id).is_ok(), "{job_id}");
+            links.push(body["data"]["download_url"].as_str().unwrap().to_string());
+        }
+
+        assert!(links.iter().all(|link| link.starts_with("/downloads/")));
+        assert_ne!(links[0], links[1]);
+        // Nothing has been written yet, so neither link is ready.
+        let (status, _, _) = app.send("GET", &links[0], &[], None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn one_character_search_is_a_400() {
+        let app = TestApp::new();
+        app.create("Ann", "ann@example.com").await;
+
+        let request = Request::get("/users/search?q=a")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = app.call(request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            String::from_utf8(body)
+                .unwrap()
+                .contains("Search query too short")
+        );
+
+        let (status, _, found) = app.send("GET", "/users/search?q=an", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found["total"], 1);
+    }
+
+    #[tokio::test]
+    async fn email_change_goes_through_request_and_confirm() {
+        let app = TestApp::new();
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        let (status, _, requested) = app
+            .send(
+                "POST",
+                &format!("/users/{id}/email-change"),
+                &[],
+                Some(serde_json::json!({ "email": "ann@new.example" })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{requested}");
+        let token = requested["data"]["token"].clone();
+
+        let (status, _, confirmed) = app
+            .send(
+                "POST",
+                "/users/email-change/confirm",
+                &[],
+                Some(serde_json::json!({ "token": token })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{confirmed}");
+        assert_eq!(confirmed["data"]["email"], "ann@new.example");
+
+        let (status, _, _) = app
+            .send(
+                "POST",
+                "/users/email-change/confirm",
+                &[],
+                Some(serde_json::json!({ "token": token })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn csv_bom_is_written_only_when_asked_for() {
+        const BOM: &[u8] = b"\xEF\xBB\xBF";
+        async fn download(app: &TestApp, uri: &str) -> Vec<u8> {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let (status, _, body) = app.call(request).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            body
+        }
+
+        let app = TestApp::new();
+        app.create("Zoë", "zoe@example.com").await;
+        assert!(!download(&app, "/users/export.csv").await.starts_with(BOM));
+        let with_bom = download(&app, "/users/export.csv?bom=true").await;
+        assert!(with_bom.starts_with(BOM));
+        assert!(with_bom[BOM.len()..].starts_with(b"id,"));
+        assert_eq!(with_bom.windows(BOM.len()).filter(|w| *w == BOM).count(), 1);
+
+        let configured = TestApp::with_config(AppConfig {
+            csv_bom: true,
+            ..AppConfig::default()
+        });
+        configured.create("Zoë", "zoe@example.com").await;
+        assert!(
+            download(&configured, "/users/export.csv")
+                .await
+                .starts_with(BOM)
+        );
+        assert!(
+            !download(&configured, "/users/export.csv?bom=false")
+                .await
+                .starts_with(BOM)
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_delete_is_forbidden_while_reads_work() {
+        let app = TestApp::with_config(AppConfig {
+            feature_flags: FeatureFlags::default().disable(Feature::Delete),
+            ..AppConfig::default()
+        });
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+
+        for uri in [
+            format!("/users/{id}"),
+            "/users/email/ann@example.com".to_string(),
+        ] {
+            let (status, _, _) = app.send("DELETE", &uri, &[], None).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+        }
+
+        let (status, _, body) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["name"], "Ann");
+        let (status, _, _) = app
+            .send("PATCH", &format!("/users/{id}"), &[], rename("Annie"))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn partial_bulk_failure_status_follows_the_config() {
+        let with_duplicate = || {
+            Some(serde_json::json!([
+                { "name": "Ann", "email": "ann@example.com", "age": 30 },
+                { "name": "Bob", "email": "bob@example.com", "age": 30 },
+                { "name": "Ann Again", "email": "ann@example.com", "age": 30 },
+            ]))
+        };
+
+        for (mode, expected) in [
+            (BulkFailureStatus::Ok, StatusCode::OK),
+            (BulkFailureStatus::MultiStatus, StatusCode::MULTI_STATUS),
+            (
+                BulkFailureStatus::Unprocessable,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let app = TestApp::with_config(AppConfig {
+                bulk_failure_status: mode,
+                ..AppConfig::default()
+            });
+
+            let (status, _, body) = app.send("POST", "/users/bulk", &[], with_duplicate()).await;
+
+            assert_eq!(status, expected, "{mode:?}");
+            assert_eq!(body["success"], false);
+            assert_eq!(
+                (
+                    body["data"]["created"].as_u64(),
+                    body["data"]["failed"].as_u64()
+                ),
+                (Some(2), Some(1))
+            );
+            let statuses: Vec<_> = body["data"]["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["status"].as_u64().unwrap())
+                .collect();
+            assert_eq!(statuses, vec![201, 201, 409], "{mode:?}");
+
+            let (status, _, _) = app.send("POST", "/users/bulk", &[], batch(2)).await;
+            assert_eq!(status, StatusCode::OK, "{mode:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_list_response_is_refused() {
+        let app = TestApp::with_config(AppConfig {
+            max_list_response_bytes: 1_000,
+            ..AppConfig::default()
+        });
+        for i in 0..10 {
+            app.create(
+                &format!("{i}{}", "x".repeat(90)),
+                &format!("user{i}@example.com"),
+            )
+            .await;
+        }
+
+        for uri in ["/users?page_size=10", "/external/users?page_size=10"] {
+            let (status, _, _) = app.send("GET", uri, &[], None).await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{uri}");
+        }
+
+        let (status, _, page) = app.send("GET", "/users?page_size=2", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["data"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_by_id_projects_requested_fields() {
+        let app = TestApp::new();
+        let (id, etag) = app.create("Ann", "ann@example.com").await;
+
+        let (status, headers, body) = app
+            .send("GET", &format!("/users/{id}?fields=name,email"), &[], None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ETAG], etag.as_str());
+        let data = body["data"].as_object().unwrap();
+        let mut names: Vec<_> = data.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, vec!["email", "name"]);
+
+        let (_, _, full) = app.send("GET", &format!("/users/{id}"), &[], None).await;
+        for field in ["id", "name", "email", "age"] {
+            assert!(full["data"].get(field).is_some(), "{field} missing");
+        }
+
+        let (status, _, _) = app
+            .send(
+                "GET",
+                &format!("/users/{id}?fields=name,password"),
+                &[],
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn head_users_reports_the_filtered_total() {
+        let app = TestApp::new();
+        app.create("Ann", "ann@example.com").await;
+        app.create("Anna", "anna@example.com").await;
+        app.create("Bob", "bob@example.com").await;
+
+        for (uri, total) in [("/users", "3"), ("/users?search=ann", "2")] {
+            let request = Request::head(uri).body(Body::empty()).unwrap();
+            let (status, headers, body) = app.call(request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers["x-total-count"], total, "{uri}");
+            assert!(body.is_empty());
+        }
+    }
+
+    fn lagged_progress() -> broadcast::Receiver<ImportProgress> {
+        let (tx, rx) = broadcast::channel(2);
+        for processed in 1..=4 {
+            tx.send(ImportProgress::Running {
+                processed,
+                total: 4,
+            })
+            .unwrap();
+        }
+        rx
+    }
+
+    #[tokio::test]
+    async fn lagging_progress_client_is_told_what_it_missed() {
+        let events: Vec<_> = progress_stream(lagged_progress(), SlowConsumerPolicy::DropLagged)
+            .collect()
+            .await;
+
+        assert!(matches!(events[0], ImportProgress::Lagged { missed: 2 }));
+        assert!(matches!(
+            events[1..],
+            [
+                ImportProgress::Running { processed: 3, .. },
+                ImportProgress::Running { processed: 4, .. }
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn lagging_progress_client_is_disconnected() {
+        let events: Vec<_> = progress_stream(lagged_progress(), SlowConsumerPolicy::Disconnect)
+            .collect()
+            .await;
+
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_are_turned_away_once_draining() {
+        let gate = Arc::new(Gate::default());
+        let repo = Arc::new(SlowRepo {
+            inner: InMemoryUserRepository::new(),
+            delay: Duration::ZERO,
+            failing_reads: AtomicU32::new(0),
+            gate: Some(gate.clone()),
+        });
+        let app = Arc::new(TestApp::with_repo(AppConfig::default(), repo));
+        let (status, _, _) = app.send("GET", "/ready", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A listing already running when the drain starts is let finish.
+        let in_flight = tokio::spawn({
+            let app = app.clone();
+            async move { app.send("GET", "/users", &[], None).await.0 }
+        });
+        gate.entered.notified().await;
+        app.trigger.trigger();
+
+        for uri in ["/ready", "/users"] {
+            let (status, headers, _) = app.send("GET", uri, &[], None).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+            assert_eq!(headers[header::CONNECTION], "close");
+            assert_eq!(headers[header::RETRY_AFTER], "30");
+        }
+
+        gate.release.notify_one();
+        assert_eq!(in_flight.await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn export_read_retries_are_counted() {
+        let repo = Arc::new(SlowRepo {
+            inner: InMemoryUserRepository::new(),
+            delay: Duration::ZERO,
+            failing_reads: AtomicU32::new(1),
+            gate: None,
+        });
+        let app = TestApp::with_repo(AppConfig::default(), repo.clone());
+        app.create("Ann", "ann@example.com").await;
+
+        let (_, _, stats) = app.send("GET", "/stats/retries", &[], None).await;
+        assert_eq!(stats["data"], serde_json::json!({}));
+
+        let (status, _, _) = app.send("GET", "/users/export.csv", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        repo.failing_reads.store(3, Ordering::Release);
+        let (status, _, _) = app.send("GET", "/users/export.csv", &[], None).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let (_, _, stats) = app.send("GET", "/stats/retries", &[], None).await;
+        assert_eq!(
+            stats["data"]["export_read"],
+            serde_json::json!({ "attempted": 3, "exhausted": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_json_counts_requests_seen_so_far() {
+        let app = TestApp::new();
+        app.create("Ann", "ann@example.com").await;
+        app.send("GET", "/users/missing", &[], None).await;
+
+        let (status, _, stats) = app.send("GET", "/stats/json", &[], None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        // `create` also fetches the ETag, and the stats request itself is
+        // still in flight while it is answered.
+        assert_eq!(stats["data"]["counters"]["requests"], 3);
+        assert_eq!(stats["data"]["counters"]["client_errors"], 1);
+        assert_eq!(stats["data"]["counters"]["operations"]["create_count"], 1);
+        assert_eq!(stats["data"]["gauges"]["users"], 1);
+        assert_eq!(stats["data"]["gauges"]["in_flight_requests"], 1);
+        assert_eq!(stats["data"]["latency"]["count"], 3);
+    }
+
+    #[tokio::test]
+    async fn raw_user_includes_the_stored_timestamps() {
+        let app = TestApp::with_config(admin_config());
+        let (id, _) = app.create("Ann", "ann@example.com").await;
+        let uri = format!("/admin/users/{id}/raw");
+
+        let (status, _, body) = app.send("GET", &uri, &[ADMIN], None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["email"], "ann@example.com");
+        for field in ["created_at", "updated_at"] {
+            assert!(body["data"][field].is_string(), "{field} missing");
+        }
+
+        let (status, _, _) = app.send("GET", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, _) = app
+            .send("GET", "/admin/users/missing/raw", &[ADMIN], None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn bulk_get_returns_found_users_in_request_order() {
+        // `SlowRepo` has no batched lookup, so this also covers the
+        // trait's default `find_by_ids`.
+        let slow = Arc::new(SlowRepo {
+            inner: InMemoryUserRepository::new(),
+            delay: Duration::ZERO,
+            failing_reads: AtomicU32::new(0),
+            gate: None,
+        });
+        for app in [
+            TestApp::new(),
+            TestApp::with_repo(
+                AppConfig {
+                    bulk_get_concurrency: 2,
+                    ..AppConfig::default()
+                },
+                slow,
+            ),
+        ] {
+            let (ann, _) = app.create("Ann", "ann@example.com").await;
+            let (bob, _) = app.create("Bob", "bob@example.com").await;
+
+            let ids = serde_json::json!([bob, "missing", ann, bob]);
+            let (status, _, body) = app.send("POST", "/users/bulk-get", &[], Some(ids)).await;
+
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let names: Vec<_> = body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|user| user["name"].as_str().unwrap())
+                .collect();
+            assert_eq!(names, vec!["Bob", "Ann"]);
+        }
+    }
 }
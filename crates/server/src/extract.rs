@@ -0,0 +1,613 @@
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Query, Request},
+    http::{header, request::Parts},
+};
+use serde::{
+    Deserialize, Deserializer,
+    de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+};
+use shared::{
+    config::AppConfig,
+    database::SharedState,
+    domain::{
+        ChangesQuery, CsvDialect, DlqQuery, ExportFilter, FieldsQuery, FindAllUserRequest,
+        SearchQuery, TimeseriesQuery,
+    },
+    errors::AppError,
+    validation::{FieldError, FieldLimits, Validate},
+};
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+};
+use subtle::ConstantTimeEq;
+
+pub struct LenientJson<T>(pub T);
+
+impl<T> FromRequest<SharedState> for LenientJson<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let bytes = json_body(req, state).await?;
+        let unknown = UnknownFields::default();
+
+        let value = state.config.field_limits.scope(|| {
+            let mut de = serde_json::Deserializer::from_slice(&bytes);
+            let value = if state.config.strict_json {
+                T::deserialize(Strict::body(&mut de, &unknown, ""))
+            } else {
+                T::deserialize(&mut de)
+            };
+            value.and_then(|value| de.end().map(|_| value))
+        });
+
+        let unknown = unknown.into_inner();
+        if !unknown.is_empty() {
+            return Err(AppError::FieldErrors(unknown));
+        }
+        value
+            .map(LenientJson)
+            .map_err(|e| match FieldLimits::take_rejected_field() {
+                Some(error) => AppError::FieldErrors(vec![error]),
+                None => AppError::ValidationError(format!("Invalid JSON body: {e}")),
+            })
+    }
+}
+
+pub struct ValidJson<T>(pub T);
+
+impl<T> FromRequest<SharedState> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let LenientJson(value) = LenientJson::<T>::from_request(req, state).await?;
+        value.validate().map_err(AppError::FieldErrors)?;
+        Ok(ValidJson(value))
+    }
+}
+
+/// A JSON array capped at `max_bulk_size` elements. Parsing stops as soon as
+/// the limit is exceeded, so an oversized batch is rejected without
+/// materialising every element.
+pub struct BulkJson<T>(pub Vec<T>);
+
+impl<T> FromRequest<SharedState> for BulkJson<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let bytes = json_body(req, state).await?;
+        let max = state.config.max_bulk_size;
+        let exceeded = Cell::new(false);
+        let index = Cell::new(0);
+        let unknown = UnknownFields::default();
+
+        let items = state.config.field_limits.scope(|| {
+            let mut de = serde_json::Deserializer::from_slice(&bytes);
+            BoundedSeq::<T> {
+                max,
+                exceeded: &exceeded,
+                index: &index,
+                unknown: state.config.strict_json.then_some(&unknown),
+                marker: PhantomData,
+            }
+            .deserialize(&mut de)
+            .and_then(|items| de.end().map(|_| items))
+        });
+
+        let unknown = unknown.into_inner();
+        match items {
+            Err(_) if exceeded.get() => Err(AppError::PayloadTooLarge(format!(
+                "Batch exceeds maximum of {max} items"
+            ))),
+            _ if !unknown.is_empty() => Err(AppError::FieldErrors(unknown)),
+            Ok(items) => Ok(BulkJson(items)),
+            Err(e) => match FieldLimits::take_rejected_field() {
+                Some(error) => Err(AppError::FieldErrors(vec![FieldError::new(
+                    &format!("items[{}].{}", index.get(), error.field),
+                    error.message,
+                )])),
+                None => Err(AppError::ValidationError(format!("Invalid JSON body: {e}"))),
+            },
+        }
+    }
+}
+
+struct BoundedSeq<'a, T> {
+    max: usize,
+    exceeded: &'a Cell<bool>,
+    /// Position of the element being parsed, for naming a rejected field.
+    index: &'a Cell<usize>,
+    /// Where unknown keys are collected in strict mode.
+    unknown: Option<&'a UnknownFields>,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for BoundedSeq<'_, T> {
+    type Value = Vec<T>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for BoundedSeq<'_, T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "an array of at most {} items", self.max)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(self.max));
+        loop {
+            self.index.set(items.len());
+            let element = Element {
+                index: items.len(),
+                unknown: self.unknown,
+                marker: PhantomData,
+            };
+            let Some(item) = seq.next_element_seed(element)? else {
+                break;
+            };
+            if items.len() == self.max {
+                self.exceeded.set(true);
+                return Err(de::Error::custom("too many items"));
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+struct Element<'a, T> {
+    index: usize,
+    unknown: Option<&'a UnknownFields>,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for Element<'_, T> {
+    type Value = T;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        match self.unknown {
+            Some(unknown) => {
+                let prefix = format!("items[{}].", self.index);
+                T::deserialize(Strict::body(deserializer, unknown, &prefix))
+            }
+            None => T::deserialize(deserializer),
+        }
+    }
+}
+
+/// Keys met while parsing that the target struct does not declare.
+type UnknownFields = RefCell<Vec<FieldError>>;
+
+/// Wraps a deserializer so that the struct it produces reports every key
+/// its `Deserialize` impl does not declare, instead of skipping it. Only the
+/// outermost struct is checked; types that are not plain structs pass
+/// straight through.
+struct Strict<'a, D> {
+    inner: D,
+    role: Role,
+    unknown: &'a UnknownFields,
+    /// Prepended to reported keys, such as `items[2].` for a batch element.
+    prefix: &'a str,
+}
+
+#[derive(Clone, Copy)]
+enum Role {
+    Body,
+    Key(&'static [&'static str]),
+}
+
+impl<'a, D> Strict<'a, D> {
+    fn body(inner: D, unknown: &'a UnknownFields, prefix: &'a str) -> Self {
+        Self {
+            inner,
+            role: Role::Body,
+            unknown,
+            prefix,
+        }
+    }
+}
+
+macro_rules! forward_to_inner {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.inner.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Strict<'_, D> {
+    type Error = D::Error;
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let Role::Body = self.role else {
+            return self.inner.deserialize_struct(name, fields, visitor);
+        };
+        let visitor = StrictStruct {
+            inner: visitor,
+            fields,
+            unknown: self.unknown,
+            prefix: self.prefix,
+        };
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let Role::Key(fields) = self.role else {
+            return self.inner.deserialize_identifier(visitor);
+        };
+        let visitor = StrictKey {
+            inner: visitor,
+            fields,
+            unknown: self.unknown,
+            prefix: self.prefix,
+        };
+        self.inner.deserialize_identifier(visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_enum(name, variants, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+
+    forward_to_inner! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+        deserialize_option deserialize_unit deserialize_seq deserialize_map
+        deserialize_ignored_any
+    }
+}
+
+struct StrictStruct<'a, V> {
+    inner: V,
+    fields: &'static [&'static str],
+    unknown: &'a UnknownFields,
+    prefix: &'a str,
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for StrictStruct<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_map(StrictMap {
+            inner: map,
+            fields: self.fields,
+            unknown: self.unknown,
+            prefix: self.prefix,
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_seq(seq)
+    }
+}
+
+struct StrictMap<'a, A> {
+    inner: A,
+    fields: &'static [&'static str],
+    unknown: &'a UnknownFields,
+    prefix: &'a str,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for StrictMap<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        self.inner.next_key_seed(KeySeed {
+            inner: seed,
+            fields: self.fields,
+            unknown: self.unknown,
+            prefix: self.prefix,
+        })
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        self.inner.next_value_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct KeySeed<'a, K> {
+    inner: K,
+    fields: &'static [&'static str],
+    unknown: &'a UnknownFields,
+    prefix: &'a str,
+}
+
+impl<'de, K: DeserializeSeed<'de>> DeserializeSeed<'de> for KeySeed<'_, K> {
+    type Value = K::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<K::Value, D::Error> {
+        self.inner.deserialize(Strict {
+            inner: deserializer,
+            role: Role::Key(self.fields),
+            unknown: self.unknown,
+            prefix: self.prefix,
+        })
+    }
+}
+
+/// Sees each key on its way to the derived field visitor and notes the ones
+/// the struct does not declare.
+struct StrictKey<'a, V> {
+    inner: V,
+    fields: &'static [&'static str],
+    unknown: &'a UnknownFields,
+    prefix: &'a str,
+}
+
+impl<V> StrictKey<'_, V> {
+    fn note(&self, key: &str) {
+        if !self.fields.contains(&key) {
+            self.unknown.borrow_mut().push(FieldError::new(
+                &format!("{}{key}", self.prefix),
+                "Unknown field",
+            ));
+        }
+    }
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for StrictKey<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<Self::Value, E> {
+        self.note(key);
+        self.inner.visit_str(key)
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, key: &'de str) -> Result<Self::Value, E> {
+        self.note(key);
+        self.inner.visit_borrowed_str(key)
+    }
+
+    fn visit_string<E: de::Error>(self, key: String) -> Result<Self::Value, E> {
+        self.note(&key);
+        self.inner.visit_string(key)
+    }
+
+    fn visit_u64<E: de::Error>(self, index: u64) -> Result<Self::Value, E> {
+        self.inner.visit_u64(index)
+    }
+}
+
+async fn json_body(req: Request, state: &SharedState) -> Result<Bytes, AppError> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned());
+
+    if !accepts_content_type(content_type.as_deref(), state.config.lenient_content_type) {
+        return Err(AppError::ValidationError(format!(
+            "Unsupported Content-Type: {}",
+            content_type.unwrap_or_else(|| "<missing>".to_string())
+        )));
+    }
+
+    Bytes::from_request(req, state)
+        .await
+        .map_err(|e| AppError::ValidationError(e.body_text()))
+}
+
+fn accepts_content_type(content_type: Option<&str>, lenient: bool) -> bool {
+    let Some(content_type) = content_type else {
+        return lenient;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    if mime == "application/json" || mime.ends_with("+json") {
+        return true;
+    }
+    lenient && mime == "text/plain"
+}
+
+/// Query parameters that need defaults or clamping applied after parsing.
+pub trait QueryParams {
+    fn normalize(&mut self, _config: &AppConfig) {}
+}
+
+impl QueryParams for FindAllUserRequest {
+    fn normalize(&mut self, config: &AppConfig) {
+        self.clamp(config.max_page_size);
+    }
+}
+
+impl QueryParams for ChangesQuery {
+    fn normalize(&mut self, config: &AppConfig) {
+        self.clamp(config.max_page_size);
+    }
+}
+
+impl QueryParams for SearchQuery {
+    fn normalize(&mut self, config: &AppConfig) {
+        self.clamp(config.max_search_page_size());
+    }
+}
+
+impl QueryParams for ExportFilter {}
+
+impl QueryParams for FieldsQuery {}
+
+impl QueryParams for CsvDialect {
+    fn normalize(&mut self, config: &AppConfig) {
+        self.bom.get_or_insert(config.csv_bom);
+    }
+}
+
+impl QueryParams for TimeseriesQuery {}
+
+impl QueryParams for DlqQuery {
+    fn normalize(&mut self, config: &AppConfig) {
+        let max = config.dlq_peek_limit.max(1);
+        self.limit = Some(self.limit.unwrap_or(max).clamp(1, max));
+    }
+}
+
+pub struct ValidQuery<T>(pub T);
+
+impl<T> FromRequestParts<SharedState> for ValidQuery<T>
+where
+    T: DeserializeOwned + QueryParams,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(mut value) = Query::<T>::try_from_uri(&parts.uri).map_err(|e| {
+            AppError::ValidationError(format!("Invalid query parameters: {}", e.body_text()))
+        })?;
+        value.normalize(&state.config);
+        Ok(ValidQuery(value))
+    }
+}
+
+pub struct AdminGuard;
+
+impl FromRequestParts<SharedState> for AdminGuard {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        if !state.config.admin_enabled {
+            return Err(AppError::Forbidden(
+                "Admin endpoints are disabled".to_string(),
+            ));
+        }
+
+        // `AppConfig::check` keeps the server from starting like this; a
+        // config built some other way still never opens the endpoints.
+        let Some(expected) = &state.config.admin_token else {
+            return Err(AppError::Forbidden(
+                "Admin endpoints need ADMIN_TOKEN to be set".to_string(),
+            ));
+        };
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // Compared in constant time so response timing does not reveal how
+        // much of a guessed token was right.
+        let matches = provided
+            .is_some_and(|provided| bool::from(provided.as_bytes().ct_eq(expected.as_bytes())));
+        if !matches {
+            return Err(AppError::Unauthorized);
+        }
+
+        Ok(AdminGuard)
+    }
+}
+
+/// The client identity sent in `X-Lock-Owner`, used for advisory edit locks.
+pub struct LockOwner(pub Option<String>);
+
+impl LockOwner {
+    pub fn required(self) -> Result<String, AppError> {
+        self.0
+            .ok_or_else(|| AppError::ValidationError("Missing X-Lock-Owner header".to_string()))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for LockOwner {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let owner = parts
+            .headers
+            .get("x-lock-owner")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_owned);
+        Ok(LockOwner(owner))
+    }
+}
@@ -1 +1,4 @@
 pub mod api;
+pub mod extract;
+pub mod middleware;
+pub mod run;
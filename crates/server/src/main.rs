@@ -1,47 +1,52 @@
-use dashmap::DashMap;
-use server::api::user_routes;
+use server::run::{RunError, run_combined, run_server, run_worker};
 use shared::{
-    kafka::{consumer::KafkaEventConsumer, producer::KafkaEventProducer},
-    repository::InMemoryUserRepository,
-    service::UserServiceImpl,
+    config::AppConfig,
+    context::{AppContext, ServiceBuilder},
+    shutdown,
 };
-use std::{env, sync::Arc};
+use std::{env, time::Duration};
 use tokio::net::TcpListener;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn main() -> Result<(), RunError> {
     let args: Vec<String> = env::args().collect();
-    let db = Arc::new(DashMap::new());
-    let repo = Arc::new(InMemoryUserRepository { db: db.clone() });
+    let config = AppConfig::from_env();
+    config.check()?;
+    let AppContext { config, service } = ServiceBuilder::new(config).build();
+    println!("⚙️ Config: {}", config.summary());
 
-    let kafka_producer: Option<Arc<KafkaEventProducer>> = Some(Arc::new(KafkaEventProducer::new(
-        "172.17.0.2:9092",
-        "user-jobs",
-    )));
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    let (trigger, shutdown_signal) = shutdown::channel();
+    tokio::spawn(async move {
+        shutdown::wait_for_os_signal().await;
+        println!("🛑 Shutdown signal received");
+        trigger.trigger();
+    });
 
-    let service = Arc::new(UserServiceImpl::new(repo, kafka_producer));
+    service.spawn_maintenance(shutdown_signal.clone());
+    service.spawn_expiry_sweeper(shutdown_signal.clone());
 
     match args.get(1).map(|s| s.as_str()) {
         Some("worker") => {
             println!("👷 Worker mode: consuming from Kafka");
-            let consumer = KafkaEventConsumer::new(
-                "172.17.0.2:9092",
-                "user-worker-group",
-                "user-jobs",
-                service.clone(),
-            );
-            consumer.await.start_listening().await;
+            run_worker(&config, service, shutdown_signal, grace).await?;
         }
         Some("server") | None => {
-            let addr = "0.0.0.0:5000";
-            let listener = TcpListener::bind(addr).await?;
-            println!("🚀 Server running on http://{}", addr);
-            let router = user_routes(service);
-            axum::serve(listener, router).await?;
+            run_server(bind().await?, service, shutdown_signal, grace).await?;
+        }
+        Some("combined") => {
+            println!("🧩 Combined mode: server and Kafka worker in one process");
+            let listener = bind().await?;
+            if let Err(e) = run_combined(&config, listener, service, shutdown_signal, grace).await {
+                eprintln!("❌ Server stopped: {}", e);
+                // Aborting is not enough while the worker sits in a blocking
+                // Kafka call, which the runtime waits for on the way out.
+                std::process::exit(1);
+            }
         }
         Some(unknown) => {
             eprintln!(
-                "❌ Unknown mode: {}. Usage: {} [server|worker]",
+                "❌ Unknown mode: {}. Usage: {} [server|worker|combined]",
                 unknown, args[0]
             );
             std::process::exit(1);
@@ -49,3 +54,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     }
     Ok(())
 }
+
+async fn bind() -> Result<TcpListener, RunError> {
+    let addr = "0.0.0.0:5000";
+    let listener = TcpListener::bind(addr).await?;
+    println!("🚀 Server running on http://{}", addr);
+    Ok(listener)
+}
@@ -0,0 +1,780 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody, to_bytes},
+    extract::{MatchedPath, Query, Request, State},
+    http::{HeaderValue, Method, StatusCode, Uri, header, response::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use dashmap::DashMap;
+use futures::StreamExt;
+use serde::Deserialize;
+use shared::{
+    config::TrailingSlash, database::SharedState, errors::AppError, feature_flags::Feature,
+    shutdown::ShutdownSignal,
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+/// Re-serializes JSON responses with indentation when `?pretty=true` is
+/// passed, or when `pretty_json` is enabled and the request does not opt out
+/// with `?pretty=false`. Compact responses pass through untouched.
+pub async fn json_format(State(state): State<SharedState>, req: Request, next: Next) -> Response {
+    let pretty = query_flag(req.uri().query(), "pretty").unwrap_or(state.config.pretty_json);
+    let response = next.run(req).await;
+
+    if !pretty {
+        return response;
+    }
+    let (mut parts, bytes) = match buffer_json(response).await {
+        Ok(buffered) => buffered,
+        Err(response) => return response,
+    };
+
+    match serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+    {
+        Ok(pretty) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(pretty))
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+/// Reads a JSON body into memory for the middlewares that rewrite it. Only
+/// bodies of known length are buffered: anything else, such as the
+/// streamed `/users/export.json`, is handed back untouched in `Err` so it
+/// keeps streaming. A body that fails to read becomes a 500.
+async fn buffer_json(response: Response) -> Result<(Parts, Bytes), Response> {
+    if !is_json(&response) {
+        return Err(response);
+    }
+    let Some(len) = response.body().size_hint().exact() else {
+        return Err(response);
+    };
+    let (parts, body) = response.into_parts();
+    to_bytes(body, len as usize)
+        .await
+        .map(|bytes| (parts, bytes))
+        .map_err(|e| {
+            AppError::Internal(format!("Failed to read response body: {e}")).into_response()
+        })
+}
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/json"))
+        .unwrap_or(false)
+}
+
+fn query_flag(query: Option<&str>, key: &str) -> Option<bool> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| matches!(value, "" | "1" | "true" | "yes" | "on"))
+}
+
+/// Fields rendered in the `?tz=` timezone. Everything else is left alone,
+/// even if it looks like a timestamp.
+const TIMESTAMP_FIELDS: [&str; 3] = ["created_at", "updated_at", "expires_at"];
+
+#[derive(Deserialize)]
+struct TimezoneQuery {
+    tz: Option<String>,
+}
+
+/// Rewrites the timestamp fields of JSON responses into the IANA timezone
+/// given as `?tz=America/New_York`. Without it they stay in UTC; an unknown
+/// name is a 400 before the handler runs.
+pub async fn timezone(req: Request, next: Next) -> Response {
+    let tz = match Query::<TimezoneQuery>::try_from_uri(req.uri()) {
+        Ok(Query(TimezoneQuery { tz: Some(name) })) => match name.parse::<Tz>() {
+            Ok(Tz::UTC) => None,
+            Ok(tz) => Some(tz),
+            Err(_) => {
+                return AppError::ValidationError(format!("Unknown timezone: {name}"))
+                    .into_response();
+            }
+        },
+        _ => None,
+    };
+    let response = next.run(req).await;
+
+    let Some(tz) = tz else {
+        return response;
+    };
+    let (mut parts, bytes) = match buffer_json(response).await {
+        Ok(buffered) => buffered,
+        Err(response) => return response,
+    };
+
+    match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(mut value) => {
+            convert_timestamps(&mut value, tz);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(value.to_string()))
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+fn convert_timestamps(value: &mut serde_json::Value, tz: Tz) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (name, field) in map.iter_mut() {
+                if let serde_json::Value::String(text) = field
+                    && TIMESTAMP_FIELDS.contains(&name.as_str())
+                {
+                    if let Ok(ts) = DateTime::parse_from_rfc3339(text) {
+                        *text = ts.with_timezone(&tz).to_rfc3339();
+                    }
+                } else {
+                    convert_timestamps(field, tz);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                convert_timestamps(item, tz);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Normalizes a trailing `/` according to `trailing_slash`. Must wrap the
+/// router from the outside, since it has to run before route matching.
+pub async fn trailing_slash(
+    State(state): State<SharedState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let mode = state.config.trailing_slash;
+    let path = req.uri().path();
+    if mode == TrailingSlash::Strict || path.len() <= 1 || !path.ends_with('/') {
+        return next.run(req).await;
+    }
+
+    let trimmed = path.trim_end_matches('/');
+    let trimmed = if trimmed.is_empty() { "/" } else { trimmed };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{trimmed}?{query}"),
+        None => trimmed.to_string(),
+    };
+
+    if mode == TrailingSlash::Redirect {
+        return (
+            StatusCode::PERMANENT_REDIRECT,
+            [(header::LOCATION, path_and_query)],
+        )
+            .into_response();
+    }
+
+    let mut parts = req.uri().clone().into_parts();
+    match path_and_query.parse() {
+        Ok(pq) => parts.path_and_query = Some(pq),
+        Err(_) => return next.run(req).await,
+    }
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+    next.run(req).await
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token buckets per route pattern. Each bucket holds up to one second's
+/// worth of requests, so short bursts up to the limit are allowed.
+pub struct RouteRateLimiter {
+    limits: HashMap<String, u32>,
+    buckets: HashMap<String, Mutex<TokenBucket>>,
+}
+
+impl RouteRateLimiter {
+    pub fn new(limits: HashMap<String, u32>) -> Self {
+        let now = Instant::now();
+        let buckets = limits
+            .iter()
+            .map(|(route, &limit)| {
+                let bucket = TokenBucket {
+                    tokens: f64::from(limit),
+                    refilled_at: now,
+                };
+                (route.clone(), Mutex::new(bucket))
+            })
+            .collect();
+        Self { limits, buckets }
+    }
+
+    /// Takes a token for `route`, or returns how long until the next one is
+    /// available.
+    fn try_acquire(&self, route: &str) -> Result<(), Duration> {
+        let (Some(&limit), Some(bucket)) = (self.limits.get(route), self.buckets.get(route)) else {
+            return Ok(());
+        };
+        let mut bucket = bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * f64::from(limit)).min(f64::from(limit));
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / f64::from(limit.max(1));
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+/// Applies [`RouteRateLimiter`] by matched route. Install with
+/// `route_layer` so the route pattern is known and unmatched paths are not
+/// counted.
+pub async fn route_rate_limit(
+    State(limiter): State<Arc<RouteRateLimiter>>,
+    matched: Option<MatchedPath>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Some(route) = matched
+        && let Err(wait) = limiter.try_acquire(route.as_str())
+    {
+        let response =
+            AppError::TooManyRequests(format!("Rate limit exceeded for {}", route.as_str()))
+                .into_response();
+        return with_retry_after(response, wait);
+    }
+    next.run(req).await
+}
+
+/// Requests without `X-Api-Key` all count against this key.
+const ANONYMOUS_API_KEY: &str = "anonymous";
+
+/// In-flight import/export jobs per API key (`X-Api-Key`), so one client
+/// cannot tie up every job slot. A zero limit turns the check off.
+pub struct JobLimiter {
+    limit: usize,
+    in_flight: Arc<DashMap<String, usize>>,
+}
+
+impl JobLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            in_flight: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Claims a slot for `key`, or `None` if it already has `limit` jobs
+    /// running. The slot is given back when the returned guard drops.
+    fn try_acquire(&self, key: &str) -> Option<JobSlot> {
+        let mut count = self.in_flight.entry(key.to_string()).or_insert(0);
+        if self.limit > 0 && *count >= self.limit {
+            return None;
+        }
+        *count += 1;
+        Some(JobSlot {
+            key: key.to_string(),
+            in_flight: self.in_flight.clone(),
+        })
+    }
+}
+
+/// Decrements the key's count on drop, however the job ended.
+struct JobSlot {
+    key: String,
+    in_flight: Arc<DashMap<String, usize>>,
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        if let Some(mut count) = self.in_flight.get_mut(&self.key) {
+            *count = count.saturating_sub(1);
+        }
+        self.in_flight.remove_if(&self.key, |_, count| *count == 0);
+    }
+}
+
+/// Applies [`JobLimiter`]. Jobs often stream their result, so the slot is
+/// held until the response body has been sent or dropped, not just until
+/// the handler returns.
+pub async fn job_limit(
+    State(limiter): State<Arc<JobLimiter>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let key = req
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .unwrap_or(ANONYMOUS_API_KEY)
+        .to_string();
+    let Some(slot) = limiter.try_acquire(&key) else {
+        return AppError::TooManyRequests(format!(
+            "At most {} import/export jobs may run at once per API key",
+            limiter.limit
+        ))
+        .into_response();
+    };
+
+    hold_until_body_ends(next.run(req).await, slot)
+}
+
+/// Open event streams across every route it is installed on. A zero limit
+/// turns the cap off.
+pub struct SubscriberLimiter {
+    limit: usize,
+    active: Arc<AtomicUsize>,
+}
+
+impl SubscriberLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn try_acquire(&self) -> Option<Subscriber> {
+        let limit = if self.limit == 0 {
+            usize::MAX
+        } else {
+            self.limit
+        };
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < limit).then_some(active + 1)
+            })
+            .ok()?;
+        Some(Subscriber(self.active.clone()))
+    }
+}
+
+/// Gives the subscriber's place back when the stream is dropped, whether
+/// it finished or the client went away.
+struct Subscriber(Arc<AtomicUsize>);
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Applies [`SubscriberLimiter`], holding the place for as long as the
+/// response body is streaming.
+pub async fn subscriber_limit(
+    State(limiter): State<Arc<SubscriberLimiter>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(subscriber) = limiter.try_acquire() else {
+        return AppError::ServiceUnavailable(format!(
+            "Too many open event streams (limit {})",
+            limiter.limit
+        ))
+        .into_response();
+    };
+    hold_until_body_ends(next.run(req).await, subscriber)
+}
+
+/// Moves `guard` into the response body so it drops with the stream.
+fn hold_until_body_ends<G: Send + 'static>(response: Response, guard: G) -> Response {
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _guard = &guard;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Turns new requests away once shutdown has begun, so clients retry
+/// elsewhere instead of having the request cut off when the grace period
+/// ends. Requests already running are not affected. `Retry-After` is the
+/// shutdown grace period, by which time a replacement instance should be
+/// taking traffic.
+pub async fn drain(
+    State((shutdown, grace)): State<(ShutdownSignal, Duration)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !shutdown.is_triggered() {
+        return next.run(req).await;
+    }
+    let mut response =
+        AppError::ServiceUnavailable("Server is shutting down".to_string()).into_response();
+    response
+        .headers_mut()
+        .insert(header::CONNECTION, HeaderValue::from_static("close"));
+    with_retry_after(response, grace)
+}
+
+/// Sets `Retry-After` in whole seconds, rounded up and at least one.
+fn with_retry_after(mut response: Response, wait: Duration) -> Response {
+    let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    response
+}
+
+/// Gives `429` and `503` responses that did not get a more specific hint
+/// the configured `retry_after_secs`, so clients back off instead of
+/// retrying at once. A zero setting leaves them without the header.
+pub async fn retry_after(State(state): State<SharedState>, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    let throttled = matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    );
+    if !throttled
+        || state.config.retry_after_secs == 0
+        || response.headers().contains_key(header::RETRY_AFTER)
+    {
+        return response;
+    }
+    with_retry_after(response, Duration::from_secs(state.config.retry_after_secs))
+}
+
+/// Stands in for a route whose feature is switched off.
+pub async fn feature_disabled(
+    State(feature): State<Feature>,
+    _req: Request,
+    _next: Next,
+) -> Response {
+    AppError::Forbidden(format!("The {feature} feature is disabled")).into_response()
+}
+
+/// Feeds every request's status and latency into the service metrics.
+/// Latency is measured up to the response head, not the end of a streamed
+/// body.
+pub async fn track_metrics(State(state): State<SharedState>, req: Request, next: Next) -> Response {
+    let _in_flight = state.metrics.start_request();
+    let started = Instant::now();
+    let response = next.run(req).await;
+    state
+        .metrics
+        .record_response(response.status().as_u16(), started.elapsed());
+    response
+}
+
+/// Logs every request that takes longer than `threshold`, with its method,
+/// path and elapsed time. A zero threshold turns the check off.
+#[derive(Debug, Clone, Copy)]
+pub struct SlowRequestLayer {
+    threshold: Duration,
+    log: SlowRequestLog,
+}
+
+/// Receives the method, path and elapsed time of each slow request.
+pub type SlowRequestLog = fn(&Method, &str, Duration);
+
+fn log_slow_request(method: &Method, path: &str, elapsed: Duration) {
+    eprintln!("🐢 Slow request: {} {} took {:?}", method, path, elapsed);
+}
+
+impl SlowRequestLayer {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            log: log_slow_request,
+        }
+    }
+
+    /// Reports slow requests to `log` instead of stderr.
+    pub fn with_log(mut self, log: SlowRequestLog) -> Self {
+        self.log = log;
+        self
+    }
+}
+
+impl<S> Layer<S> for SlowRequestLayer {
+    type Service = SlowRequestService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SlowRequestService {
+            inner,
+            threshold: self.threshold,
+            log: self.log,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SlowRequestService<S> {
+    inner: S,
+    threshold: Duration,
+    log: SlowRequestLog,
+}
+
+impl<S> Service<Request> for SlowRequestService<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let threshold = self.threshold;
+        let log = self.log;
+        let started = Instant::now();
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = future.await;
+            let elapsed = started.elapsed();
+            if !threshold.is_zero() && elapsed > threshold {
+                log(&method, &path, elapsed);
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Json, Router, middleware::from_fn, middleware::from_fn_with_state, routing::get};
+    use shared::{config::AppConfig, context::ServiceBuilder};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const STREAMED: [&str; 2] = [r#"[{"created_at":"2024-01-01T00:00:00Z"}"#, "]"];
+
+    fn app() -> Router {
+        let state = ServiceBuilder::new(AppConfig::default())
+            .without_kafka()
+            .build()
+            .service;
+        Router::new()
+            .route(
+                "/buffered",
+                get(|| async { Json(serde_json::json!({ "created_at": "2024-01-01T00:00:00Z" })) }),
+            )
+            .route(
+                "/streamed",
+                get(|| async {
+                    let chunks = futures::stream::iter(
+                        STREAMED.map(|chunk| Ok::<_, std::io::Error>(chunk.to_string())),
+                    );
+                    (
+                        [(header::CONTENT_TYPE, "application/json")],
+                        Body::from_stream(chunks),
+                    )
+                }),
+            )
+            .layer(from_fn(timezone))
+            .layer(from_fn_with_state(state, json_format))
+    }
+
+    async fn get_body(uri: &str) -> (StatusCode, String) {
+        let response = app()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn buffered_json_is_rewritten() {
+        let (status, body) = get_body("/buffered?tz=Asia/Tokyo&pretty=true").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("2024-01-01T09:00:00+09:00"), "{body}");
+        assert!(body.contains('\n'), "{body}");
+    }
+
+    #[tokio::test]
+    async fn json_stays_compact_by_default() {
+        let (status, body) = get_body("/buffered").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains('\n'), "{body}");
+    }
+
+    #[tokio::test]
+    async fn unknown_timezone_is_rejected() {
+        let (status, _) = get_body("/buffered?tz=Mars/Olympus").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn streamed_json_passes_through() {
+        let (status, body) = get_body("/streamed?tz=Asia/Tokyo&pretty=true").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, STREAMED.concat());
+    }
+
+    static SLOW: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn record_slow(method: &Method, path: &str, _elapsed: Duration) {
+        SLOW.lock().unwrap().push(format!("{method} {path}"));
+    }
+
+    #[tokio::test]
+    async fn only_requests_over_the_threshold_are_logged() {
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "done" }))
+            .layer(SlowRequestLayer::new(Duration::from_millis(50)).with_log(record_slow));
+
+        for uri in ["/fast", "/slow"] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert_eq!(*SLOW.lock().unwrap(), vec!["GET /slow"]);
+    }
+
+    #[tokio::test]
+    async fn job_slots_are_counted_per_api_key() {
+        let entered = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+        let app = Router::new()
+            .route(
+                "/slow",
+                get({
+                    let (entered, release) = (entered.clone(), release.clone());
+                    move || async move {
+                        entered.notify_one();
+                        release.notified().await;
+                        "done"
+                    }
+                }),
+            )
+            .route(
+                "/fail",
+                get(|| async { AppError::Internal("boom".to_string()) }),
+            )
+            .route_layer(from_fn_with_state(Arc::new(JobLimiter::new(1)), job_limit));
+        let call = |uri: &'static str, key: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(uri)
+                    .header("x-api-key", key)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                status
+            }
+        };
+
+        let slow = tokio::spawn(call("/slow", "a"));
+        entered.notified().await;
+        assert_eq!(call("/fail", "a").await, StatusCode::TOO_MANY_REQUESTS);
+        // Another key has its own slot, and a failed job gives it back.
+        assert_eq!(call("/fail", "b").await, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(call("/fail", "b").await, StatusCode::INTERNAL_SERVER_ERROR);
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap(), StatusCode::OK);
+        assert_eq!(call("/fail", "a").await, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn subscriber_place_is_held_until_the_stream_is_dropped() {
+        let app = Router::new()
+            .route(
+                "/events",
+                get(|| async {
+                    Body::from_stream(futures::stream::pending::<Result<String, std::io::Error>>())
+                }),
+            )
+            .route_layer(from_fn_with_state(
+                Arc::new(SubscriberLimiter::new(1)),
+                subscriber_limit,
+            ));
+        let open = || {
+            let app = app.clone();
+            async move {
+                let request = Request::get("/events").body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap()
+            }
+        };
+
+        let first = open().await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(open().await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(first);
+        assert_eq!(open().await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn throttled_responses_get_the_default_retry_after() {
+        let retry_after_of = |retry_after_secs: u64, uri: &'static str| async move {
+            let state = ServiceBuilder::new(AppConfig {
+                retry_after_secs,
+                ..AppConfig::default()
+            })
+            .without_kafka()
+            .build()
+            .service;
+            let app = Router::new()
+                .route("/busy", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+                .route(
+                    "/hinted",
+                    get(|| async { (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "9")]) }),
+                )
+                .route("/ok", get(|| async { "ok" }))
+                .layer(from_fn_with_state(state, retry_after));
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(retry_after_of(5, "/busy").await.as_deref(), Some("5"));
+        assert_eq!(retry_after_of(5, "/hinted").await.as_deref(), Some("9"));
+        assert_eq!(retry_after_of(5, "/ok").await, None);
+        assert_eq!(retry_after_of(0, "/busy").await, None);
+    }
+}
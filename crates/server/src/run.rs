@@ -0,0 +1,150 @@
+use std::{future::IntoFuture, time::Duration};
+
+use shared::{
+    config::AppConfig,
+    database::SharedState,
+    kafka::{
+        admin::{TopicSpec, ensure_topics},
+        consumer::KafkaEventConsumer,
+    },
+    shutdown::{self, ShutdownSignal},
+};
+use tokio::net::TcpListener;
+
+use crate::api::user_routes;
+
+pub type RunError = Box<dyn std::error::Error + Send + Sync>;
+
+pub async fn run_server(
+    listener: TcpListener,
+    service: SharedState,
+    shutdown_signal: ShutdownSignal,
+    grace: Duration,
+) -> Result<(), RunError> {
+    let router = user_routes(service, shutdown_signal.clone());
+    let server = axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown_signal.clone().recv())
+        .into_future();
+    if let Some(result) = shutdown::run_with_grace(server, shutdown_signal, grace).await {
+        result?;
+    }
+    Ok(())
+}
+
+pub async fn run_worker(
+    config: &AppConfig,
+    service: SharedState,
+    shutdown_signal: ShutdownSignal,
+    grace: Duration,
+) -> Result<(), RunError> {
+    let spec = TopicSpec {
+        partitions: config.topic_partitions,
+        replication: config.topic_replication,
+    };
+    ensure_topics(
+        &config.kafka_brokers,
+        &config.kafka_auth,
+        &config.kafka_topics.topics(),
+        config.create_topics,
+        spec,
+    )
+    .await?;
+    let topics = config.kafka_topics.worker_topics();
+    let consumer = KafkaEventConsumer::new(
+        &config.kafka_brokers,
+        &config.kafka_auth,
+        "user-worker-group",
+        &topics,
+        &config.kafka_assignment,
+        service,
+    );
+    consumer
+        .await
+        .with_csv_job_limit(config.max_concurrent_csv_jobs)
+        .with_dedup_window(
+            Duration::from_secs(config.event_dedup_window_secs),
+            config.cache_max_entries,
+        )
+        .start_listening(shutdown_signal, grace)
+        .await;
+    Ok(())
+}
+
+/// Runs the server and the Kafka worker side by side on one service.
+///
+/// A broken Kafka setup should not take the API down with it in local
+/// development, so worker errors are only logged. A server error is returned
+/// right away without waiting for the worker, which may be stuck in a
+/// blocking Kafka call; the caller is expected to exit.
+pub async fn run_combined(
+    config: &AppConfig,
+    listener: TcpListener,
+    service: SharedState,
+    shutdown_signal: ShutdownSignal,
+    grace: Duration,
+) -> Result<(), RunError> {
+    let worker = tokio::spawn({
+        let (config, service, shutdown_signal) =
+            (config.clone(), service.clone(), shutdown_signal.clone());
+        async move {
+            if let Err(e) = run_worker(&config, service, shutdown_signal, grace).await {
+                eprintln!("❌ Worker stopped: {}", e);
+            }
+        }
+    });
+    run_server(listener, service, shutdown_signal.clone(), grace).await?;
+    // The worker stops on its own after the shutdown signal, within the same
+    // grace period as the server.
+    shutdown::run_with_grace(worker, shutdown_signal, grace).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{context::ServiceBuilder, kafka::KafkaAuth};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn combined_mode_serves_requests_when_kafka_is_broken() {
+        let config = AppConfig {
+            // Rejected by librdkafka up front, so the worker fails at once
+            // instead of waiting on a metadata timeout.
+            kafka_auth: KafkaAuth {
+                security_protocol: Some("bogus".to_string()),
+                ..KafkaAuth::default()
+            },
+            ..AppConfig::default()
+        };
+        let service = ServiceBuilder::new(config.clone())
+            .without_kafka()
+            .build()
+            .service;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, signal) = shutdown::channel();
+        let running = tokio::spawn(async move {
+            run_combined(&config, listener, service, signal, Duration::from_secs(1)).await
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /users HTTP/1.1\r\nhost: test\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        trigger.trigger();
+        let outcome = tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("combined mode did not shut down")
+            .unwrap();
+        assert!(outcome.is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use shared::{
+    abstract_trait::UserServiceTrait,
+    config::AppConfig,
+    context::ServiceBuilder,
+    database::SharedState,
+    domain::{CreateUserRequest, CsvDialect, ExportFilter, SearchField},
+};
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 3] = [100, 1_000, 5_000];
+
+fn request(i: usize) -> CreateUserRequest {
+    CreateUserRequest {
+        id: None,
+        name: format!("User {i}"),
+        email: format!("user{i}@example.com"),
+        age: (i % 100) as u8,
+        expires_at: None,
+    }
+}
+
+fn fresh_service() -> SharedState {
+    ServiceBuilder::new(AppConfig::default())
+        .without_kafka()
+        .build()
+        .service
+}
+
+fn seeded_service(rt: &Runtime, size: usize) -> SharedState {
+    let service = fresh_service();
+    rt.block_on(async {
+        for i in 0..size {
+            service.repo.create_user(&request(i)).await.unwrap();
+        }
+    });
+    service
+}
+
+fn bench_create_user(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("create_user");
+
+    for size in SIZES {
+        // Each iteration adds one more user, so the store grows slightly past
+        // `size` over the run.
+        let service = seeded_service(&rt, size);
+        let next = AtomicUsize::new(size);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                service.create_user(&request(i)).await.unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_find_all(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("find_all");
+
+    for size in SIZES {
+        let service = seeded_service(&rt, size);
+
+        group.bench_with_input(BenchmarkId::new("no_search", size), &size, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                service
+                    .repo
+                    .find_all(1, 50, None, SearchField::All)
+                    .await
+                    .unwrap()
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("search", size), &size, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                service
+                    .repo
+                    .find_all(1, 50, Some("user 4".to_string()), SearchField::All)
+                    .await
+                    .unwrap()
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_bulk_create(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("bulk_create_users");
+    group.sample_size(10);
+
+    for size in SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.to_async(&rt).iter_batched(
+                || (fresh_service(), (0..size).map(request).collect::<Vec<_>>()),
+                |(service, inputs)| async move {
+                    service.bulk_create_users(inputs).await.unwrap();
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_csv(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("csv");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let service = seeded_service(&rt, size);
+        let filter = ExportFilter::default();
+
+        group.bench_with_input(BenchmarkId::new("export", size), &size, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                service
+                    .render_csv(&filter, &CsvDialect::default())
+                    .await
+                    .unwrap()
+            });
+        });
+
+        let csv = rt
+            .block_on(service.render_csv(&filter, &CsvDialect::default()))
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("import", size), &size, |b, _| {
+            b.to_async(&rt).iter_batched(
+                || (fresh_service(), csv.clone()),
+                |(service, csv)| async move {
+                    service.import_csv_bytes(csv, None).await.unwrap();
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_create_user,
+    bench_find_all,
+    bench_bulk_create,
+    bench_csv
+);
+criterion_main!(benches);
@@ -1,8 +1,20 @@
+use chrono::{DateTime, Utc};
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
+use tokio::sync::broadcast;
+
 use crate::{
+    csv_import::ImportReport,
     domain::{
-        ApiResponse, ApiResponsePagination, CreateUserRequest, FindAllUserRequest,
+        ApiResponse, ApiResponsePagination, ApiResponseSearch, BulkCreateReport, BulkUpsertResult,
+        ChangesQuery, CreateUserRequest, CsvDialect, EmailChangeRequest, EmailChangeToken,
+        EmailDomainCount, ExportFilter, FindAllUserRequest, ImportProgress, MergeField,
+        MergeUsersRequest, ReconcileReport, SearchField, SearchQuery, SimilarUser,
         UpdateUserRequest, User, UserResponse,
     },
+    edit_lock::EditLock,
     errors::AppError,
 };
 
@@ -13,13 +25,122 @@ pub trait UserRepositoryTrait: Send + Sync {
         page: i32,
         page_size: i32,
         search: Option<String>,
+        search_field: SearchField,
+    ) -> Result<(Vec<User>, i64), AppError>;
+    /// Users whose `updated_at` lies within `since..=until`, ordered by
+    /// `updated_at` so incremental sync clients can page through changes.
+    async fn find_updated_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
     ) -> Result<(Vec<User>, i64), AppError>;
     async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError>;
     async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError>;
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError>;
     async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError>;
+    /// The users among `ids` that exist, in the order of `ids`. Backends
+    /// with a batched lookup should override this; the default issues one
+    /// `find_by_id` per id, at most `concurrency` at a time.
+    async fn find_by_ids(&self, ids: &[String], concurrency: usize) -> Result<Vec<User>, AppError> {
+        let lookups: Vec<_> = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| async move { (index, self.find_by_id(id).await) })
+            .collect();
+        let results: Vec<_> = stream::iter(lookups)
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+        let mut found = Vec::with_capacity(results.len());
+        for (index, result) in results {
+            if let Some(user) = result? {
+                found.push((index, user));
+            }
+        }
+        found.sort_by_key(|(index, _)| *index);
+        Ok(found.into_iter().map(|(_, user)| user).collect())
+    }
     async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError>;
+    /// Applies `input` only if the stored user still matches `if_match`,
+    /// failing with `412` otherwise, including when the user is missing.
+    /// The default compares before writing and is not atomic; backends that
+    /// can do better override it.
+    async fn update_if_match(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: &str,
+    ) -> Result<User, AppError> {
+        let current = self.find_by_id(id).await?;
+        check_if_match(current.as_ref(), if_match)?;
+        self.update_user(input, id).await
+    }
+    async fn update_by_email(
+        &self,
+        input: &UpdateUserRequest,
+        email: &str,
+    ) -> Result<User, AppError>;
     async fn delete_user(&self, email: &str) -> Result<(), AppError>;
+    async fn delete_by_id(&self, id: &str) -> Result<(), AppError>;
+    async fn clear(&self) -> Result<usize, AppError>;
+    async fn count(&self) -> Result<usize, AppError>;
+    /// Users physically held, including expired ones not yet purged. Used to
+    /// enforce `max_users` on every create, so backends should answer it
+    /// without a scan; the default falls back to `count`.
+    async fn stored_count(&self) -> Result<usize, AppError> {
+        self.count().await
+    }
+    /// Every user, oldest first, without materializing the whole table.
+    /// The stream owns what it needs, so it can outlive the borrow of `self`
+    /// and be handed straight to a response body.
+    fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>>;
+    /// Deletes `remove` and applies `fields` copied from it to `keep`,
+    /// returning the kept user. The default is a delete followed by an
+    /// update and is not atomic; backends that can do better override it.
+    async fn merge_users(
+        &self,
+        keep: &str,
+        remove: &str,
+        fields: &[MergeField],
+    ) -> Result<User, AppError> {
+        let removed = self
+            .find_by_id(remove)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+        if self.find_by_id(keep).await?.is_none() {
+            return Err(AppError::UserNotFound);
+        }
+        self.delete_by_id(remove).await?;
+        self.update_user(&MergeField::update_from(&removed, fields), keep)
+            .await
+    }
+    /// Deletes users whose `expires_at` has passed and returns how many
+    /// were removed. Deletes happen `batch_size` at a time, yielding in
+    /// between so a large backlog does not stall other requests.
+    async fn purge_expired(&self, _batch_size: usize) -> Result<usize, AppError> {
+        Ok(0)
+    }
+    /// Checks derived state against the primary store and repairs drift.
+    /// Backends without derived state have nothing to do.
+    async fn reconcile(&self) -> Result<ReconcileReport, AppError> {
+        Ok(ReconcileReport::default())
+    }
+}
+
+/// Fails with `412` unless `user` exists and matches `if_match`.
+pub fn check_if_match(user: Option<&User>, if_match: &str) -> Result<(), AppError> {
+    match user {
+        Some(user) if user.matches_if_match(if_match) => Ok(()),
+        _ => Err(stale_etag()),
+    }
+}
+
+/// The `412` for an `If-Match` that no longer matches, including one sent
+/// for a user that does not exist.
+pub fn stale_etag() -> AppError {
+    AppError::PreconditionFailed("User was modified since the given ETag".to_string())
 }
 
 #[async_trait::async_trait]
@@ -28,18 +149,229 @@ pub trait UserServiceTrait: Send + Sync {
         &self,
         req: FindAllUserRequest,
     ) -> Result<ApiResponsePagination<Vec<UserResponse>>, AppError>;
+    async fn get_changes(
+        &self,
+        query: ChangesQuery,
+    ) -> Result<ApiResponsePagination<Vec<UserResponse>>, AppError>;
+    async fn search_users(
+        &self,
+        query: SearchQuery,
+    ) -> Result<ApiResponseSearch<Vec<UserResponse>>, AppError>;
     async fn create_user(
         &self,
         input: &CreateUserRequest,
     ) -> Result<ApiResponse<UserResponse>, AppError>;
     async fn find_by_id(&self, id: &str) -> Result<Option<ApiResponse<UserResponse>>, AppError>;
+    async fn find_by_ids(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<ApiResponse<Vec<UserResponse>>, AppError>;
+    async fn find_similar(
+        &self,
+        id: &str,
+    ) -> Result<Option<ApiResponse<Vec<SimilarUser>>>, AppError>;
+    /// `owner` is the caller's edit lock identity, if any. Writes that
+    /// touch a user locked by someone else fail with `423 Locked` when
+    /// `enforce_edit_locks` is on. With `if_match`, the write only happens
+    /// if the user's ETag still matches, checked atomically with it.
     async fn update_user(
         &self,
         id: &str,
         input: &UpdateUserRequest,
+        owner: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<ApiResponse<UserResponse>>, AppError>;
+    async fn update_user_by_email(
+        &self,
+        email: &str,
+        input: &UpdateUserRequest,
+        owner: Option<&str>,
+        if_match: Option<&str>,
     ) -> Result<Option<ApiResponse<UserResponse>>, AppError>;
-    async fn delete_user(&self, email: &str) -> Result<ApiResponse<()>, AppError>;
-    async fn bulk_create_users(&self, inputs: Vec<CreateUserRequest>) -> Result<(), AppError>;
-    async fn export_to_csv(&self, path: &str) -> Result<(), AppError>;
+    async fn request_email_change(
+        &self,
+        id: &str,
+        input: &EmailChangeRequest,
+    ) -> Result<Option<ApiResponse<EmailChangeToken>>, AppError>;
+    async fn confirm_email_change(
+        &self,
+        token: &str,
+        owner: Option<&str>,
+    ) -> Result<ApiResponse<UserResponse>, AppError>;
+    /// Deletes honor edit locks like updates do; `owner` is as for
+    /// `update_user`.
+    async fn delete_user(
+        &self,
+        email: &str,
+        owner: Option<&str>,
+    ) -> Result<Option<ApiResponse<()>>, AppError>;
+    async fn delete_user_by_id(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<Option<ApiResponse<()>>, AppError>;
+    async fn lock_user(
+        &self,
+        id: &str,
+        owner: &str,
+    ) -> Result<Option<ApiResponse<EditLock>>, AppError>;
+    async fn unlock_user(&self, id: &str, owner: &str) -> Result<ApiResponse<()>, AppError>;
+    async fn clear_users(&self) -> Result<ApiResponse<usize>, AppError>;
+    async fn merge_users(
+        &self,
+        req: &MergeUsersRequest,
+        owner: Option<&str>,
+    ) -> Result<ApiResponse<UserResponse>, AppError>;
+    async fn email_domain_counts(&self) -> Result<ApiResponse<Vec<EmailDomainCount>>, AppError>;
+    async fn bulk_create_users(
+        &self,
+        inputs: Vec<CreateUserRequest>,
+    ) -> Result<BulkCreateReport, AppError>;
+    async fn bulk_upsert_users(
+        &self,
+        inputs: Vec<CreateUserRequest>,
+        owner: Option<&str>,
+    ) -> Result<ApiResponse<BulkUpsertResult>, AppError>;
+    async fn export_users(&self, filter: &ExportFilter) -> Result<Vec<User>, AppError>;
+    async fn render_csv(
+        &self,
+        filter: &ExportFilter,
+        dialect: &CsvDialect,
+    ) -> Result<Vec<u8>, AppError>;
+    async fn export_to_csv(
+        &self,
+        path: &str,
+        filter: &ExportFilter,
+        dialect: &CsvDialect,
+    ) -> Result<(), AppError>;
     async fn import_from_csv(&self, path: &str) -> Result<(), AppError>;
+    async fn validate_import(&self, contents: Vec<u8>) -> Result<ImportReport, AppError>;
+    async fn import_csv_bytes(
+        &self,
+        contents: Vec<u8>,
+        progress: Option<broadcast::Sender<ImportProgress>>,
+    ) -> Result<usize, AppError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+
+    /// Delegates to an in-memory repository but takes a while to answer
+    /// `find_by_id`, recording the most lookups that were ever in flight.
+    /// `find_by_ids` is left to the trait default.
+    struct SlowLookups {
+        inner: InMemoryUserRepository,
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl UserRepositoryTrait for SlowLookups {
+        async fn find_all(
+            &self,
+            page: i32,
+            page_size: i32,
+            search: Option<String>,
+            search_field: SearchField,
+        ) -> Result<(Vec<User>, i64), AppError> {
+            self.inner
+                .find_all(page, page_size, search, search_field)
+                .await
+        }
+        async fn find_updated_between(
+            &self,
+            since: Option<DateTime<Utc>>,
+            until: Option<DateTime<Utc>>,
+            page: i32,
+            page_size: i32,
+        ) -> Result<(Vec<User>, i64), AppError> {
+            self.inner
+                .find_updated_between(since, until, page, page_size)
+                .await
+        }
+        async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError> {
+            self.inner.find_by_email_exists(email).await
+        }
+        async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError> {
+            self.inner.create_user(input).await
+        }
+        async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+            self.inner.find_by_email(email).await
+        }
+        async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError> {
+            let now = self.in_flight.fetch_add(1, Ordering::AcqRel) + 1;
+            self.peak.fetch_max(now, Ordering::AcqRel);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.in_flight.fetch_sub(1, Ordering::AcqRel);
+            self.inner.find_by_id(id).await
+        }
+        async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError> {
+            self.inner.update_user(input, id).await
+        }
+        async fn update_by_email(
+            &self,
+            input: &UpdateUserRequest,
+            email: &str,
+        ) -> Result<User, AppError> {
+            self.inner.update_by_email(input, email).await
+        }
+        async fn delete_user(&self, email: &str) -> Result<(), AppError> {
+            self.inner.delete_user(email).await
+        }
+        async fn delete_by_id(&self, id: &str) -> Result<(), AppError> {
+            self.inner.delete_by_id(id).await
+        }
+        async fn clear(&self) -> Result<usize, AppError> {
+            self.inner.clear().await
+        }
+        async fn count(&self) -> Result<usize, AppError> {
+            self.inner.count().await
+        }
+        fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>> {
+            self.inner.stream_all()
+        }
+    }
+
+    #[tokio::test]
+    async fn default_find_by_ids_bounds_concurrency_and_keeps_input_order() {
+        let repo = Arc::new(SlowLookups {
+            inner: InMemoryUserRepository::new(),
+            in_flight: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        });
+        let mut ids = Vec::new();
+        for i in 0..12 {
+            let user = repo
+                .create_user(&CreateUserRequest {
+                    id: None,
+                    name: format!("User {i}"),
+                    email: format!("user{i}@example.com"),
+                    age: 30,
+                    expires_at: None,
+                })
+                .await
+                .unwrap();
+            ids.push(user.id);
+        }
+        ids.reverse();
+        ids.insert(3, "missing".to_string());
+
+        let found = repo.find_by_ids(&ids, 3).await.unwrap();
+
+        let peak = repo.peak.load(Ordering::Acquire);
+        assert!((2..=3).contains(&peak), "peak of {peak} lookups in flight");
+        let found: Vec<_> = found.into_iter().map(|user| user.id).collect();
+        let expected: Vec<_> = ids.into_iter().filter(|id| id != "missing").collect();
+        assert_eq!(found, expected);
+    }
 }
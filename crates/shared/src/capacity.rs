@@ -0,0 +1,88 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use tokio::sync::Mutex;
+
+use crate::{abstract_trait::UserRepositoryTrait, errors::AppError};
+
+/// Enforces `max_users` across concurrent creates. Each create reserves room
+/// before inserting, so parallel bulk and import writes cannot overshoot
+/// the cap between counting and inserting. A zero limit turns it off.
+pub struct UserCapacity {
+    max: usize,
+    /// Reservations whose inserts may not be visible in the repository yet.
+    pending: Arc<AtomicUsize>,
+    /// Serializes reservations so two callers cannot both see the same
+    /// free room. Held only for `stored_count`, which backends answer
+    /// without a scan.
+    reserving: Mutex<()>,
+}
+
+impl UserCapacity {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            pending: Arc::new(AtomicUsize::new(0)),
+            reserving: Mutex::new(()),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Reserves room for up to `wanted` new users. The grant may be smaller,
+    /// down to zero once the cap is reached, and lasts until the returned
+    /// reservation drops.
+    pub async fn reserve(
+        &self,
+        repo: &dyn UserRepositoryTrait,
+        wanted: usize,
+    ) -> Result<Reservation, AppError> {
+        if self.max == 0 {
+            return Ok(Reservation {
+                granted: wanted,
+                pending: None,
+            });
+        }
+
+        let _reserving = self.reserving.lock().await;
+        // Reservations are released only after their insert is visible, so
+        // reading `pending` before counting may count an insert twice but
+        // never misses one.
+        let pending = self.pending.load(Ordering::Acquire);
+        let stored = repo.stored_count().await?;
+        let granted = wanted.min(self.max.saturating_sub(stored + pending));
+        self.pending.fetch_add(granted, Ordering::AcqRel);
+        Ok(Reservation {
+            granted,
+            pending: Some(self.pending.clone()),
+        })
+    }
+
+    pub fn limit_error(&self) -> AppError {
+        AppError::InsufficientStorage(format!("User limit of {} reached", self.max))
+    }
+}
+
+/// Room for `granted` users. Drop it once the inserts it covers are done.
+pub struct Reservation {
+    granted: usize,
+    pending: Option<Arc<AtomicUsize>>,
+}
+
+impl Reservation {
+    pub fn granted(&self) -> usize {
+        self.granted
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(pending) = &self.pending {
+            pending.fetch_sub(self.granted, Ordering::AcqRel);
+        }
+    }
+}
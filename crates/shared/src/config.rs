@@ -0,0 +1,713 @@
+use std::{collections::HashMap, env};
+
+use encoding_rs::Encoding;
+
+use crate::{
+    csv_import::CsvImportOptions,
+    domain::AgeFormat,
+    feature_flags::FeatureFlags,
+    kafka::{Acks, KafkaAuth, PartitionAssignment, TopicRouting},
+    validation::{EmailDomainPolicy, FieldLimits},
+};
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// When enabled, deleting a user that does not exist succeeds with
+    /// `204 No Content` instead of `404 Not Found`. This follows idempotent
+    /// DELETE semantics, at the cost of hiding typos in the email/id from
+    /// the client.
+    pub delete_idempotent: bool,
+    /// Accept JSON bodies sent without a `Content-Type` or as `text/plain`,
+    /// as simple scripted clients often do.
+    pub lenient_content_type: bool,
+    /// Reject JSON bodies carrying fields the target type does not know,
+    /// instead of silently ignoring them. Off by default so older clients
+    /// sending extra fields keep working.
+    pub strict_json: bool,
+    /// Number of rows inserted between two progress reports on CSV import.
+    pub import_progress_every: usize,
+    /// Admin endpoints are disabled unless this is set, to avoid accidental
+    /// data loss from destructive operations.
+    pub admin_enabled: bool,
+    /// Bearer token required on admin endpoints. There is no unauthenticated
+    /// admin mode: `check` refuses a config that enables admin endpoints
+    /// without one, and they stay closed if it is missing anyway.
+    pub admin_token: Option<String>,
+    /// 64 hex digits. When set, emails are stored encrypted, sealed the
+    /// same way each time so the ciphertext doubles as the lookup key; see
+    /// `EncryptedEmailRepository`.
+    pub email_encryption_key: Option<String>,
+    /// With `email_encryption_key`, store only the keyed hash of each email
+    /// instead of an encrypted copy. Existence and duplicate checks keep
+    /// working, but addresses cannot be read back.
+    pub email_hash_only: bool,
+    /// Serialize `age` in responses as a JSON string for clients that cannot
+    /// handle numbers there.
+    pub age_format: AgeFormat,
+    /// Maximum number of concurrent repository calls during bulk operations.
+    pub bulk_concurrency: usize,
+    /// Validate every item of `POST /users/bulk` before inserting any and
+    /// reject the whole batch with `422` and all errors if one is invalid.
+    /// Otherwise valid items are inserted and failures reported per item.
+    pub bulk_validate_first: bool,
+    /// Maximum number of concurrent lookups in `POST /users/bulk-get` for
+    /// backends that cannot fetch a batch of ids in one query.
+    pub bulk_get_concurrency: usize,
+    /// Deadline for synchronous export requests, covering both the repository
+    /// read and streaming the body.
+    pub export_timeout_secs: u64,
+    /// How many times a failed repository read is retried before an export
+    /// gives up.
+    pub export_read_retries: u32,
+    /// Above this many users the inline export endpoints queue a Kafka
+    /// export job and answer `202 Accepted` instead. `0` means no limit.
+    pub max_sync_export_rows: usize,
+    /// Prefix CSV exports with a UTF-8 byte order mark unless the request
+    /// says otherwise with `?bom=`.
+    pub csv_bom: bool,
+    pub kafka_brokers: String,
+    /// From `KAFKA_SECURITY_PROTOCOL`, `KAFKA_SASL_MECHANISM`,
+    /// `KAFKA_SASL_USERNAME` and `KAFKA_SASL_PASSWORD`; empty for an
+    /// unsecured cluster.
+    pub kafka_auth: KafkaAuth,
+    /// `KAFKA_CDC_TOPIC` and `KAFKA_DLQ_TOPIC` fall back to
+    /// `KAFKA_JOBS_TOPIC`. `GET /admin/dlq` only works with a DLQ topic of
+    /// its own.
+    pub kafka_topics: TopicRouting,
+    /// `all` (default) for durable delivery, `1` to wait for the leader
+    /// only, `0` for fire-and-forget at the highest throughput.
+    pub kafka_acks: Acks,
+    /// Set `KAFKA_PARTITIONS=topic:0,topic:2` to make the worker read only
+    /// those partitions instead of joining the group's rebalancing.
+    pub kafka_assignment: PartitionAssignment,
+    /// Produce requests allowed in flight at once; further sends wait for
+    /// one to complete.
+    pub kafka_max_in_flight: usize,
+    /// Most messages `GET /admin/dlq` returns, and the default when the
+    /// request gives no `?limit=`.
+    pub dlq_peek_limit: usize,
+    /// How long `GET /admin/dlq` waits for messages before returning what it
+    /// has.
+    pub dlq_peek_timeout_ms: u64,
+    /// Largest array accepted by the bulk endpoints.
+    pub max_bulk_size: usize,
+    /// Most users stored at once. Creates beyond it fail with
+    /// `507 Insufficient Storage`; bulk creates and imports stop at the cap
+    /// and report what was left out. Expired users count until the sweeper
+    /// purges them. `0` means no limit.
+    pub max_users: usize,
+    /// Upper bound applied to `page_size` on listing endpoints.
+    pub max_page_size: i32,
+    /// Largest serialized body, in bytes, for a page of users from the
+    /// listing and search endpoints. Larger pages are refused with
+    /// `413 Payload Too Large` rather than cut short, since a short page
+    /// would throw off the offsets of the pages after it. `0` means no cap.
+    pub max_list_response_bytes: usize,
+    /// Results per page on `/users/search` when the client sends no
+    /// `page_size`, and the most it may ask for (within `max_page_size`).
+    /// Also the most matches any page may reach: pages past it are empty.
+    /// Further matches are counted and reported via `truncated`.
+    pub max_search_results: usize,
+    /// Shortest `q` accepted by `/users/search`. Shorter terms match most of
+    /// the dataset, so they are rejected instead of scanned.
+    pub min_search_len: usize,
+    /// What `/users/search` does with a blank `q`.
+    pub empty_search: EmptySearch,
+    /// Keep a name token index in the in-memory repository. Name searches
+    /// then match whole-word prefixes in any order (`smith jo` finds
+    /// `John Smith`) instead of a substring scan.
+    pub search_index: bool,
+    /// How long in-flight requests and Kafka handlers may keep running after
+    /// a shutdown signal before they are aborted.
+    pub shutdown_grace_secs: u64,
+    /// `Retry-After` sent with `429` and `503` responses that have no better
+    /// estimate, such as job and event stream limits. `0` omits it.
+    pub retry_after_secs: u64,
+    /// Width of each bucket in the stats timeseries.
+    pub stats_bucket_secs: u64,
+    /// Number of buckets kept before the oldest is dropped.
+    pub stats_retention_buckets: usize,
+    pub email_domain_policy: EmailDomainPolicy,
+    pub field_limits: FieldLimits,
+    /// Create missing Kafka topics on worker startup instead of failing.
+    pub create_topics: bool,
+    /// Publish a `UserChanged` event to the CDC topic after every user
+    /// create, update and delete. Clears and expiry purges are not
+    /// published.
+    pub publish_changes: bool,
+    pub topic_partitions: i32,
+    pub topic_replication: i32,
+    /// Indent JSON responses by default. Clients can still override per
+    /// request with `?pretty=true|false`.
+    pub pretty_json: bool,
+    pub trailing_slash: TrailingSlash,
+    /// Status of a `POST /users/bulk` where some elements failed.
+    pub bulk_failure_status: BulkFailureStatus,
+    /// Maximum number of entries in each bounded cache (event dedup, email
+    /// change tokens, download tokens) before the least recently used one
+    /// is evicted. Each cache has its own TTL setting.
+    pub cache_max_entries: usize,
+    pub csv_import: CsvImportOptions,
+    /// Requests per second allowed on a route, keyed by the route pattern as
+    /// registered (e.g. `/users/search`, `/users/{id}`). Routes not listed
+    /// are unlimited. Set as `ROUTE_RATE_LIMITS=/users/search=10,...`.
+    pub route_rate_limits: HashMap<String, u32>,
+    /// Lifetime of an advisory edit lock taken via `POST /users/{id}/lock`.
+    pub edit_lock_ttl_secs: u64,
+    /// How long a token from `POST /users/{id}/email-change` can be
+    /// confirmed.
+    pub email_change_ttl_secs: u64,
+    /// How long the `GET /downloads/{token}` link of a queued export stays
+    /// valid, counted from when the export is queued.
+    pub download_token_ttl_secs: u64,
+    /// Reject writes to a user with `423 Locked` when another client holds
+    /// its edit lock: updates and deletes by id or email, email change
+    /// confirmations, merges and the update half of bulk upserts. Without
+    /// this, locks are purely informational.
+    pub enforce_edit_locks: bool,
+    /// Interval of the background task that reconciles repository state and
+    /// drops expired locks. `0` disables it.
+    pub maintenance_interval_secs: u64,
+    /// Interval of the background task deleting users whose `expires_at`
+    /// has passed. Expired users are hidden from reads either way. `0`
+    /// disables the sweep.
+    pub expiry_sweep_interval_secs: u64,
+    /// Users the expiry sweep deletes before yielding to other tasks.
+    pub expiry_sweep_batch_size: usize,
+    /// How many CSV import/export jobs the worker runs at the same time.
+    pub max_concurrent_csv_jobs: usize,
+    /// How long the worker remembers handled event ids, so a redelivered
+    /// event within this window is skipped instead of run again. `0` turns
+    /// deduplication off.
+    pub event_dedup_window_secs: u64,
+    /// How many import/export requests one API key (`X-Api-Key`) may have
+    /// in flight on the server. Clients without a key share one budget.
+    /// `0` means unlimited.
+    pub max_jobs_per_api_key: usize,
+    /// Largest name edit distance reported by `/users/{id}/similar`.
+    pub similar_name_threshold: usize,
+    /// Most candidates returned by `/users/{id}/similar`.
+    pub similar_limit: usize,
+    /// Accept a client-supplied `id` on create and keep the `id` column on
+    /// CSV import. Ids must be UUIDs or ULIDs and must not already exist.
+    pub allow_client_ids: bool,
+    /// Requests slower than this are logged. `0` disables the check.
+    pub slow_request_ms: u64,
+    /// Server-sent event streams open at once, such as import progress.
+    /// Further connections get `503 Service Unavailable`. `0` means no cap.
+    pub max_sse_subscribers: usize,
+    /// What happens to an event stream client that falls behind.
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    /// Operations switched off via `FEATURE_FLAGS` or `FEATURE_FLAGS_FILE`.
+    pub feature_flags: FeatureFlags,
+}
+
+/// How a request path ending in `/` (other than the root) is routed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Route `/users/` exactly as `/users`.
+    #[default]
+    Match,
+    /// Answer with `308 Permanent Redirect` to the path without the slash.
+    Redirect,
+    /// Leave the path alone, so `/users/` does not match `/users`.
+    Strict,
+}
+
+impl std::str::FromStr for TrailingSlash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "match" => Ok(TrailingSlash::Match),
+            "redirect" => Ok(TrailingSlash::Redirect),
+            "strict" => Ok(TrailingSlash::Strict),
+            other => Err(format!("Unknown trailing slash mode: {other}")),
+        }
+    }
+}
+
+/// Event streams are fed through a bounded broadcast buffer so a slow
+/// client never holds up the work it is watching. These are the options
+/// once a client has fallen further behind than the buffer holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Skip the missed events and tell the client how many it lost.
+    #[default]
+    DropLagged,
+    /// End the client's stream.
+    Disconnect,
+}
+
+impl std::str::FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "drop_lagged" | "drop" => Ok(SlowConsumerPolicy::DropLagged),
+            "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+            other => Err(format!("Unknown slow consumer policy: {other}")),
+        }
+    }
+}
+
+/// Handling of a blank search term, which would otherwise match every user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptySearch {
+    /// Answer `400 Bad Request`.
+    #[default]
+    Reject,
+    /// Return every user, paginated, as if no term was given.
+    MatchAll,
+}
+
+impl std::str::FromStr for EmptySearch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "reject" | "error" => Ok(EmptySearch::Reject),
+            "match_all" | "all" => Ok(EmptySearch::MatchAll),
+            other => Err(format!("Unknown empty search mode: {other}")),
+        }
+    }
+}
+
+/// How a bulk create reports partial failure. Every mode returns the
+/// per-item report; a fully successful batch is always `200 OK`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BulkFailureStatus {
+    /// `200 OK`, leaving clients to inspect `failed`.
+    #[default]
+    Ok,
+    /// `207 Multi-Status`, with each item carrying its own status.
+    MultiStatus,
+    /// `422 Unprocessable Entity` if any element failed.
+    Unprocessable,
+}
+
+impl std::str::FromStr for BulkFailureStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "ok" | "200" => Ok(BulkFailureStatus::Ok),
+            "multi_status" | "207" => Ok(BulkFailureStatus::MultiStatus),
+            "unprocessable" | "422" => Ok(BulkFailureStatus::Unprocessable),
+            other => Err(format!("Unknown bulk failure status: {other}")),
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            delete_idempotent: false,
+            lenient_content_type: true,
+            strict_json: false,
+            import_progress_every: 500,
+            admin_enabled: false,
+            admin_token: None,
+            email_encryption_key: None,
+            email_hash_only: false,
+            age_format: AgeFormat::Number,
+            bulk_concurrency: 16,
+            bulk_validate_first: false,
+            bulk_get_concurrency: 16,
+            export_timeout_secs: 30,
+            export_read_retries: 2,
+            max_sync_export_rows: 100_000,
+            csv_bom: false,
+            kafka_brokers: "172.17.0.2:9092".to_string(),
+            kafka_auth: KafkaAuth::default(),
+            kafka_topics: TopicRouting::default(),
+            kafka_acks: Acks::default(),
+            kafka_assignment: PartitionAssignment::default(),
+            kafka_max_in_flight: 1000,
+            dlq_peek_limit: 100,
+            dlq_peek_timeout_ms: 5000,
+            max_bulk_size: 1000,
+            max_users: 0,
+            max_page_size: 100,
+            max_list_response_bytes: 0,
+            max_search_results: 100,
+            min_search_len: 2,
+            empty_search: EmptySearch::default(),
+            search_index: false,
+            shutdown_grace_secs: 30,
+            retry_after_secs: 1,
+            stats_bucket_secs: 60,
+            stats_retention_buckets: 1440,
+            email_domain_policy: EmailDomainPolicy::default(),
+            field_limits: FieldLimits::default(),
+            create_topics: false,
+            publish_changes: false,
+            topic_partitions: 1,
+            topic_replication: 1,
+            pretty_json: false,
+            trailing_slash: TrailingSlash::default(),
+            bulk_failure_status: BulkFailureStatus::default(),
+            cache_max_entries: 10_000,
+            csv_import: CsvImportOptions::default(),
+            route_rate_limits: HashMap::new(),
+            edit_lock_ttl_secs: 60,
+            email_change_ttl_secs: 900,
+            download_token_ttl_secs: 3600,
+            enforce_edit_locks: false,
+            maintenance_interval_secs: 0,
+            expiry_sweep_interval_secs: 60,
+            expiry_sweep_batch_size: 500,
+            max_concurrent_csv_jobs: 1,
+            event_dedup_window_secs: 3600,
+            max_jobs_per_api_key: 0,
+            similar_name_threshold: 2,
+            similar_limit: 20,
+            allow_client_ids: false,
+            slow_request_ms: 1000,
+            max_sse_subscribers: 0,
+            slow_consumer_policy: SlowConsumerPolicy::default(),
+            feature_flags: FeatureFlags::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        Self {
+            delete_idempotent: env_flag("DELETE_IDEMPOTENT", defaults.delete_idempotent),
+            lenient_content_type: env_flag("LENIENT_CONTENT_TYPE", defaults.lenient_content_type),
+            strict_json: env_flag("STRICT_JSON", defaults.strict_json),
+            import_progress_every: env_parse(
+                "IMPORT_PROGRESS_EVERY",
+                defaults.import_progress_every,
+            ),
+            admin_enabled: env_flag("ADMIN_ENABLED", defaults.admin_enabled),
+            admin_token: env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty())
+                .or(defaults.admin_token),
+            email_encryption_key: env::var("EMAIL_ENCRYPTION_KEY")
+                .ok()
+                .or(defaults.email_encryption_key),
+            email_hash_only: env_flag("EMAIL_HASH_ONLY", defaults.email_hash_only),
+            age_format: env_parse("AGE_FORMAT", defaults.age_format),
+            bulk_concurrency: env_parse("BULK_CONCURRENCY", defaults.bulk_concurrency),
+            bulk_validate_first: env_flag("BULK_VALIDATE_FIRST", defaults.bulk_validate_first),
+            bulk_get_concurrency: env_parse("BULK_GET_CONCURRENCY", defaults.bulk_get_concurrency),
+            export_timeout_secs: env_parse("EXPORT_TIMEOUT_SECS", defaults.export_timeout_secs),
+            export_read_retries: env_parse("EXPORT_READ_RETRIES", defaults.export_read_retries),
+            max_sync_export_rows: env_parse("MAX_SYNC_EXPORT_ROWS", defaults.max_sync_export_rows),
+            csv_bom: env_flag("CSV_BOM", defaults.csv_bom),
+            kafka_brokers: env::var("KAFKA_BROKERS").unwrap_or(defaults.kafka_brokers),
+            kafka_auth: kafka_auth_from_env(defaults.kafka_auth),
+            kafka_topics: topic_routing_from_env(defaults.kafka_topics),
+            kafka_acks: env_parse("KAFKA_ACKS", defaults.kafka_acks),
+            kafka_assignment: env_parse("KAFKA_PARTITIONS", defaults.kafka_assignment),
+            kafka_max_in_flight: env_parse("KAFKA_MAX_IN_FLIGHT", defaults.kafka_max_in_flight),
+            dlq_peek_limit: env_parse("DLQ_PEEK_LIMIT", defaults.dlq_peek_limit),
+            dlq_peek_timeout_ms: env_parse("DLQ_PEEK_TIMEOUT_MS", defaults.dlq_peek_timeout_ms),
+            max_bulk_size: env_parse("MAX_BULK_SIZE", defaults.max_bulk_size),
+            max_users: env_parse("MAX_USERS", defaults.max_users),
+            max_page_size: env_parse("MAX_PAGE_SIZE", defaults.max_page_size),
+            max_list_response_bytes: env_parse(
+                "MAX_LIST_RESPONSE_BYTES",
+                defaults.max_list_response_bytes,
+            ),
+            max_search_results: env_parse("MAX_SEARCH_RESULTS", defaults.max_search_results),
+            min_search_len: env_parse("MIN_SEARCH_LEN", defaults.min_search_len),
+            empty_search: env_parse("EMPTY_SEARCH", defaults.empty_search),
+            search_index: env_flag("SEARCH_INDEX", defaults.search_index),
+            shutdown_grace_secs: env_parse("SHUTDOWN_GRACE_SECS", defaults.shutdown_grace_secs),
+            retry_after_secs: env_parse("RETRY_AFTER_SECS", defaults.retry_after_secs),
+            stats_bucket_secs: env_parse("STATS_BUCKET_SECS", defaults.stats_bucket_secs),
+            stats_retention_buckets: env_parse(
+                "STATS_RETENTION_BUCKETS",
+                defaults.stats_retention_buckets,
+            ),
+            email_domain_policy: EmailDomainPolicy::new(
+                env_list("ALLOWED_EMAIL_DOMAINS"),
+                env_list("BLOCKED_EMAIL_DOMAINS"),
+            ),
+            field_limits: FieldLimits {
+                max_name_len: env_parse("MAX_NAME_LEN", defaults.field_limits.max_name_len),
+                max_email_len: env_parse("MAX_EMAIL_LEN", defaults.field_limits.max_email_len),
+            },
+            create_topics: env_flag("CREATE_TOPICS", defaults.create_topics),
+            publish_changes: env_flag("PUBLISH_CHANGES", defaults.publish_changes),
+            topic_partitions: env_parse("TOPIC_PARTITIONS", defaults.topic_partitions),
+            topic_replication: env_parse("TOPIC_REPLICATION", defaults.topic_replication),
+            pretty_json: env_flag("PRETTY_JSON", defaults.pretty_json),
+            trailing_slash: env_parse("TRAILING_SLASH", defaults.trailing_slash),
+            bulk_failure_status: env_parse("BULK_FAILURE_STATUS", defaults.bulk_failure_status),
+            cache_max_entries: env_parse("CACHE_MAX_ENTRIES", defaults.cache_max_entries),
+            csv_import: CsvImportOptions {
+                allow_extra_columns: env_flag(
+                    "CSV_ALLOW_EXTRA_COLUMNS",
+                    defaults.csv_import.allow_extra_columns,
+                ),
+                encoding: env::var("CSV_ENCODING")
+                    .ok()
+                    .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+                    .or(defaults.csv_import.encoding),
+                default_age: env::var("CSV_DEFAULT_AGE")
+                    .ok()
+                    .and_then(|age| age.trim().parse().ok())
+                    .or(defaults.csv_import.default_age),
+                max_columns: env_parse("CSV_MAX_COLUMNS", defaults.csv_import.max_columns),
+                max_rows: env_parse("CSV_MAX_ROWS", defaults.csv_import.max_rows),
+                ..defaults.csv_import
+            },
+            route_rate_limits: env_map("ROUTE_RATE_LIMITS").unwrap_or(defaults.route_rate_limits),
+            edit_lock_ttl_secs: env_parse("EDIT_LOCK_TTL_SECS", defaults.edit_lock_ttl_secs),
+            email_change_ttl_secs: env_parse(
+                "EMAIL_CHANGE_TTL_SECS",
+                defaults.email_change_ttl_secs,
+            ),
+            download_token_ttl_secs: env_parse(
+                "DOWNLOAD_TOKEN_TTL_SECS",
+                defaults.download_token_ttl_secs,
+            ),
+            enforce_edit_locks: env_flag("ENFORCE_EDIT_LOCKS", defaults.enforce_edit_locks),
+            maintenance_interval_secs: env_parse(
+                "MAINTENANCE_INTERVAL_SECS",
+                defaults.maintenance_interval_secs,
+            ),
+            expiry_sweep_interval_secs: env_parse(
+                "EXPIRY_SWEEP_INTERVAL_SECS",
+                defaults.expiry_sweep_interval_secs,
+            ),
+            expiry_sweep_batch_size: env_parse(
+                "EXPIRY_SWEEP_BATCH_SIZE",
+                defaults.expiry_sweep_batch_size,
+            ),
+            max_concurrent_csv_jobs: env_parse(
+                "MAX_CONCURRENT_CSV_JOBS",
+                defaults.max_concurrent_csv_jobs,
+            ),
+            event_dedup_window_secs: env_parse(
+                "EVENT_DEDUP_WINDOW_SECS",
+                defaults.event_dedup_window_secs,
+            ),
+            max_jobs_per_api_key: env_parse("MAX_JOBS_PER_API_KEY", defaults.max_jobs_per_api_key),
+            similar_name_threshold: env_parse(
+                "SIMILAR_NAME_THRESHOLD",
+                defaults.similar_name_threshold,
+            ),
+            similar_limit: env_parse("SIMILAR_LIMIT", defaults.similar_limit),
+            allow_client_ids: env_flag("ALLOW_CLIENT_IDS", defaults.allow_client_ids),
+            slow_request_ms: env_parse("SLOW_REQUEST_MS", defaults.slow_request_ms),
+            max_sse_subscribers: env_parse("MAX_SSE_SUBSCRIBERS", defaults.max_sse_subscribers),
+            slow_consumer_policy: env_parse("SLOW_CONSUMER_POLICY", defaults.slow_consumer_policy),
+            feature_flags: FeatureFlags::from_env(),
+        }
+    }
+
+    /// Rejects settings that are fine on their own but unsafe together, so
+    /// the server can refuse to start instead of running exposed.
+    pub fn check(&self) -> Result<(), String> {
+        if self.admin_enabled && self.admin_token.is_none() {
+            return Err("ADMIN_ENABLED is set but ADMIN_TOKEN is not".to_string());
+        }
+        Ok(())
+    }
+
+    /// Largest `page_size` `/users/search` serves.
+    pub fn max_search_page_size(&self) -> i32 {
+        let max_results = i32::try_from(self.max_search_results).unwrap_or(i32::MAX);
+        self.max_page_size.min(max_results).max(1)
+    }
+
+    /// One `key=value` line with the settings most worth checking on boot.
+    /// Secrets are replaced by `***`, or `-` when unset.
+    pub fn summary(&self) -> String {
+        let secret = |value: &Option<String>| if value.is_some() { "***" } else { "-" };
+        let disabled = self.feature_flags.disabled();
+        [
+            format!("kafka_brokers={}", self.kafka_brokers),
+            format!(
+                "kafka_topics=jobs:{},cdc:{},dlq:{}",
+                self.kafka_topics.jobs, self.kafka_topics.cdc, self.kafka_topics.dlq
+            ),
+            format!("kafka_acks={}", self.kafka_acks.as_str()),
+            format!(
+                "kafka_security_protocol={}",
+                self.kafka_auth.security_protocol.as_deref().unwrap_or("-")
+            ),
+            format!(
+                "kafka_sasl_mechanism={}",
+                self.kafka_auth.sasl_mechanism.as_deref().unwrap_or("-")
+            ),
+            format!(
+                "kafka_sasl_username={}",
+                secret(&self.kafka_auth.sasl_username)
+            ),
+            format!(
+                "kafka_sasl_password={}",
+                secret(&self.kafka_auth.sasl_password)
+            ),
+            format!(
+                "kafka_partitions={}",
+                match &self.kafka_assignment {
+                    PartitionAssignment::Subscribe => "subscribe".to_string(),
+                    PartitionAssignment::Partitions(partitions) => partitions
+                        .iter()
+                        .map(|(topic, partition)| format!("{topic}:{partition}"))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                }
+            ),
+            format!("admin_enabled={}", self.admin_enabled),
+            format!("admin_token={}", secret(&self.admin_token)),
+            format!(
+                "email_encryption_key={}",
+                secret(&self.email_encryption_key)
+            ),
+            format!("max_page_size={}", self.max_page_size),
+            format!("max_bulk_size={}", self.max_bulk_size),
+            format!("max_sync_export_rows={}", self.max_sync_export_rows),
+            format!("max_list_response_bytes={}", self.max_list_response_bytes),
+            format!("max_concurrent_csv_jobs={}", self.max_concurrent_csv_jobs),
+            format!("max_jobs_per_api_key={}", self.max_jobs_per_api_key),
+            format!("strict_json={}", self.strict_json),
+            format!("allow_client_ids={}", self.allow_client_ids),
+            format!(
+                "disabled_features={}",
+                if disabled.is_empty() {
+                    "-".to_string()
+                } else {
+                    disabled.join(",")
+                }
+            ),
+        ]
+        .join(" ")
+    }
+}
+
+fn kafka_auth_from_env(defaults: KafkaAuth) -> KafkaAuth {
+    KafkaAuth {
+        security_protocol: env::var("KAFKA_SECURITY_PROTOCOL")
+            .ok()
+            .or(defaults.security_protocol),
+        sasl_mechanism: env::var("KAFKA_SASL_MECHANISM")
+            .ok()
+            .or(defaults.sasl_mechanism),
+        sasl_username: env::var("KAFKA_SASL_USERNAME")
+            .ok()
+            .or(defaults.sasl_username),
+        sasl_password: env::var("KAFKA_SASL_PASSWORD")
+            .ok()
+            .or(defaults.sasl_password),
+    }
+}
+
+fn topic_routing_from_env(defaults: TopicRouting) -> TopicRouting {
+    let jobs = env::var("KAFKA_JOBS_TOPIC").unwrap_or(defaults.jobs);
+    TopicRouting {
+        cdc: env::var("KAFKA_CDC_TOPIC").unwrap_or_else(|_| jobs.clone()),
+        dlq: env::var("KAFKA_DLQ_TOPIC").unwrap_or_else(|_| jobs.clone()),
+        jobs,
+    }
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses `key=value` pairs from a comma-separated list, skipping entries
+/// whose value does not parse. `None` when the variable is unset.
+fn env_map<T: std::str::FromStr>(key: &str) -> Option<HashMap<String, T>> {
+    env::var(key).ok()?;
+    Some(
+        env_list(key)
+            .iter()
+            .filter_map(|entry| {
+                let (name, value) = entry.rsplit_once('=')?;
+                Some((name.trim().to_string(), value.trim().parse().ok()?))
+            })
+            .collect(),
+    )
+}
+
+fn env_flag(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(value) => matches!(
+            value.trim().to_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        ),
+        Err(_) => default,
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_redacts_secrets() {
+        let config = AppConfig {
+            admin_token: Some("admin-token-value".to_string()),
+            email_encryption_key: Some("00112233445566778899aabbccddeeff".to_string()),
+            kafka_auth: KafkaAuth {
+                security_protocol: Some("SASL_SSL".to_string()),
+                sasl_mechanism: Some("PLAIN".to_string()),
+                sasl_username: Some("API-KEY-1234".to_string()),
+                sasl_password: Some("api-secret-5678".to_string()),
+            },
+            ..AppConfig::default()
+        };
+
+        let summary = config.summary();
+        for secret in [
+            "admin-token-value",
+            "00112233445566778899aabbccddeeff",
+            "API-KEY-1234",
+            "api-secret-5678",
+        ] {
+            assert!(!summary.contains(secret), "{secret} leaked: {summary}");
+        }
+        assert!(summary.contains("kafka_sasl_username=***"));
+        assert!(summary.contains("kafka_sasl_password=***"));
+        assert!(summary.contains("kafka_security_protocol=SASL_SSL"));
+        assert!(!format!("{:?}", config.kafka_auth).contains("api-secret-5678"));
+    }
+
+    #[test]
+    fn admin_endpoints_need_a_token() {
+        let open = AppConfig {
+            admin_enabled: true,
+            ..AppConfig::default()
+        };
+        assert!(open.check().is_err());
+
+        let guarded = AppConfig {
+            admin_token: Some("secret".to_string()),
+            ..open
+        };
+        assert!(guarded.check().is_ok());
+        assert!(AppConfig::default().check().is_ok());
+    }
+
+    #[test]
+    fn summary_marks_unset_secrets() {
+        let summary = AppConfig::default().summary();
+        assert!(summary.contains("kafka_sasl_username=-"));
+        assert!(summary.contains("kafka_sasl_password=-"));
+    }
+}
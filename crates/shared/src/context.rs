@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use crate::{
+    abstract_trait::UserRepositoryTrait,
+    clock::{Clock, SystemClock},
+    config::AppConfig,
+    database::SharedState,
+    encrypted_email::{EmailCipher, EncryptedEmailRepository},
+    kafka::producer::KafkaEventProducer,
+    repository::InMemoryUserRepository,
+    service::UserServiceImpl,
+};
+
+/// Everything a server or worker process needs, assembled in one place.
+#[derive(Debug, Clone)]
+pub struct AppContext {
+    pub config: AppConfig,
+    pub service: SharedState,
+}
+
+enum ProducerSource {
+    FromConfig,
+    Given(Option<Arc<KafkaEventProducer>>),
+}
+
+/// Wires repository, Kafka producer and config into a [`UserServiceImpl`].
+/// Anything not set explicitly falls back to the in-memory repository and a
+/// producer built from the config's broker settings.
+pub struct ServiceBuilder {
+    config: AppConfig,
+    repo: Option<Arc<dyn UserRepositoryTrait>>,
+    producer: ProducerSource,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl ServiceBuilder {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            config,
+            repo: None,
+            producer: ProducerSource::FromConfig,
+            clock: None,
+        }
+    }
+
+    pub fn repository(mut self, repo: Arc<dyn UserRepositoryTrait>) -> Self {
+        self.repo = Some(repo);
+        self
+    }
+
+    pub fn kafka_producer(mut self, producer: Option<Arc<KafkaEventProducer>>) -> Self {
+        self.producer = ProducerSource::Given(producer);
+        self
+    }
+
+    /// Builds the service with no producer; Kafka-backed routes report the
+    /// feature as unavailable.
+    pub fn without_kafka(self) -> Self {
+        self.kafka_producer(None)
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn build(self) -> AppContext {
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let mut repo = self.repo.unwrap_or_else(|| {
+            Arc::new(
+                InMemoryUserRepository::with_clock(clock.clone())
+                    .with_name_index(self.config.search_index),
+            )
+        });
+        // Falling back to plaintext on a bad key would quietly store the
+        // data the key was meant to protect, so refuse to start instead.
+        if let Some(key) = &self.config.email_encryption_key {
+            let mut cipher = EmailCipher::from_hex_key(key).unwrap_or_else(|e| panic!("{e}"));
+            if self.config.email_hash_only {
+                cipher = cipher.hash_only();
+            }
+            repo = Arc::new(EncryptedEmailRepository::new(repo, cipher));
+        }
+        let producer = match self.producer {
+            ProducerSource::FromConfig => Some(Arc::new(
+                KafkaEventProducer::with_acks(
+                    &self.config.kafka_brokers,
+                    self.config.kafka_topics.clone(),
+                    self.config.kafka_acks,
+                    &self.config.kafka_auth,
+                )
+                .with_max_in_flight(self.config.kafka_max_in_flight),
+            )),
+            ProducerSource::Given(producer) => producer,
+        };
+
+        let service = UserServiceImpl::new(repo, producer)
+            .with_config(self.config.clone())
+            .with_clock(clock);
+
+        AppContext {
+            config: self.config,
+            service: Arc::new(service),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        abstract_trait::UserServiceTrait, domain::CreateUserRequest,
+        repository::InMemoryUserRepository,
+    };
+
+    #[tokio::test]
+    async fn builds_on_the_given_repository_without_kafka() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        let config = AppConfig {
+            max_bulk_size: 7,
+            ..AppConfig::default()
+        };
+
+        let context = ServiceBuilder::new(config)
+            .repository(repo.clone())
+            .without_kafka()
+            .build();
+
+        assert_eq!(context.config.max_bulk_size, 7);
+        assert_eq!(context.service.config.max_bulk_size, 7);
+        assert!(context.service.kafka_producer.is_none());
+
+        let request = CreateUserRequest {
+            id: None,
+            name: "Ann".to_string(),
+            email: "ann@example.com".to_string(),
+            age: 30,
+            expires_at: None,
+        };
+        context.service.create_user(&request).await.unwrap();
+        let stored = repo.find_by_email("ann@example.com").await.unwrap();
+        assert_eq!(stored.unwrap().name, "Ann");
+    }
+}
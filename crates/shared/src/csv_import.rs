@@ -0,0 +1,538 @@
+use std::{borrow::Cow, collections::HashSet, io::Read};
+
+use csv::{Reader, StringRecord};
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use serde::Serialize;
+
+use crate::{
+    domain::CreateUserRequest,
+    errors::AppError,
+    validation::{EmailDomainPolicy, FieldError, age_from_wire, validate_age, validate_id},
+};
+
+const ID_COLUMN: usize = 0;
+const NAME_COLUMN: usize = 1;
+const EMAIL_COLUMN: usize = 2;
+const AGE_COLUMN: usize = 3;
+const EXPECTED_HEADERS: [&str; 6] = ["id", "name", "email", "age", "created_at", "updated_at"];
+
+#[derive(Debug, Clone, Default)]
+pub struct CsvImportOptions {
+    /// Accept unknown columns after the expected ones and ignore them, e.g.
+    /// for exports from other tools that append their own fields.
+    pub allow_extra_columns: bool,
+    /// Use the `id` column for imported users instead of generating new
+    /// ids. Empty ids are still generated. The service sets this from
+    /// `allow_client_ids`.
+    pub keep_ids: bool,
+    /// Character encoding of uploaded files. When unset, UTF-8 is assumed
+    /// if the file is valid UTF-8 and `windows-1252` (a superset of
+    /// Latin-1) otherwise. A byte order mark always wins.
+    pub encoding: Option<&'static Encoding>,
+    /// Age given to rows whose `age` cell is blank. When unset such rows
+    /// are rejected.
+    pub default_age: Option<u8>,
+    /// Most columns a header or data row may have. `0` means unlimited.
+    pub max_columns: usize,
+    /// Most data rows a file may have, not counting the header. `0` means
+    /// unlimited.
+    pub max_rows: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportRowError {
+    pub line: u64,
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ImportReport {
+    pub valid: usize,
+    pub invalid: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+/// Parses and validates an import CSV without touching the repository.
+pub fn parse_csv_requests<R: Read>(
+    reader: R,
+    options: &CsvImportOptions,
+) -> Result<Vec<CreateUserRequest>, AppError> {
+    let mut rdr = reader_for(reader);
+    check_headers(&mut rdr, options)?;
+
+    let mut requests = Vec::new();
+    for (index, result) in rdr.into_records().enumerate() {
+        let record = result.map_err(|e| AppError::CsvError(e.to_string()))?;
+        if let Some(message) = row_limit_exceeded(index, options) {
+            return Err(AppError::CsvError(format!(
+                "Line {}: {message}",
+                line_of(&record)
+            )));
+        }
+
+        match parse_row(&record, options) {
+            Ok(request) => requests.push(request),
+            Err(mut errors) => {
+                let first = errors.remove(0);
+                return Err(if first.field == "row" {
+                    AppError::CsvError(format!("Line {}: {}", line_of(&record), first.message))
+                } else {
+                    AppError::ValidationError(first.message)
+                });
+            }
+        }
+    }
+
+    Ok(requests)
+}
+
+/// Validates every row and reports all problems instead of stopping at the
+/// first one. Nothing is inserted.
+pub fn validate_csv<R: Read>(
+    reader: R,
+    options: &CsvImportOptions,
+    policy: &EmailDomainPolicy,
+) -> ImportReport {
+    let mut report = ImportReport::default();
+    let mut rdr = reader_for(reader);
+
+    if let Err(e) = check_headers(&mut rdr, options) {
+        report.errors.push(ImportRowError {
+            line: 1,
+            field: "header".to_string(),
+            message: e.to_string(),
+        });
+        return report;
+    }
+
+    for (index, result) in rdr.into_records().enumerate() {
+        if let Some(message) = row_limit_exceeded(index, options) {
+            let line = match &result {
+                Ok(record) => line_of(record),
+                Err(e) => e.position().map(|p| p.line()).unwrap_or_default(),
+            };
+            report.errors.push(ImportRowError {
+                line,
+                field: "row".to_string(),
+                message,
+            });
+            report.invalid += 1;
+            break;
+        }
+
+        let (line, errors) = match result {
+            Ok(record) => {
+                let line = line_of(&record);
+                let errors = match parse_row(&record, options) {
+                    Ok(request) => match policy.check(&request.email) {
+                        Ok(()) => Vec::new(),
+                        Err(message) => vec![FieldError::new("email", message)],
+                    },
+                    Err(errors) => errors,
+                };
+                (line, errors)
+            }
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                (line, vec![FieldError::new("row", e.to_string())])
+            }
+        };
+
+        if errors.is_empty() {
+            report.valid += 1;
+        } else {
+            report.invalid += 1;
+            report
+                .errors
+                .extend(errors.into_iter().map(|e| ImportRowError {
+                    line,
+                    field: e.field,
+                    message: e.message,
+                }));
+        }
+    }
+
+    report
+}
+
+/// Transcodes an import file to UTF-8 so it can be parsed. Bytes that are
+/// not valid in the chosen encoding are an error rather than being replaced.
+pub fn decode_csv<'a>(
+    bytes: &'a [u8],
+    options: &CsvImportOptions,
+) -> Result<Cow<'a, str>, AppError> {
+    let (encoding, body) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_len)) => (encoding, &bytes[bom_len..]),
+        None => {
+            let guessed = match std::str::from_utf8(bytes) {
+                Ok(_) => UTF_8,
+                Err(_) => WINDOWS_1252,
+            };
+            (options.encoding.unwrap_or(guessed), bytes)
+        }
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(body)
+        .ok_or_else(|| {
+            AppError::CsvError(format!(
+                "File is not valid {}; set CSV_ENCODING to its encoding",
+                encoding.name()
+            ))
+        })
+}
+
+fn reader_for<R: Read>(reader: R) -> Reader<R> {
+    // Flexible so that short rows reach the bounds check in `parse_row` and
+    // get a line-numbered error instead of a generic length mismatch.
+    csv::ReaderBuilder::new().flexible(true).from_reader(reader)
+}
+
+fn check_headers<R: Read>(rdr: &mut Reader<R>, options: &CsvImportOptions) -> Result<(), AppError> {
+    let headers = rdr
+        .headers()
+        .map_err(|e| AppError::CsvError(e.to_string()))?;
+
+    if options.max_columns > 0 && headers.len() > options.max_columns {
+        return Err(AppError::CsvError(format!(
+            "CSV header has {} columns, the limit is {}",
+            headers.len(),
+            options.max_columns
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = headers.iter().find(|name| !seen.insert(name.trim())) {
+        return Err(AppError::CsvError(format!(
+            "Duplicate column: {}",
+            duplicate.trim()
+        )));
+    }
+
+    let expected_prefix = headers.len() >= EXPECTED_HEADERS.len()
+        && headers.iter().zip(EXPECTED_HEADERS).all(|(a, b)| a == b);
+    if !expected_prefix {
+        return Err(AppError::CsvError(format!(
+            "Invalid CSV header. Expected: {}",
+            EXPECTED_HEADERS.join(",")
+        )));
+    }
+
+    if !options.allow_extra_columns
+        && let Some(extra) = headers.iter().nth(EXPECTED_HEADERS.len())
+    {
+        return Err(AppError::CsvError(format!("Unexpected column: {extra}")));
+    }
+    Ok(())
+}
+
+/// The error for the data row at `index` (0-based) when it is past
+/// `max_rows`.
+fn row_limit_exceeded(index: usize, options: &CsvImportOptions) -> Option<String> {
+    (options.max_rows > 0 && index >= options.max_rows)
+        .then(|| format!("CSV has more than {} rows", options.max_rows))
+}
+
+fn line_of(record: &StringRecord) -> u64 {
+    record.position().map(|p| p.line()).unwrap_or_default()
+}
+
+/// Parses a single data row, collecting every field problem.
+fn parse_row(
+    record: &StringRecord,
+    options: &CsvImportOptions,
+) -> Result<CreateUserRequest, Vec<FieldError>> {
+    if options.max_columns > 0 && record.len() > options.max_columns {
+        return Err(vec![FieldError::new(
+            "row",
+            format!(
+                "CSV row has {} fields, the limit is {}",
+                record.len(),
+                options.max_columns
+            ),
+        )]);
+    }
+    if record.len() <= AGE_COLUMN {
+        return Err(vec![FieldError::new(
+            "row",
+            format!(
+                "CSV row has {} fields, need at least {} (id, name, email, age)",
+                record.len(),
+                AGE_COLUMN + 1
+            ),
+        )]);
+    }
+
+    let id = Some(record[ID_COLUMN].trim())
+        .filter(|id| options.keep_ids && !id.is_empty())
+        .map(str::to_string);
+    let name = record[NAME_COLUMN].trim();
+    let email = record[EMAIL_COLUMN].trim();
+    let age_str = record[AGE_COLUMN].trim();
+    let mut errors = Vec::new();
+
+    if let Some(Err(message)) = id.as_deref().map(validate_id) {
+        errors.push(FieldError::new("id", message));
+    }
+    if name.is_empty() {
+        errors.push(FieldError::new("name", "Name is empty"));
+    }
+    if email.is_empty() {
+        errors.push(FieldError::new("email", "Email is empty"));
+    } else if !email.contains('@') {
+        errors.push(FieldError::new("email", "Invalid email format"));
+    }
+
+    let age = if age_str.is_empty() {
+        match options.default_age.map(|age| (age, validate_age(age))) {
+            Some((age, Ok(()))) => Some(age),
+            Some((_, Err(message))) => {
+                errors.push(FieldError::new("age", format!("Default age: {message}")));
+                None
+            }
+            None => {
+                errors.push(FieldError::new("age", "Age is empty"));
+                None
+            }
+        }
+    } else {
+        match age_str.parse::<i64>() {
+            Ok(age) => match age_from_wire(age) {
+                Ok(age) => Some(age),
+                Err(message) => {
+                    errors.push(FieldError::new("age", message));
+                    None
+                }
+            },
+            Err(_) => {
+                errors.push(FieldError::new("age", "Invalid age: not a number"));
+                None
+            }
+        }
+    };
+
+    match age {
+        Some(age) if errors.is_empty() => Ok(CreateUserRequest {
+            id,
+            name: name.to_string(),
+            email: email.to_lowercase(),
+            age,
+            expires_at: None,
+        }),
+        _ => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Inputs from `fuzz/corpus/import_csv` that used to panic.
+    const SHORT_ROW: &[u8] = b"id,name,email,age,created_at,updated_at\n1,Ann,ann@example.com\n";
+    const MISSING_COLUMNS: &[u8] = b"id,name,email,age\n1,Ann,ann@example.com,30\n";
+    const EMBEDDED_NEWLINE: &[u8] =
+        b"id,name,email,age,created_at,updated_at\n1,\"Ann\nSmith\",ann@example.com,30,x,y\n";
+
+    #[test]
+    fn short_row_is_a_line_numbered_error() {
+        let err = parse_csv_requests(SHORT_ROW, &CsvImportOptions::default()).unwrap_err();
+        let AppError::CsvError(message) = err else {
+            panic!("expected a CSV error, got {err:?}");
+        };
+        assert!(message.starts_with("Line 2:"), "{message}");
+        assert!(message.contains("has 3 fields"), "{message}");
+
+        let report = validate_csv(
+            SHORT_ROW,
+            &CsvImportOptions::default(),
+            &EmailDomainPolicy::default(),
+        );
+        assert_eq!(report.invalid, 1);
+        assert_eq!(report.errors[0].field, "row");
+    }
+
+    #[test]
+    fn missing_columns_fail_the_header_check() {
+        let err = parse_csv_requests(MISSING_COLUMNS, &CsvImportOptions::default()).unwrap_err();
+        assert!(matches!(err, AppError::CsvError(m) if m.starts_with("Invalid CSV header")));
+    }
+
+    #[test]
+    fn embedded_newline_stays_in_the_field() {
+        let requests = parse_csv_requests(EMBEDDED_NEWLINE, &CsvImportOptions::default()).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].name, "Ann\nSmith");
+        assert_eq!(requests[0].email, "ann@example.com");
+    }
+
+    #[test]
+    fn four_fields_are_enough_for_a_row() {
+        let csv = b"id,name,email,age,created_at,updated_at\n,Ann,ann@example.com,30\n";
+
+        let requests = parse_csv_requests(&csv[..], &CsvImportOptions::default()).unwrap();
+
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].age, 30);
+    }
+
+    #[test]
+    fn out_of_range_age_is_a_field_error() {
+        let csv = b"id,name,email,age,created_at,updated_at\n,Ann,ann@example.com,300,,\n";
+
+        let report = validate_csv(
+            &csv[..],
+            &CsvImportOptions::default(),
+            &EmailDomainPolicy::default(),
+        );
+
+        assert_eq!(report.invalid, 1);
+        assert_eq!(report.errors[0].field, "age");
+        assert_eq!(report.errors[0].message, "Age must be between 0 and 150");
+    }
+
+    #[test]
+    fn blank_age_takes_the_default_when_one_is_set() {
+        let csv = b"id,name,email,age,created_at,updated_at\n,Ann,ann@example.com,,,\n";
+
+        let report = validate_csv(
+            &csv[..],
+            &CsvImportOptions::default(),
+            &EmailDomainPolicy::default(),
+        );
+        assert_eq!(report.invalid, 1);
+        assert_eq!(report.errors[0].message, "Age is empty");
+
+        let options = CsvImportOptions {
+            default_age: Some(18),
+            ..CsvImportOptions::default()
+        };
+        let requests = parse_csv_requests(&csv[..], &options).unwrap();
+        assert_eq!(requests[0].age, 18);
+
+        let options = CsvImportOptions {
+            default_age: Some(200),
+            ..CsvImportOptions::default()
+        };
+        let report = validate_csv(&csv[..], &options, &EmailDomainPolicy::default());
+        assert_eq!(
+            report.errors[0].message,
+            "Default age: Age must be between 0 and 150"
+        );
+    }
+
+    #[test]
+    fn files_past_max_rows_are_rejected() {
+        let csv = b"id,name,email,age,created_at,updated_at\n\
+            ,Ann,ann@example.com,30,,\n\
+            ,Bob,bob@example.com,30,,\n\
+            ,Cat,cat@example.com,30,,\n";
+        let options = CsvImportOptions {
+            max_rows: 2,
+            ..CsvImportOptions::default()
+        };
+
+        let err = parse_csv_requests(&csv[..], &options).unwrap_err();
+        assert!(
+            matches!(&err, AppError::CsvError(m) if m == "Line 4: CSV has more than 2 rows"),
+            "{err:?}"
+        );
+
+        let report = validate_csv(&csv[..], &options, &EmailDomainPolicy::default());
+        assert_eq!((report.valid, report.invalid), (2, 1));
+        assert_eq!(report.errors[0].line, 4);
+
+        let options = CsvImportOptions {
+            max_rows: 3,
+            ..CsvImportOptions::default()
+        };
+        assert_eq!(parse_csv_requests(&csv[..], &options).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn wide_headers_and_rows_are_rejected() {
+        let options = CsvImportOptions {
+            max_columns: 6,
+            allow_extra_columns: true,
+            ..CsvImportOptions::default()
+        };
+
+        let wide_header =
+            b"id,name,email,age,created_at,updated_at,team\n,Ann,ann@example.com,30,,,x\n";
+        let err = parse_csv_requests(&wide_header[..], &options).unwrap_err();
+        assert!(matches!(err, AppError::CsvError(m) if m.contains("header has 7 columns")));
+
+        let wide_row = b"id,name,email,age,created_at,updated_at\n,Ann,ann@example.com,30,,,x,y\n";
+        let report = validate_csv(&wide_row[..], &options, &EmailDomainPolicy::default());
+        assert_eq!(report.invalid, 1);
+        assert_eq!(
+            report.errors[0].message,
+            "CSV row has 8 fields, the limit is 6"
+        );
+    }
+
+    #[test]
+    fn duplicate_header_is_rejected() {
+        let csv = b"id,name,email,age,created_at,updated_at,email\n,Ann,ann@example.com,30,,,x\n";
+        let options = CsvImportOptions {
+            allow_extra_columns: true,
+            ..CsvImportOptions::default()
+        };
+
+        let err = parse_csv_requests(&csv[..], &options).unwrap_err();
+
+        assert!(matches!(err, AppError::CsvError(m) if m == "Duplicate column: email"));
+    }
+
+    #[test]
+    fn extra_columns_are_ignored_only_when_allowed() {
+        let csv = b"id,name,email,age,created_at,updated_at,team\n,Ann,ann@example.com,30,,,blue\n";
+
+        let err = parse_csv_requests(&csv[..], &CsvImportOptions::default()).unwrap_err();
+        assert!(matches!(err, AppError::CsvError(m) if m == "Unexpected column: team"));
+
+        let options = CsvImportOptions {
+            allow_extra_columns: true,
+            ..CsvImportOptions::default()
+        };
+        let requests = parse_csv_requests(&csv[..], &options).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].email, "ann@example.com");
+    }
+
+    const LATIN1_CSV: &[u8] =
+        b"id,name,email,age,created_at,updated_at\n,Jos\xE9 Garc\xEDa,jose@example.com,30,,\n,Zo\xEB,zoe@example.com,25,,\n";
+
+    #[test]
+    fn latin1_file_is_transcoded_before_parsing() {
+        let options = CsvImportOptions::default();
+
+        let text = decode_csv(LATIN1_CSV, &options).unwrap();
+        let requests = parse_csv_requests(text.as_bytes(), &options).unwrap();
+
+        let names: Vec<_> = requests.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["José García", "Zoë"]);
+    }
+
+    #[test]
+    fn utf8_and_bom_marked_files_decode_as_they_are() {
+        let options = CsvImportOptions::default();
+        let utf8 = "name\nZoë\n".as_bytes();
+        assert_eq!(decode_csv(utf8, &options).unwrap(), "name\nZoë\n");
+
+        let mut with_bom = b"\xEF\xBB\xBF".to_vec();
+        with_bom.extend_from_slice(utf8);
+        assert_eq!(decode_csv(&with_bom, &options).unwrap(), "name\nZoë\n");
+    }
+
+    #[test]
+    fn bytes_invalid_in_the_configured_encoding_are_a_clear_error() {
+        let options = CsvImportOptions {
+            encoding: Some(UTF_8),
+            ..CsvImportOptions::default()
+        };
+
+        let err = decode_csv(LATIN1_CSV, &options).unwrap_err();
+
+        assert!(matches!(err, AppError::CsvError(m) if m.contains("not valid UTF-8")));
+    }
+}
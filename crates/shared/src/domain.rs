@@ -1,5 +1,10 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::validation::{
+    FieldLimits, age_from_wire, bounded_optional_string, bounded_string, reject_field,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -9,27 +14,218 @@ pub struct User {
     pub age: u8,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Ephemeral users disappear from reads once this passes and are deleted
+    /// by the expiry sweeper.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl User {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Strong entity tag for conditional requests. Every write bumps
+    /// `updated_at`, so it changes whenever the stored user does.
+    pub fn etag(&self) -> String {
+        let nanos = self
+            .updated_at
+            .timestamp_nanos_opt()
+            .unwrap_or_else(|| self.updated_at.timestamp_micros());
+        format!("\"{:x}\"", nanos)
+    }
+
+    /// Matches an `If-Match` value, which is `*` or a comma-separated list
+    /// of tags. Weak tags never match, as required for `If-Match`.
+    pub fn matches_if_match(&self, if_match: &str) -> bool {
+        let etag = self.etag();
+        if_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == etag)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CreateUserRequest {
+    /// Client-supplied UUID or ULID, honored only when `allow_client_ids`
+    /// is enabled. The server generates one otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(deserialize_with = "deserialize_name")]
     pub name: String,
+    #[serde(deserialize_with = "deserialize_email")]
     pub email: String,
+    #[serde(deserialize_with = "deserialize_age")]
     pub age: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailChangeRequest {
+    #[serde(deserialize_with = "deserialize_email")]
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailChangeConfirm {
+    pub token: String,
+}
+
+/// Returned by the first step of an email change. The token is what the
+/// confirmation step expects; the current email stays active until then.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailChangeToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// `None` leaves a field unchanged. Name and email cannot be cleared, so an
+/// empty string for either is rejected during validation.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UpdateUserRequest {
+    #[serde(default, deserialize_with = "deserialize_optional_name")]
     pub name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_email")]
     pub email: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_age")]
     pub age: Option<u8>,
 }
 
+/// Body of `POST /admin/users/merge`: `remove` is deleted and the fields
+/// listed in `copy` are taken over from it by `keep`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeUsersRequest {
+    pub keep: String,
+    pub remove: String,
+    #[serde(default)]
+    pub copy: Vec<MergeField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeField {
+    Name,
+    Email,
+    Age,
+}
+
+impl MergeField {
+    /// The update that copies `fields` from `removed`, skipping empty ones.
+    pub fn update_from(removed: &User, fields: &[MergeField]) -> UpdateUserRequest {
+        let take = |field| fields.contains(&field);
+        UpdateUserRequest {
+            name: Some(removed.name.clone())
+                .filter(|name| take(MergeField::Name) && !name.is_empty()),
+            email: Some(removed.email.clone())
+                .filter(|email| take(MergeField::Email) && !email.is_empty()),
+            age: take(MergeField::Age).then_some(removed.age),
+        }
+    }
+}
+
+fn deserialize_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    bounded_string(deserializer, "name", FieldLimits::current().max_name_len)
+}
+
+fn deserialize_email<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    bounded_string(deserializer, "email", FieldLimits::current().max_email_len)
+}
+
+fn deserialize_optional_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    bounded_optional_string(deserializer, "name", FieldLimits::current().max_name_len)
+}
+
+fn deserialize_optional_email<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    bounded_optional_string(deserializer, "email", FieldLimits::current().max_email_len)
+}
+
+fn deserialize_age<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let age = i64::deserialize(deserializer)?;
+    age_from_wire(age).map_err(|message| reject_field("age", message))
+}
+
+fn deserialize_optional_age<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u8>, D::Error> {
+    Option::<i64>::deserialize(deserializer)?
+        .map(|age| age_from_wire(age).map_err(|message| reject_field("age", message)))
+        .transpose()
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FindAllUserRequest {
+    #[serde(default = "default_page")]
     pub page: i32,
+    #[serde(default = "default_page_size")]
     pub page_size: i32,
     pub search: Option<String>,
+    #[serde(default)]
+    pub search_field: SearchField,
+}
+
+fn default_page() -> i32 {
+    1
+}
+
+fn default_page_size() -> i32 {
+    10
+}
+
+impl FindAllUserRequest {
+    /// Also drops an empty `search`, which would match everyone anyway, so
+    /// the listing skips the per-user string matching.
+    pub fn clamp(&mut self, max_page_size: i32) {
+        self.page = self.page.max(1);
+        self.page_size = self.page_size.clamp(1, max_page_size.max(1));
+        self.search = self.search.take().filter(|q| !q.is_empty());
+    }
+}
+
+/// Query for `GET /users/changes`: users whose `updated_at` falls within
+/// `since..=until`, oldest change first. Either bound may be left open.
+#[derive(Debug, Deserialize)]
+pub struct ChangesQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    #[serde(default = "default_page")]
+    pub page: i32,
+    #[serde(default = "default_page_size")]
+    pub page_size: i32,
+}
+
+impl ChangesQuery {
+    pub fn clamp(&mut self, max_page_size: i32) {
+        self.page = self.page.max(1);
+        self.page_size = self.page_size.clamp(1, max_page_size.max(1));
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchField {
+    Name,
+    Email,
+    #[default]
+    All,
+}
+
+impl SearchField {
+    pub fn matches(&self, user: &User, query: &str) -> bool {
+        match self {
+            SearchField::Name => user.name.to_lowercase().contains(query),
+            SearchField::Email => user.email.to_lowercase().contains(query),
+            SearchField::All => {
+                user.name.to_lowercase().contains(query)
+                    || user.email.to_lowercase().contains(query)
+            }
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -37,10 +233,173 @@ pub struct UserResponse {
     pub id: String,
     pub name: String,
     pub email: String,
-    pub age: u8,
+    pub age: AgeValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// [`User::etag`] of the user this was built from. Sent as the `ETag`
+    /// header, not in the body.
+    #[serde(skip)]
+    pub etag: String,
+}
+
+impl UserResponse {
+    /// Names clients may pick with `?fields=` on `GET /users/{id}`.
+    pub const FIELDS: [&str; 5] = ["id", "name", "email", "age", "expires_at"];
+}
+
+/// `?fields=name,email` projection. Absent means every field.
+#[derive(Debug, Default, Deserialize)]
+pub struct FieldsQuery {
+    pub fields: Option<String>,
+}
+
+impl FieldsQuery {
+    /// The requested field names, checked against `allowed`. `None` when no
+    /// projection was asked for.
+    pub fn selected(&self, allowed: &[&str]) -> Result<Option<Vec<String>>, String> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+        let selected: Vec<String> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .collect();
+        if selected.is_empty() {
+            return Err("fields must name at least one field".to_string());
+        }
+        if let Some(unknown) = selected
+            .iter()
+            .find(|name| !allowed.contains(&name.as_str()))
+        {
+            return Err(format!(
+                "Unknown field: {unknown}. Allowed: {}",
+                allowed.join(", ")
+            ));
+        }
+        Ok(Some(selected))
+    }
+}
+
+/// [`UserResponse`] with the field names a downstream system expects,
+/// served under `/external`.
+#[derive(Serialize)]
+pub struct ExternalUserResponse {
+    pub id: String,
+    pub user_name: String,
+    pub email_address: String,
+    pub age: AgeValue,
+}
+
+impl From<UserResponse> for ExternalUserResponse {
+    fn from(user: UserResponse) -> Self {
+        Self {
+            id: user.id,
+            user_name: user.name,
+            email_address: user.email,
+            age: user.age,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AgeFormat {
+    #[default]
+    Number,
+    String,
 }
 
-#[derive(Debug, Default, Clone)]
+impl std::str::FromStr for AgeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "number" => Ok(AgeFormat::Number),
+            "string" => Ok(AgeFormat::String),
+            other => Err(format!("Unknown age format: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum AgeValue {
+    Number(u8),
+    Text(String),
+}
+
+impl AgeValue {
+    pub fn new(age: u8, format: AgeFormat) -> Self {
+        match format {
+            AgeFormat::Number => AgeValue::Number(age),
+            AgeFormat::String => AgeValue::Text(age.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmailDomainCount {
+    pub domain: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimilarityReason {
+    Name,
+    EmailLocalPart,
+}
+
+#[derive(Serialize)]
+pub struct SimilarUser {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub reason: SimilarityReason,
+    /// Name edit distance; `0` for email local-part matches.
+    pub distance: usize,
+}
+
+/// Outcome of one repository reconciliation pass.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ReconcileReport {
+    pub checked: usize,
+    pub fixed: usize,
+    /// Emails held by more than one user. Reported, not fixed, since picking
+    /// a survivor needs a human.
+    pub duplicate_emails: Vec<String>,
+    /// Keys of users that could not be re-keyed because another user
+    /// already has their id. Both are kept for the same reason.
+    pub id_collisions: Vec<String>,
+}
+
+/// Outcome of one element of a bulk create, by its position in the request.
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkItemResult {
+    pub index: usize,
+    /// The status a single create of this element would have answered with.
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BulkCreateReport {
+    pub created: usize,
+    pub failed: usize,
+    pub items: Vec<BulkItemResult>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BulkUpsertResult {
+    pub created: usize,
+    pub updated: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct ServiceStats {
     pub total_operations: u64,
     pub create_count: u64,
@@ -49,6 +408,14 @@ pub struct ServiceStats {
     pub delete_count: u64,
 }
 
+/// Retries of one operation since startup. A rising `attempted` rate points
+/// at an unhealthy dependency; `exhausted` counts calls that failed anyway.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RetryCounts {
+    pub attempted: u64,
+    pub exhausted: u64,
+}
+
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
@@ -64,13 +431,202 @@ pub struct ApiResponsePagination<T> {
     pub total: i64,
 }
 
+/// One page of search results. `truncated` is set when the `total` matches
+/// exceed `max_search_results`, so some of them cannot be paged to.
+#[derive(Serialize)]
+pub struct ApiResponseSearch<T> {
+    pub success: bool,
+    pub data: T,
+    pub page: i32,
+    pub page_size: i32,
+    pub total: i64,
+    pub truncated: bool,
+}
+
+/// Query for `GET /admin/dlq`.
+#[derive(Debug, Deserialize)]
+pub struct DlqQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeseriesQuery {
+    pub window: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SearchQuery {
     pub q: String,
+    #[serde(default)]
+    pub search_field: SearchField,
+    /// Defaults to the first page.
+    #[serde(default)]
+    pub page: Option<i32>,
+    /// Defaults to `max_search_results`, which also caps an explicit value
+    /// along with `max_page_size`.
+    #[serde(default)]
+    pub page_size: Option<i32>,
+}
+
+impl SearchQuery {
+    pub fn clamp(&mut self, max_page_size: i32) {
+        self.page = self.page.map(|page| page.max(1));
+        self.page_size = self
+            .page_size
+            .map(|size| size.clamp(1, max_page_size.max(1)));
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportFilter {
+    #[serde(default, alias = "updated_since")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default, alias = "updated_until")]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl ExportFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        self.in_window(user.created_at) || self.in_window(user.updated_at)
+    }
+
+    fn in_window(&self, ts: DateTime<Utc>) -> bool {
+        self.since.is_none_or(|since| ts >= since) && self.until.is_none_or(|until| ts <= until)
+    }
+}
+
+/// An export handed off to the Kafka worker instead of being served inline.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportJob {
+    pub job_id: String,
+    /// Where the worker writes the CSV file. Kept off the wire; clients
+    /// fetch the result through `download_url` instead.
+    #[serde(skip)]
+    pub path: String,
+    /// One-time link that starts working once the worker has written the
+    /// file.
+    pub download_url: String,
+}
+
+/// When fields are wrapped in quotes on CSV export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvQuoteStyle {
+    /// Only fields containing the delimiter, a quote or a line break.
+    #[default]
+    Necessary,
+    Always,
+    NonNumeric,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvTerminator {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// Output dialect for CSV exports. The default matches the `csv` crate's
+/// writer defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvDialect {
+    pub quote_style: CsvQuoteStyle,
+    #[serde(deserialize_with = "deserialize_quote")]
+    pub quote: char,
+    pub terminator: CsvTerminator,
+    /// Start the file with a UTF-8 byte order mark so Excel detects the
+    /// encoding. `None` falls back to the `csv_bom` config.
+    pub bom: Option<bool>,
+    /// Comma-separated columns to write, in this order, e.g.
+    /// `email,name,id`. `None` writes [`CsvDialect::COLUMNS`].
+    pub columns: Option<String>,
+}
+
+impl CsvDialect {
+    pub const COLUMNS: [&str; 6] = ["id", "name", "email", "age", "created_at", "updated_at"];
+
+    /// The columns to write, in order, checked against [`Self::COLUMNS`].
+    pub fn columns(&self) -> Result<Vec<String>, String> {
+        let fields = FieldsQuery {
+            fields: self.columns.clone(),
+        };
+        Ok(fields
+            .selected(&Self::COLUMNS)?
+            .unwrap_or_else(|| Self::COLUMNS.map(str::to_owned).to_vec()))
+    }
+}
+
+/// The CSV writer quotes with a single byte, so reject anything else while
+/// parsing rather than failing halfway through a streamed export.
+fn deserialize_quote<'de, D: Deserializer<'de>>(deserializer: D) -> Result<char, D::Error> {
+    let quote = char::deserialize(deserializer)?;
+    if !quote.is_ascii() {
+        return Err(serde::de::Error::custom("quote must be an ASCII character"));
+    }
+    Ok(quote)
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            quote_style: CsvQuoteStyle::default(),
+            quote: '"',
+            terminator: CsvTerminator::default(),
+            bom: None,
+            columns: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ImportProgress {
+    Running {
+        processed: usize,
+        total: usize,
+    },
+    Completed {
+        imported: usize,
+    },
+    Failed {
+        error: String,
+    },
+    /// Sent in place of updates a slow client fell too far behind to get.
+    Lagged {
+        missed: u64,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum KafkaEvent {
-    ImportCsv { path: String },
-    ExportCsv { path: String },
+    ImportCsv {
+        path: String,
+    },
+    ExportCsv {
+        path: String,
+        #[serde(default)]
+        since: Option<DateTime<Utc>>,
+        #[serde(default)]
+        until: Option<DateTime<Utc>>,
+        #[serde(default)]
+        dialect: CsvDialect,
+    },
+    /// A user write, published to the CDC topic when `publish_changes` is
+    /// on. Carries only the id so no personal data leaves the service.
+    UserChanged {
+        op: ChangeOp,
+        user_id: String,
+        at: DateTime<Utc>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Created,
+    Updated,
+    Deleted,
 }
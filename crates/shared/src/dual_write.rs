@@ -0,0 +1,346 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+
+use crate::{
+    abstract_trait::UserRepositoryTrait,
+    domain::{
+        CreateUserRequest, MergeField, ReconcileReport, SearchField, UpdateUserRequest, User,
+    },
+    errors::AppError,
+};
+
+/// Which of the two wrapped backends is authoritative.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DualWritePrimary {
+    #[default]
+    Left,
+    Right,
+}
+
+/// Writes every change to two repositories and reads from the primary one,
+/// for migrating between backends without downtime. A failed primary write
+/// fails the call; a failed or diverging secondary write is only logged, so
+/// the secondary can lag behind until it is caught up and promoted.
+pub struct DualWriteRepository {
+    left: Arc<dyn UserRepositoryTrait>,
+    right: Arc<dyn UserRepositoryTrait>,
+    primary: DualWritePrimary,
+}
+
+impl DualWriteRepository {
+    pub fn new(left: Arc<dyn UserRepositoryTrait>, right: Arc<dyn UserRepositoryTrait>) -> Self {
+        Self {
+            left,
+            right,
+            primary: DualWritePrimary::default(),
+        }
+    }
+
+    /// Switches which backend serves reads and decides write failures.
+    pub fn with_primary(mut self, primary: DualWritePrimary) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    pub fn primary(&self) -> &Arc<dyn UserRepositoryTrait> {
+        match self.primary {
+            DualWritePrimary::Left => &self.left,
+            DualWritePrimary::Right => &self.right,
+        }
+    }
+
+    pub fn secondary(&self) -> &Arc<dyn UserRepositoryTrait> {
+        match self.primary {
+            DualWritePrimary::Left => &self.right,
+            DualWritePrimary::Right => &self.left,
+        }
+    }
+
+    fn mirrored<T>(&self, op: &str, result: Result<T, AppError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!("⚠️ Secondary repository failed on {}: {}", op, e);
+                None
+            }
+        }
+    }
+
+    fn check_divergence(&self, op: &str, primary: &User, secondary: &User) {
+        let same = primary.id == secondary.id
+            && primary.name == secondary.name
+            && primary.email == secondary.email
+            && primary.age == secondary.age;
+        if !same {
+            eprintln!(
+                "⚠️ Repositories diverged on {} for user {}: primary {:?}, secondary {:?}",
+                op, primary.id, primary, secondary
+            );
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UserRepositoryTrait for DualWriteRepository {
+    async fn find_all(
+        &self,
+        page: i32,
+        page_size: i32,
+        search: Option<String>,
+        search_field: SearchField,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        self.primary()
+            .find_all(page, page_size, search, search_field)
+            .await
+    }
+
+    async fn find_updated_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        self.primary()
+            .find_updated_between(since, until, page, page_size)
+            .await
+    }
+
+    async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError> {
+        self.primary().find_by_email_exists(email).await
+    }
+
+    async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError> {
+        let user = self.primary().create_user(input).await?;
+
+        // Reuse the primary's id so both backends agree on the key.
+        let mirrored = CreateUserRequest {
+            id: Some(user.id.clone()),
+            ..input.clone()
+        };
+        let result = self.secondary().create_user(&mirrored).await;
+        if let Some(copy) = self.mirrored("create_user", result) {
+            self.check_divergence("create_user", &user, &copy);
+        }
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+        self.primary().find_by_email(email).await
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError> {
+        self.primary().find_by_id(id).await
+    }
+
+    async fn find_by_ids(&self, ids: &[String], concurrency: usize) -> Result<Vec<User>, AppError> {
+        self.primary().find_by_ids(ids, concurrency).await
+    }
+
+    async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError> {
+        let user = self.primary().update_user(input, id).await?;
+        let result = self.secondary().update_user(input, id).await;
+        if let Some(copy) = self.mirrored("update_user", result) {
+            self.check_divergence("update_user", &user, &copy);
+        }
+        Ok(user)
+    }
+
+    /// Only the primary's ETag is checked; the secondary follows whatever
+    /// the primary accepted.
+    async fn update_if_match(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: &str,
+    ) -> Result<User, AppError> {
+        let user = self.primary().update_if_match(input, id, if_match).await?;
+        let result = self.secondary().update_user(input, id).await;
+        if let Some(copy) = self.mirrored("update_if_match", result) {
+            self.check_divergence("update_if_match", &user, &copy);
+        }
+        Ok(user)
+    }
+
+    async fn update_by_email(
+        &self,
+        input: &UpdateUserRequest,
+        email: &str,
+    ) -> Result<User, AppError> {
+        let user = self.primary().update_by_email(input, email).await?;
+        // The email may be the field being changed, so follow the id.
+        let result = self.secondary().update_user(input, &user.id).await;
+        if let Some(copy) = self.mirrored("update_by_email", result) {
+            self.check_divergence("update_by_email", &user, &copy);
+        }
+        Ok(user)
+    }
+
+    async fn delete_user(&self, email: &str) -> Result<(), AppError> {
+        self.primary().delete_user(email).await?;
+        let result = self.secondary().delete_user(email).await;
+        self.mirrored("delete_user", result);
+        Ok(())
+    }
+
+    async fn delete_by_id(&self, id: &str) -> Result<(), AppError> {
+        self.primary().delete_by_id(id).await?;
+        let result = self.secondary().delete_by_id(id).await;
+        self.mirrored("delete_by_id", result);
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<usize, AppError> {
+        let removed = self.primary().clear().await?;
+        let result = self.secondary().clear().await;
+        if let Some(copy) = self.mirrored("clear", result)
+            && copy != removed
+        {
+            eprintln!(
+                "⚠️ Repositories diverged on clear: primary removed {}, secondary {}",
+                removed, copy
+            );
+        }
+        Ok(removed)
+    }
+
+    async fn count(&self) -> Result<usize, AppError> {
+        self.primary().count().await
+    }
+
+    async fn stored_count(&self) -> Result<usize, AppError> {
+        self.primary().stored_count().await
+    }
+
+    async fn merge_users(
+        &self,
+        keep: &str,
+        remove: &str,
+        fields: &[MergeField],
+    ) -> Result<User, AppError> {
+        let user = self.primary().merge_users(keep, remove, fields).await?;
+        let result = self.secondary().merge_users(keep, remove, fields).await;
+        if let Some(copy) = self.mirrored("merge_users", result) {
+            self.check_divergence("merge_users", &user, &copy);
+        }
+        Ok(user)
+    }
+
+    async fn purge_expired(&self, batch_size: usize) -> Result<usize, AppError> {
+        let purged = self.primary().purge_expired(batch_size).await?;
+        let result = self.secondary().purge_expired(batch_size).await;
+        self.mirrored("purge_expired", result);
+        Ok(purged)
+    }
+
+    fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>> {
+        self.primary().stream_all()
+    }
+
+    async fn reconcile(&self) -> Result<ReconcileReport, AppError> {
+        let report = self.primary().reconcile().await?;
+        let result = self.secondary().reconcile().await;
+        self.mirrored("reconcile", result);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+
+    fn request(name: &str, email: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            id: None,
+            name: name.to_string(),
+            email: email.to_string(),
+            age: 30,
+            expires_at: None,
+        }
+    }
+
+    fn repos() -> (Arc<InMemoryUserRepository>, Arc<InMemoryUserRepository>) {
+        (
+            Arc::new(InMemoryUserRepository::new()),
+            Arc::new(InMemoryUserRepository::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn both_backends_receive_every_write() {
+        let (left, right) = repos();
+        let repo = DualWriteRepository::new(left.clone(), right.clone());
+
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        let bob = repo
+            .create_user(&request("Bob", "bob@example.com"))
+            .await
+            .unwrap();
+        let rename = UpdateUserRequest {
+            name: Some("Annie".to_string()),
+            email: None,
+            age: None,
+        };
+        repo.update_user(&rename, &ann.id).await.unwrap();
+        repo.delete_by_id(&bob.id).await.unwrap();
+
+        for backend in [&left, &right] {
+            assert_eq!(backend.count().await.unwrap(), 1);
+            let copy = backend.find_by_id(&ann.id).await.unwrap().unwrap();
+            assert_eq!(copy.name, "Annie");
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_come_from_the_primary() {
+        let (left, right) = repos();
+        right
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+
+        let left_primary = DualWriteRepository::new(left.clone(), right.clone());
+        let right_primary =
+            DualWriteRepository::new(left, right).with_primary(DualWritePrimary::Right);
+
+        assert_eq!(left_primary.count().await.unwrap(), 0);
+        assert!(
+            right_primary
+                .find_by_email("ann@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_primary_failures_fail_the_write() {
+        let (left, right) = repos();
+        let repo = DualWriteRepository::new(left.clone(), right.clone());
+        // The secondary already holds this email, so its copy is refused.
+        right
+            .create_user(&request("Old", "ann@example.com"))
+            .await
+            .unwrap();
+
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        assert!(left.find_by_id(&ann.id).await.unwrap().is_some());
+        assert!(right.find_by_id(&ann.id).await.unwrap().is_none());
+
+        let err = repo
+            .create_user(&request("Again", "ann@example.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Conflict(_)));
+        assert_eq!(right.count().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use serde::Serialize;
+
+use crate::{clock::Clock, errors::AppError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EditLock {
+    pub owner: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Advisory per-user locks for coordinating edits between admin clients.
+/// Locks expire on their own so a crashed client cannot hold one forever.
+pub struct EditLocks {
+    locks: DashMap<String, EditLock>,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl EditLocks {
+    pub fn new(ttl_secs: u64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            locks: DashMap::new(),
+            ttl: Duration::seconds(ttl_secs as i64),
+            clock,
+        }
+    }
+
+    /// Takes the lock, or extends it if `owner` already holds it.
+    pub fn acquire(&self, id: &str, owner: &str) -> Result<EditLock, AppError> {
+        let now = self.clock.now();
+        let lock = EditLock {
+            owner: owner.to_string(),
+            expires_at: now + self.ttl,
+        };
+
+        match self.locks.entry(id.to_string()) {
+            Entry::Occupied(mut held) => {
+                if held.get().owner != owner && held.get().expires_at > now {
+                    return Err(locked_by(held.get()));
+                }
+                held.insert(lock.clone());
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(lock.clone());
+            }
+        }
+        Ok(lock)
+    }
+
+    /// Releasing a lock that is missing or expired is a no-op.
+    pub fn release(&self, id: &str, owner: &str) -> Result<(), AppError> {
+        let now = self.clock.now();
+        let removed = self
+            .locks
+            .remove_if(id, |_, lock| lock.owner == owner || lock.expires_at <= now);
+        if removed.is_none()
+            && let Some(lock) = self.locks.get(id)
+        {
+            return Err(locked_by(&lock));
+        }
+        Ok(())
+    }
+
+    /// Drops expired locks and returns how many were removed.
+    pub fn purge_expired(&self) -> usize {
+        let now = self.clock.now();
+        let before = self.locks.len();
+        self.locks.retain(|_, lock| lock.expires_at > now);
+        before.saturating_sub(self.locks.len())
+    }
+
+    /// Fails when someone other than `owner` holds a live lock on `id`.
+    pub fn check(&self, id: &str, owner: Option<&str>) -> Result<(), AppError> {
+        let now = self.clock.now();
+        match self.locks.get(id) {
+            Some(lock) if lock.expires_at > now && Some(lock.owner.as_str()) != owner => {
+                Err(locked_by(&lock))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn locked_by(lock: &EditLock) -> AppError {
+    AppError::Locked(format!(
+        "User is locked by {} until {}",
+        lock.owner,
+        lock.expires_at.to_rfc3339()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn locks() -> (EditLocks, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        (EditLocks::new(60, clock.clone()), clock)
+    }
+
+    #[test]
+    fn owner_can_reacquire_but_others_conflict() {
+        let (locks, _) = locks();
+
+        locks.acquire("u1", "alice").unwrap();
+        locks.acquire("u1", "alice").unwrap();
+
+        assert!(matches!(
+            locks.acquire("u1", "bob"),
+            Err(AppError::Locked(_))
+        ));
+        assert!(matches!(
+            locks.release("u1", "bob"),
+            Err(AppError::Locked(_))
+        ));
+        locks.release("u1", "alice").unwrap();
+        locks.acquire("u1", "bob").unwrap();
+    }
+
+    #[test]
+    fn expired_lock_can_be_taken_over() {
+        let (locks, clock) = locks();
+        locks.acquire("u1", "alice").unwrap();
+
+        clock.advance(Duration::seconds(61));
+
+        locks.check("u1", Some("bob")).unwrap();
+        locks.acquire("u1", "bob").unwrap();
+        assert_eq!(locks.purge_expired(), 0);
+    }
+
+    #[test]
+    fn check_rejects_everyone_but_the_owner() {
+        let (locks, _) = locks();
+        locks.acquire("u1", "alice").unwrap();
+
+        locks.check("u1", Some("alice")).unwrap();
+        assert!(matches!(
+            locks.check("u1", Some("bob")),
+            Err(AppError::Locked(_))
+        ));
+        assert!(matches!(locks.check("u1", None), Err(AppError::Locked(_))));
+        locks.check("u2", None).unwrap();
+    }
+}
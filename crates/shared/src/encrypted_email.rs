@@ -0,0 +1,476 @@
+use std::sync::Arc;
+
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, aead::Aead};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, stream::BoxStream};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    abstract_trait::UserRepositoryTrait,
+    domain::{
+        CreateUserRequest, MergeField, ReconcileReport, SearchField, UpdateUserRequest, User,
+    },
+    errors::AppError,
+};
+
+const NONCE_LEN: usize = 12;
+
+/// How emails are protected at rest. `Plaintext` is the no-op default.
+/// With a key, emails are sealed with ChaCha20-Poly1305 under a nonce
+/// derived from the address (as in SIV), so equal emails seal to the same
+/// ciphertext: it is stored in place of the email and doubles as the
+/// lookup key for uniqueness checks. `Hashed` keeps only an HMAC-SHA256 of
+/// the address: existence and duplicate checks still work, but the address
+/// cannot be recovered and reads return the hash in its place.
+#[derive(Clone, Default)]
+pub enum EmailCipher {
+    #[default]
+    Plaintext,
+    Sealed {
+        cipher: ChaCha20Poly1305,
+        nonce_key: [u8; 32],
+        index_key: [u8; 32],
+    },
+    Hashed {
+        index_key: [u8; 32],
+    },
+}
+
+impl std::fmt::Debug for EmailCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailCipher::Plaintext => f.write_str("Plaintext"),
+            EmailCipher::Sealed { .. } => f.write_str("Sealed"),
+            EmailCipher::Hashed { .. } => f.write_str("Hashed"),
+        }
+    }
+}
+
+/// Emails compare equal regardless of case and surrounding whitespace.
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+impl EmailCipher {
+    /// Builds a sealing cipher from a 32-byte key given as 64 hex digits.
+    /// The nonce and lookup keys are derived from it, so one secret covers
+    /// all three.
+    pub fn from_hex_key(key: &str) -> Result<Self, String> {
+        let key: [u8; 32] = hex::decode(key.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| "Email encryption key must be 64 hex digits".to_string())?;
+        Ok(EmailCipher::Sealed {
+            cipher: ChaCha20Poly1305::new(&key.into()),
+            nonce_key: hmac(&key, b"email-nonce"),
+            index_key: hmac(&key, b"email-index"),
+        })
+    }
+
+    /// Drops the encryption key, keeping only the lookup hash.
+    pub fn hash_only(self) -> Self {
+        match self {
+            EmailCipher::Sealed { index_key, .. } => EmailCipher::Hashed { index_key },
+            other => other,
+        }
+    }
+
+    /// Whether the inner repository holds real addresses.
+    fn is_plaintext(&self) -> bool {
+        matches!(self, EmailCipher::Plaintext)
+    }
+
+    /// Deterministic stand-in for `email` used for storage and lookups: the
+    /// normalized address, its sealed form as hex of nonce and ciphertext,
+    /// or its keyed hash.
+    pub fn index(&self, email: &str) -> Result<String, AppError> {
+        let email = normalize(email);
+        match self {
+            EmailCipher::Plaintext => Ok(email),
+            EmailCipher::Hashed { index_key } => Ok(hex::encode(hmac(index_key, email.as_bytes()))),
+            EmailCipher::Sealed {
+                cipher, nonce_key, ..
+            } => {
+                let digest = hmac(nonce_key, email.as_bytes());
+                let nonce = Nonce::from_slice(&digest[..NONCE_LEN]);
+                let sealed = cipher
+                    .encrypt(nonce, email.as_bytes())
+                    .map_err(|_| AppError::Internal("Failed to encrypt email".to_string()))?;
+                Ok(hex::encode([nonce.as_slice(), &sealed].concat()))
+            }
+        }
+    }
+
+    /// Recovers the address behind a stored [`index`](Self::index).
+    pub fn decrypt(&self, stored: &str) -> Result<String, AppError> {
+        let cipher = match self {
+            EmailCipher::Plaintext => return Ok(stored.to_string()),
+            EmailCipher::Sealed { cipher, .. } => cipher,
+            EmailCipher::Hashed { .. } => {
+                return Err(AppError::Internal(
+                    "Hashed emails cannot be decrypted".to_string(),
+                ));
+            }
+        };
+        let invalid = || AppError::Internal("Stored email cannot be decrypted".to_string());
+        let bytes = hex::decode(stored).map_err(|_| invalid())?;
+        if bytes.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+        let plain = cipher
+            .decrypt(nonce.into(), sealed)
+            .map_err(|_| invalid())?;
+        String::from_utf8(plain).map_err(|_| invalid())
+    }
+}
+
+/// Keeps emails encrypted in the wrapped repository. The inner repository
+/// only ever sees [`EmailCipher::index`] in place of the email, so its own
+/// uniqueness checks and email lookups keep working, and the sealed
+/// address lives in the user record it persists, to be decrypted on the
+/// way out. Searches only match names while emails are protected.
+pub struct EncryptedEmailRepository {
+    inner: Arc<dyn UserRepositoryTrait>,
+    cipher: EmailCipher,
+}
+
+impl EncryptedEmailRepository {
+    pub fn new(inner: Arc<dyn UserRepositoryTrait>, cipher: EmailCipher) -> Self {
+        Self { inner, cipher }
+    }
+
+    fn open(&self, user: User) -> Result<User, AppError> {
+        open_with(&self.cipher, user)
+    }
+
+    fn open_all(&self, users: Vec<User>) -> Result<Vec<User>, AppError> {
+        users.into_iter().map(|user| self.open(user)).collect()
+    }
+
+    fn sealed_update(&self, input: &UpdateUserRequest) -> Result<UpdateUserRequest, AppError> {
+        Ok(UpdateUserRequest {
+            name: input.name.clone(),
+            email: input
+                .email
+                .as_deref()
+                .map(|e| self.cipher.index(e))
+                .transpose()?,
+            age: input.age,
+        })
+    }
+}
+
+fn open_with(cipher: &EmailCipher, mut user: User) -> Result<User, AppError> {
+    if let EmailCipher::Sealed { .. } = cipher {
+        user.email = cipher.decrypt(&user.email)?;
+    }
+    Ok(user)
+}
+
+#[async_trait::async_trait]
+impl UserRepositoryTrait for EncryptedEmailRepository {
+    async fn find_all(
+        &self,
+        page: i32,
+        page_size: i32,
+        search: Option<String>,
+        search_field: SearchField,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        // The inner repository holds hex lookup keys, not emails, so a
+        // substring of one says nothing about the address.
+        let search_field = match (&self.cipher, &search, search_field) {
+            (cipher, Some(_), SearchField::Email) if !cipher.is_plaintext() => {
+                return Ok((Vec::new(), 0));
+            }
+            (cipher, Some(_), SearchField::All) if !cipher.is_plaintext() => SearchField::Name,
+            _ => search_field,
+        };
+        let (users, total) = self
+            .inner
+            .find_all(page, page_size, search, search_field)
+            .await?;
+        Ok((self.open_all(users)?, total))
+    }
+
+    async fn find_updated_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        let (users, total) = self
+            .inner
+            .find_updated_between(since, until, page, page_size)
+            .await?;
+        Ok((self.open_all(users)?, total))
+    }
+
+    async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError> {
+        self.inner
+            .find_by_email_exists(&self.cipher.index(email)?)
+            .await
+    }
+
+    async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError> {
+        let mut sealed_input = input.clone();
+        sealed_input.email = self.cipher.index(&input.email)?;
+        let user = self.inner.create_user(&sealed_input).await?;
+        self.open(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+        self.inner
+            .find_by_email(&self.cipher.index(email)?)
+            .await?
+            .map(|user| self.open(user))
+            .transpose()
+    }
+
+    async fn find_by_ids(&self, ids: &[String], concurrency: usize) -> Result<Vec<User>, AppError> {
+        self.open_all(self.inner.find_by_ids(ids, concurrency).await?)
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError> {
+        self.inner
+            .find_by_id(id)
+            .await?
+            .map(|user| self.open(user))
+            .transpose()
+    }
+
+    async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError> {
+        let user = self
+            .inner
+            .update_user(&self.sealed_update(input)?, id)
+            .await?;
+        self.open(user)
+    }
+
+    async fn update_if_match(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: &str,
+    ) -> Result<User, AppError> {
+        let user = self
+            .inner
+            .update_if_match(&self.sealed_update(input)?, id, if_match)
+            .await?;
+        self.open(user)
+    }
+
+    async fn update_by_email(
+        &self,
+        input: &UpdateUserRequest,
+        email: &str,
+    ) -> Result<User, AppError> {
+        let user = self
+            .inner
+            .update_by_email(&self.sealed_update(input)?, &self.cipher.index(email)?)
+            .await?;
+        self.open(user)
+    }
+
+    async fn delete_user(&self, email: &str) -> Result<(), AppError> {
+        self.inner.delete_user(&self.cipher.index(email)?).await
+    }
+
+    async fn delete_by_id(&self, id: &str) -> Result<(), AppError> {
+        self.inner.delete_by_id(id).await
+    }
+
+    async fn clear(&self) -> Result<usize, AppError> {
+        self.inner.clear().await
+    }
+
+    async fn count(&self) -> Result<usize, AppError> {
+        self.inner.count().await
+    }
+
+    async fn stored_count(&self) -> Result<usize, AppError> {
+        self.inner.stored_count().await
+    }
+
+    async fn merge_users(
+        &self,
+        keep: &str,
+        remove: &str,
+        fields: &[MergeField],
+    ) -> Result<User, AppError> {
+        let user = self.inner.merge_users(keep, remove, fields).await?;
+        self.open(user)
+    }
+
+    async fn purge_expired(&self, batch_size: usize) -> Result<usize, AppError> {
+        self.inner.purge_expired(batch_size).await
+    }
+
+    fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>> {
+        let cipher = self.cipher.clone();
+        self.inner
+            .stream_all()
+            .map(move |user| open_with(&cipher, user?))
+            .boxed()
+    }
+
+    async fn reconcile(&self) -> Result<ReconcileReport, AppError> {
+        let mut report = self.inner.reconcile().await?;
+        if let EmailCipher::Sealed { .. } = self.cipher {
+            for email in &mut report.duplicate_emails {
+                *email = self.cipher.decrypt(email)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn request(name: &str, email: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            id: None,
+            name: name.to_string(),
+            email: email.to_string(),
+            age: 30,
+            expires_at: None,
+        }
+    }
+
+    fn sealed_repo() -> (Arc<InMemoryUserRepository>, EncryptedEmailRepository) {
+        let inner = Arc::new(InMemoryUserRepository::new());
+        let cipher = EmailCipher::from_hex_key(KEY).unwrap();
+        (inner.clone(), EncryptedEmailRepository::new(inner, cipher))
+    }
+
+    #[test]
+    fn key_must_be_64_hex_digits() {
+        assert!(EmailCipher::from_hex_key("abcd").is_err());
+        assert!(EmailCipher::from_hex_key(&"zz".repeat(32)).is_err());
+        assert!(EmailCipher::from_hex_key(KEY).is_ok());
+    }
+
+    #[test]
+    fn sealing_is_deterministic_and_round_trips() {
+        let cipher = EmailCipher::from_hex_key(KEY).unwrap();
+        let sealed = cipher.index("ann@example.com").unwrap();
+
+        assert!(!sealed.contains("ann"));
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), "ann@example.com");
+        assert!(cipher.decrypt("00ff").is_err());
+        assert_ne!(sealed, cipher.index("bob@example.com").unwrap());
+        for variant in ["Ann@Example.com", " ann@example.com\t"] {
+            assert_eq!(cipher.index(variant).unwrap(), sealed, "{variant:?}");
+        }
+        let hashed = cipher.hash_only();
+        assert_eq!(
+            hashed.index(" Ann@Example.com ").unwrap(),
+            hashed.index("ann@example.com").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn stored_emails_are_encrypted_but_read_back_in_clear() {
+        let (inner, repo) = sealed_repo();
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(ann.email, "ann@example.com");
+
+        let stored = inner.find_by_id(&ann.id).await.unwrap().unwrap();
+        assert!(!stored.email.contains("ann"));
+
+        let found = repo
+            .find_by_email("ANN@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.email, "ann@example.com");
+        assert!(matches!(
+            repo.create_user(&request("Other", "Ann@Example.com")).await,
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn email_searches_match_nothing_while_sealed() {
+        let (_, repo) = sealed_repo();
+        repo.create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+
+        let search = |field| repo.find_all(1, 10, Some("ann".to_string()), field);
+        assert_eq!(search(SearchField::Email).await.unwrap().1, 0);
+        assert_eq!(search(SearchField::All).await.unwrap().1, 1);
+    }
+
+    #[tokio::test]
+    async fn sealed_emails_survive_a_new_wrapper_over_the_same_store() {
+        let (inner, repo) = sealed_repo();
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        drop(repo);
+
+        let cipher = EmailCipher::from_hex_key(KEY).unwrap();
+        let restarted = EncryptedEmailRepository::new(inner, cipher);
+        let found = restarted.find_by_id(&ann.id).await.unwrap().unwrap();
+        assert_eq!(found.email, "ann@example.com");
+        assert!(
+            restarted
+                .find_by_email_exists(" Ann@Example.com ")
+                .await
+                .unwrap()
+        );
+
+        let update = UpdateUserRequest {
+            name: None,
+            email: Some("ann@new.example".to_string()),
+            age: None,
+        };
+        let moved = restarted.update_user(&update, &ann.id).await.unwrap();
+        assert_eq!(moved.email, "ann@new.example");
+        let (users, _) = restarted
+            .find_all(1, 10, None, SearchField::All)
+            .await
+            .unwrap();
+        assert_eq!(users[0].email, "ann@new.example");
+    }
+
+    #[tokio::test]
+    async fn hash_only_mode_keeps_existence_checks_but_not_addresses() {
+        let cipher = EmailCipher::from_hex_key(KEY).unwrap().hash_only();
+        let repo =
+            EncryptedEmailRepository::new(Arc::new(InMemoryUserRepository::new()), cipher.clone());
+
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(ann.email, cipher.index("ann@example.com").unwrap());
+        assert!(repo.find_by_email_exists("Ann@Example.com").await.unwrap());
+        assert!(!repo.find_by_email_exists("bob@example.com").await.unwrap());
+        assert!(matches!(
+            repo.create_user(&request("Other", "ann@example.com")).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(cipher.decrypt(&ann.email).is_err());
+    }
+}
@@ -1,10 +1,30 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+
+use crate::validation::FieldError;
 
 #[derive(Debug)]
 pub enum AppError {
     UserNotFound,
+    Unauthorized,
+    Forbidden(String),
     ValidationError(String),
+    FieldErrors(Vec<FieldError>),
     CsvError(String),
+    PayloadTooLarge(String),
+    Timeout(String),
+    ServiceUnavailable(String),
+    TooManyRequests(String),
+    Locked(String),
+    PreconditionFailed(String),
+    /// The write collides with existing data, such as an email or id that
+    /// another user already holds.
+    Conflict(String),
+    NotFound(String),
+    /// The configured `max_users` has been reached.
+    InsufficientStorage(String),
+    RouteNotFound,
+    MethodNotAllowed,
     Internal(String),
 }
 
@@ -12,8 +32,28 @@ impl std::fmt::Display for AppError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             AppError::UserNotFound => write!(f, "User Not found"),
+            AppError::Unauthorized => write!(f, "Unauthorized"),
+            AppError::Forbidden(msg) => write!(f, "Forbidden: {msg}"),
             AppError::ValidationError(msg) => write!(f, "Validation Error: {msg}"),
+            AppError::FieldErrors(errors) => {
+                let messages: Vec<String> = errors
+                    .iter()
+                    .map(|e| format!("{}: {}", e.field, e.message))
+                    .collect();
+                write!(f, "Validation Error: {}", messages.join(", "))
+            }
             AppError::CsvError(msg) => write!(f, "Csv error: {msg}"),
+            AppError::PayloadTooLarge(msg) => write!(f, "Payload too large: {msg}"),
+            AppError::Timeout(msg) => write!(f, "Timeout: {msg}"),
+            AppError::ServiceUnavailable(msg) => write!(f, "Service unavailable: {msg}"),
+            AppError::TooManyRequests(msg) => write!(f, "Too many requests: {msg}"),
+            AppError::Locked(msg) => write!(f, "Locked: {msg}"),
+            AppError::PreconditionFailed(msg) => write!(f, "Precondition failed: {msg}"),
+            AppError::Conflict(msg) => write!(f, "Conflict: {msg}"),
+            AppError::NotFound(msg) => write!(f, "Not found: {msg}"),
+            AppError::InsufficientStorage(msg) => write!(f, "Insufficient storage: {msg}"),
+            AppError::RouteNotFound => write!(f, "No route matches this path"),
+            AppError::MethodNotAllowed => write!(f, "Method not allowed on this path"),
             AppError::Internal(msg) => write!(f, "Internal error: {msg}"),
         }
     }
@@ -21,17 +61,60 @@ impl std::fmt::Display for AppError {
 
 impl std::error::Error for AppError {}
 
+impl AppError {
+    /// The status this error is answered with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::UserNotFound | AppError::NotFound(_) | AppError::RouteNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::ValidationError(_) | AppError::CsvError(_) => StatusCode::BAD_REQUEST,
+            AppError::FieldErrors(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Locked(_) => StatusCode::LOCKED,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            AppError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let (status, message) = match self {
-            AppError::UserNotFound => (StatusCode::NOT_FOUND, self.to_string()),
-            AppError::ValidationError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
-            AppError::CsvError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
-            AppError::Internal(_) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal error".to_string(),
-            ),
+        if let AppError::FieldErrors(errors) = self {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "errors": errors })),
+            )
+                .into_response();
+        }
+
+        if let AppError::RouteNotFound | AppError::MethodNotAllowed = self {
+            let code = match self {
+                AppError::RouteNotFound => "NOT_FOUND",
+                _ => "METHOD_NOT_ALLOWED",
+            };
+            return (
+                self.status(),
+                Json(json!({
+                    "success": false,
+                    "error": { "code": code, "message": self.to_string() },
+                })),
+            )
+                .into_response();
+        }
+
+        let message = match self {
+            AppError::Internal(_) => "Internal error".to_string(),
+            _ => self.to_string(),
         };
-        (status, message).into_response()
+        (self.status(), message).into_response()
     }
 }
@@ -0,0 +1,228 @@
+use std::{hash::Hash, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use tokio::task::JoinHandle;
+
+use crate::clock::{Clock, SystemClock};
+
+struct Slot<V> {
+    value: V,
+    expires_at: DateTime<Utc>,
+    last_access: DateTime<Utc>,
+}
+
+/// A concurrent map whose entries expire after a fixed TTL and whose size is
+/// bounded. Expired entries are dropped lazily when touched, by
+/// [`purge_expired`](Self::purge_expired), or by the optional background
+/// sweeper. When full, the least recently accessed entry is evicted; finding
+/// it is a linear scan, so keep capacities in the thousands, not millions.
+pub struct ExpiringMap<K, V> {
+    entries: DashMap<K, Slot<V>>,
+    ttl: chrono::Duration,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl<K, V> ExpiringMap<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl: chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX),
+            capacity: capacity.max(1),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let now = self.clock.now();
+        if let Some(mut slot) = self.entries.get_mut(key)
+            && slot.expires_at > now
+        {
+            slot.last_access = now;
+            return Some(slot.value.clone());
+        }
+        self.entries
+            .remove_if(key, |_, slot| slot.expires_at <= now);
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts or replaces `key`, restarting its TTL.
+    pub fn insert(&self, key: K, value: V) {
+        self.make_room(&key);
+        let now = self.clock.now();
+        self.entries.insert(key, self.slot(value, now));
+    }
+
+    /// Inserts `value` only if `key` is absent or expired. Returns `true` if
+    /// the value was stored, which makes this usable as a "seen before?"
+    /// check for deduplication.
+    pub fn insert_if_absent(&self, key: K, value: V) -> bool {
+        self.make_room(&key);
+        let now = self.clock.now();
+        match self.entries.entry(key) {
+            Entry::Occupied(mut occupied) => {
+                if occupied.get().expires_at > now {
+                    return false;
+                }
+                occupied.insert(self.slot(value, now));
+                true
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(self.slot(value, now));
+                true
+            }
+        }
+    }
+
+    /// Removes the entry and returns it unless it has already expired, so a
+    /// value can be consumed exactly once.
+    pub fn take(&self, key: &K) -> Option<V> {
+        let now = self.clock.now();
+        self.entries
+            .remove(key)
+            .filter(|(_, slot)| slot.expires_at > now)
+            .map(|(_, slot)| slot.value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|(_, slot)| slot.value)
+    }
+
+    /// Number of stored entries, including expired ones not yet purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops every expired entry and returns how many were removed.
+    pub fn purge_expired(&self) -> usize {
+        let now = self.clock.now();
+        let before = self.entries.len();
+        self.entries.retain(|_, slot| slot.expires_at > now);
+        before.saturating_sub(self.entries.len())
+    }
+
+    fn slot(&self, value: V, now: DateTime<Utc>) -> Slot<V> {
+        Slot {
+            value,
+            expires_at: now
+                .checked_add_signed(self.ttl)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            last_access: now,
+        }
+    }
+
+    /// Frees a slot for `key` if the map is full. Must not be called while
+    /// holding a guard into `entries`.
+    fn make_room(&self, key: &K) {
+        if self.entries.len() < self.capacity || self.entries.contains_key(key) {
+            return;
+        }
+        if self.purge_expired() > 0 {
+            return;
+        }
+
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|entry| entry.value().last_access)
+            .map(|entry| entry.key().clone());
+        if let Some(oldest) = oldest {
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+impl<K, V> ExpiringMap<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Purges expired entries every `every` until the returned handle is
+    /// aborted. Only needed when keys are rarely read back, since lookups
+    /// already expire entries on their own.
+    pub fn spawn_sweeper(self: &Arc<Self>, every: Duration) -> JoinHandle<()> {
+        let map = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                map.purge_expired();
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn map(capacity: usize) -> (ExpiringMap<&'static str, u32>, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let map = ExpiringMap::new(Duration::from_secs(60), capacity).with_clock(clock.clone());
+        (map, clock)
+    }
+
+    #[test]
+    fn entries_expire_on_access() {
+        let (map, clock) = map(10);
+        map.insert("a", 1);
+
+        clock.advance(chrono::Duration::seconds(59));
+        assert_eq!(map.get(&"a"), Some(1));
+
+        clock.advance(chrono::Duration::seconds(2));
+        assert_eq!(map.get(&"a"), None);
+        assert!(map.is_empty());
+        assert!(map.insert_if_absent("a", 2));
+    }
+
+    #[test]
+    fn full_map_evicts_the_least_recently_used_entry() {
+        let (map, clock) = map(2);
+        map.insert("a", 1);
+        clock.advance(chrono::Duration::seconds(1));
+        map.insert("b", 2);
+        clock.advance(chrono::Duration::seconds(1));
+        map.get(&"a");
+
+        map.insert("c", 3);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&"a"), Some(1));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.get(&"c"), Some(3));
+    }
+
+    #[test]
+    fn full_map_drops_expired_entries_before_live_ones() {
+        let (map, clock) = map(2);
+        map.insert("old", 1);
+        clock.advance(chrono::Duration::seconds(50));
+        map.insert("new", 2);
+        clock.advance(chrono::Duration::seconds(20));
+
+        map.insert("next", 3);
+
+        assert_eq!(map.get(&"new"), Some(2));
+        assert_eq!(map.get(&"next"), Some(3));
+    }
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashSet, env, fmt, fs, str::FromStr};
+
+/// Groups of operations that can be switched off, e.g. while an endpoint is
+/// being rolled out or during an incident. Disabled routes answer
+/// `403 Forbidden`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Delete,
+    Bulk,
+    Import,
+    Export,
+    EmailChange,
+    Locks,
+}
+
+impl Feature {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::Delete => "delete",
+            Feature::Bulk => "bulk",
+            Feature::Import => "import",
+            Feature::Export => "export",
+            Feature::EmailChange => "email_change",
+            Feature::Locks => "locks",
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "delete" => Ok(Feature::Delete),
+            "bulk" => Ok(Feature::Bulk),
+            "import" => Ok(Feature::Import),
+            "export" => Ok(Feature::Export),
+            "email_change" => Ok(Feature::EmailChange),
+            "locks" => Ok(Feature::Locks),
+            other => Err(format!("Unknown feature: {other}")),
+        }
+    }
+}
+
+/// Every feature is enabled unless switched off.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureFlags {
+    disabled: HashSet<Feature>,
+}
+
+impl FeatureFlags {
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        !self.disabled.contains(&feature)
+    }
+
+    /// Names of the switched-off features, sorted.
+    pub fn disabled(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = self.disabled.iter().map(Feature::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn set(&mut self, feature: Feature, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&feature);
+        } else {
+            self.disabled.insert(feature);
+        }
+    }
+
+    pub fn disable(mut self, feature: Feature) -> Self {
+        self.set(feature, false);
+        self
+    }
+
+    /// Applies `name=on|off` entries separated by commas or newlines. Blank
+    /// entries and `#` comments are skipped. Nothing is applied if any entry
+    /// is invalid.
+    pub fn apply(&mut self, entries: &str) -> Result<(), String> {
+        let mut parsed = Vec::new();
+        for entry in entries.split([',', '\n']) {
+            let entry = entry.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let (name, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=on|off, got: {entry}"))?;
+            let enabled = match value.trim().to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" => false,
+                other => return Err(format!("Invalid value for {}: {other}", name.trim())),
+            };
+            parsed.push((name.parse()?, enabled));
+        }
+        for (feature, enabled) in parsed {
+            self.set(feature, enabled);
+        }
+        Ok(())
+    }
+
+    /// Reads the file named by `FEATURE_FLAGS_FILE`, then `FEATURE_FLAGS`,
+    /// so the environment overrides the file. Problems are logged and the
+    /// offending source is skipped rather than failing startup.
+    pub fn from_env() -> Self {
+        let mut flags = Self::default();
+
+        if let Ok(path) = env::var("FEATURE_FLAGS_FILE") {
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    if let Err(e) = flags.apply(&contents) {
+                        eprintln!("⚠️ Ignoring feature flags file {}: {}", path, e);
+                    }
+                }
+                Err(e) => eprintln!("⚠️ Cannot read feature flags file {}: {}", path, e),
+            }
+        }
+
+        if let Ok(entries) = env::var("FEATURE_FLAGS")
+            && let Err(e) = flags.apply(&entries)
+        {
+            eprintln!("⚠️ Ignoring FEATURE_FLAGS: {}", e);
+        }
+
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_switch_features_on_and_off() {
+        let mut flags = FeatureFlags::default().disable(Feature::Export);
+
+        flags
+            .apply("delete=off, Email-Change = no\n# bulk=off\nexport=on")
+            .unwrap();
+
+        assert!(!flags.is_enabled(Feature::Delete));
+        assert!(!flags.is_enabled(Feature::EmailChange));
+        assert!(flags.is_enabled(Feature::Bulk));
+        assert!(flags.is_enabled(Feature::Export));
+        assert_eq!(flags.disabled(), vec!["delete", "email_change"]);
+    }
+
+    #[test]
+    fn an_invalid_entry_applies_nothing() {
+        let mut flags = FeatureFlags::default();
+
+        assert!(flags.apply("delete=off,teleport=off").is_err());
+        assert!(flags.apply("delete=maybe").is_err());
+        assert!(flags.apply("delete").is_err());
+
+        assert!(flags.disabled().is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use rdkafka::{
+    admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+    client::DefaultClientContext,
+    config::ClientConfig,
+    types::RDKafkaErrorCode,
+};
+
+use super::KafkaAuth;
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+pub struct TopicSpec {
+    pub partitions: i32,
+    pub replication: i32,
+}
+
+/// Checks that every topic exists. Missing topics are created when `create`
+/// is set; otherwise this fails fast instead of letting a consumer wait
+/// forever on a topic that will never receive messages.
+pub async fn ensure_topics(
+    brokers: &str,
+    auth: &KafkaAuth,
+    topics: &[&str],
+    create: bool,
+    spec: TopicSpec,
+) -> Result<(), String> {
+    let mut config = ClientConfig::new();
+    config.set("bootstrap.servers", brokers);
+    auth.apply(&mut config);
+    let admin: AdminClient<DefaultClientContext> = config
+        .create()
+        .map_err(|e| format!("Failed to create Kafka admin client: {e}"))?;
+
+    // The metadata fetch is a blocking call that can take up to the timeout
+    // when brokers are unreachable; keep it off the async worker threads.
+    let (admin, existing) = tokio::task::spawn_blocking(move || {
+        let metadata = admin
+            .inner()
+            .fetch_metadata(None, METADATA_TIMEOUT)
+            .map_err(|e| format!("Failed to fetch Kafka metadata: {e}"))?;
+        let existing: Vec<String> = metadata
+            .topics()
+            .iter()
+            .map(|t| t.name().to_string())
+            .collect();
+        Ok::<_, String>((admin, existing))
+    })
+    .await
+    .map_err(|e| format!("Kafka metadata task failed: {e}"))??;
+
+    let missing = missing_topics(topics, &existing);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    if !create {
+        return Err(format!(
+            "Kafka topics do not exist: {}. Create them or set CREATE_TOPICS=true",
+            missing.join(", ")
+        ));
+    }
+
+    let new_topics: Vec<NewTopic> = missing
+        .iter()
+        .map(|topic| {
+            NewTopic::new(
+                topic,
+                spec.partitions,
+                TopicReplication::Fixed(spec.replication),
+            )
+        })
+        .collect();
+
+    let results = admin
+        .create_topics(&new_topics, &AdminOptions::new())
+        .await
+        .map_err(|e| format!("Failed to create Kafka topics: {e}"))?;
+
+    for result in results {
+        match result {
+            Ok(topic) => println!("🆕 Created Kafka topic {}", topic),
+            Err((topic, RDKafkaErrorCode::TopicAlreadyExists)) => {
+                println!("ℹ️ Kafka topic {} already exists", topic)
+            }
+            Err((topic, code)) => {
+                return Err(format!("Failed to create Kafka topic {topic}: {code}"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn missing_topics<'a>(topics: &[&'a str], existing: &[String]) -> Vec<&'a str> {
+    topics
+        .iter()
+        .copied()
+        .filter(|topic| !existing.iter().any(|t| t == topic))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_topics_the_broker_lacks_are_missing() {
+        let existing = vec!["user-jobs".to_string(), "other".to_string()];
+
+        let missing = missing_topics(&["user-jobs", "user-changes", "user-dlq"], &existing);
+
+        assert_eq!(missing, vec!["user-changes", "user-dlq"]);
+    }
+
+    /// Runs against the broker in `KAFKA_TEST_BROKERS`, e.g.
+    /// `KAFKA_TEST_BROKERS=localhost:9092 cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs a Kafka broker in KAFKA_TEST_BROKERS"]
+    async fn missing_topics_are_created_when_enabled() {
+        let brokers = std::env::var("KAFKA_TEST_BROKERS").expect("KAFKA_TEST_BROKERS");
+        let topic = format!("ensure-topics-{}", uuid::Uuid::new_v4());
+        let auth = KafkaAuth::default();
+        let spec = TopicSpec {
+            partitions: 2,
+            replication: 1,
+        };
+
+        let err = ensure_topics(&brokers, &auth, &[&topic], false, spec)
+            .await
+            .unwrap_err();
+        assert!(err.contains("CREATE_TOPICS=true"), "{err}");
+
+        ensure_topics(&brokers, &auth, &[&topic], true, spec)
+            .await
+            .unwrap();
+        // Topic creation propagates asynchronously; the check must then pass
+        // without creating anything.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        ensure_topics(&brokers, &auth, &[&topic], false, spec)
+            .await
+            .unwrap();
+    }
+}
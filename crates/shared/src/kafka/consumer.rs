@@ -1,58 +1,112 @@
-use crate::{abstract_trait::UserServiceTrait, domain::KafkaEvent};
+use crate::{
+    abstract_trait::UserServiceTrait,
+    domain::{ExportFilter, KafkaEvent},
+    expiring_map::ExpiringMap,
+    kafka::{EVENT_ID_HEADER, EventCategory, KafkaAuth, PartitionAssignment},
+    shutdown::ShutdownSignal,
+};
 use futures::StreamExt;
 use rdkafka::{
     Message,
     config::ClientConfig,
     consumer::{Consumer, StreamConsumer},
+    message::{BorrowedMessage, Headers},
 };
-use std::sync::Arc;
-use tokio::task;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task::JoinSet};
 
 pub struct KafkaEventConsumer {
     consumer: StreamConsumer,
     user_service: Arc<dyn UserServiceTrait>,
+    csv_jobs: Arc<Semaphore>,
+    /// Event ids handled recently, for skipping redeliveries.
+    processed: Option<Arc<ExpiringMap<String, ()>>>,
 }
 
 impl KafkaEventConsumer {
+    /// Subscribes to `topics`, unless `assignment` lists explicit
+    /// partitions, which are then assigned instead and `topics` is unused.
     pub async fn new(
         brokers: &str,
+        auth: &KafkaAuth,
         group_id: &str,
-        topic: &str,
+        topics: &[&str],
+        assignment: &PartitionAssignment,
         user_service: Arc<dyn UserServiceTrait>,
     ) -> Self {
-        let consumer: StreamConsumer = ClientConfig::new()
+        let mut config = ClientConfig::new();
+        config
             .set("group.id", group_id)
             .set("bootstrap.servers", brokers)
             .set("enable.auto.commit", "true")
             .set("auto.offset.reset", "smallest")
-            .set("session.timeout.ms", "6000")
-            .create()
-            .expect("Failed to create Kafka consumer");
+            .set("session.timeout.ms", "6000");
+        auth.apply(&mut config);
+        let consumer: StreamConsumer = config.create().expect("Failed to create Kafka consumer");
 
-        consumer
-            .subscribe(&[topic])
-            .expect("Can't subscribe to topic");
+        match assignment.partition_list() {
+            Some(partitions) => {
+                println!("📌 Assigning Kafka partitions: {:?}", assignment);
+                consumer
+                    .assign(&partitions)
+                    .expect("Can't assign partitions");
+            }
+            None => consumer
+                .subscribe(topics)
+                .expect("Can't subscribe to topics"),
+        }
 
         Self {
             consumer,
             user_service,
+            csv_jobs: Arc::new(Semaphore::new(1)),
+            processed: None,
         }
     }
 
-    pub async fn start_listening(self) {
+    /// Skips events whose id was already seen within `window`, remembering
+    /// at most `capacity` ids. A zero window turns deduplication off.
+    pub fn with_dedup_window(mut self, window: Duration, capacity: usize) -> Self {
+        self.processed = (!window.is_zero()).then(|| Arc::new(ExpiringMap::new(window, capacity)));
+        self
+    }
+
+    /// Caps how many CSV import/export jobs run at once. Further CSV events
+    /// are still received but wait for a free slot.
+    pub fn with_csv_job_limit(mut self, limit: usize) -> Self {
+        self.csv_jobs = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
+    pub async fn start_listening(self, shutdown: ShutdownSignal, grace: Duration) {
         let mut stream = self.consumer.stream();
+        let mut tasks = JoinSet::new();
 
         println!("👂 Kafka consumer listening for events...");
 
-        while let Some(message_result) = stream.next().await {
+        loop {
+            let message_result = tokio::select! {
+                next = stream.next() => match next {
+                    Some(message_result) => message_result,
+                    None => break,
+                },
+                _ = shutdown.clone().recv() => break,
+            };
+
             match message_result {
                 Ok(message) => {
                     if let Some(payload) = message.payload() {
                         match serde_json::from_slice::<KafkaEvent>(payload) {
                             Ok(event) => {
+                                let event_id = event_id(&message);
                                 let service = self.user_service.clone();
-                                task::spawn(async move {
-                                    Self::handle_event(event, service).await;
+                                let csv_jobs = self.csv_jobs.clone();
+                                let processed = self.processed.clone();
+                                tasks.spawn(async move {
+                                    Self::handle_event(
+                                        event, event_id, service, csv_jobs, processed,
+                                    )
+                                    .await;
                                 });
                             }
                             Err(e) => eprintln!("❌ Failed to parse Kafka event: {}", e),
@@ -61,10 +115,49 @@ impl KafkaEventConsumer {
                 }
                 Err(e) => eprintln!("Kafka error: {}", e),
             }
+
+            while tasks.try_join_next().is_some() {}
+        }
+
+        println!(
+            "🛑 Kafka consumer stopping, waiting for {} handlers...",
+            tasks.len()
+        );
+        let drain = async { while tasks.join_next().await.is_some() {} };
+        if tokio::time::timeout(grace, drain).await.is_err() {
+            eprintln!("⏱️ Handlers still running after {:?}, aborting", grace);
+            tasks.abort_all();
         }
     }
 
-    async fn handle_event(event: KafkaEvent, service: Arc<dyn UserServiceTrait>) {
+    async fn handle_event(
+        event: KafkaEvent,
+        event_id: Option<String>,
+        service: Arc<dyn UserServiceTrait>,
+        csv_jobs: Arc<Semaphore>,
+        processed: Option<Arc<ExpiringMap<String, ()>>>,
+    ) {
+        // With the default single topic, change events arrive here too.
+        if event.category() != EventCategory::Jobs {
+            return;
+        }
+
+        // Ids are claimed before the work starts, so a redelivery arriving
+        // while the first copy is still running is skipped too. Events from
+        // producers that send no id are always handled.
+        if let (Some(id), Some(processed)) = (&event_id, &processed)
+            && !processed.insert_if_absent(id.clone(), ())
+        {
+            println!("⏭️ Skipping duplicate event {}", id);
+            return;
+        }
+
+        // Every event kind is currently a CSV job; the semaphore is never
+        // closed, so acquiring only fails if that changes.
+        let Ok(_permit) = csv_jobs.acquire().await else {
+            return;
+        };
+
         match event {
             KafkaEvent::ImportCsv { path } => {
                 println!("📥 Handling import from CSV: {}", path);
@@ -74,14 +167,125 @@ impl KafkaEventConsumer {
                     println!("✅ Successfully imported from {}", path);
                 }
             }
-            KafkaEvent::ExportCsv { path } => {
+            KafkaEvent::ExportCsv {
+                path,
+                since,
+                until,
+                dialect,
+            } => {
                 println!("📤 Handling export to CSV: {}", path);
-                if let Err(e) = service.export_to_csv(&path).await {
+                let filter = ExportFilter { since, until };
+                if let Err(e) = service.export_to_csv(&path, &filter, &dialect).await {
                     eprintln!("❌ Export failed: {}", e);
                 } else {
                     println!("✅ Exported to {}", path);
                 }
             }
+            KafkaEvent::UserChanged { .. } => {}
         }
     }
 }
+
+fn event_id(message: &BorrowedMessage<'_>) -> Option<String> {
+    message
+        .headers()?
+        .iter()
+        .find(|header| header.key == EVENT_ID_HEADER)?
+        .value
+        .and_then(|value| std::str::from_utf8(value).ok())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{config::AppConfig, context::ServiceBuilder};
+
+    #[tokio::test]
+    async fn csv_jobs_wait_for_a_free_slot() {
+        let service = ServiceBuilder::new(AppConfig::default())
+            .without_kafka()
+            .build()
+            .service;
+        let csv_jobs = Arc::new(Semaphore::new(1));
+        let mut paths = Vec::new();
+        for i in 0..3 {
+            let path = std::env::temp_dir().join(format!("import_{}.csv", Uuid::new_v4()));
+            let csv = format!(
+                "id,name,email,age,created_at,updated_at\n,User {i},user{i}@example.com,30,,\n"
+            );
+            tokio::fs::write(&path, csv).await.unwrap();
+            paths.push(path);
+        }
+
+        // Hold the only slot, as a running job would.
+        let running = csv_jobs.clone().acquire_owned().await.unwrap();
+        let mut jobs = JoinSet::new();
+        for path in &paths {
+            let event = KafkaEvent::ImportCsv {
+                path: path.to_string_lossy().into_owned(),
+            };
+            jobs.spawn(KafkaEventConsumer::handle_event(
+                event,
+                None,
+                service.clone(),
+                csv_jobs.clone(),
+                None,
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(service.repo.count().await.unwrap(), 0);
+
+        drop(running);
+        while jobs.join_next().await.is_some() {}
+        assert_eq!(service.repo.count().await.unwrap(), 3);
+
+        for path in paths {
+            tokio::fs::remove_file(path).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn redelivered_events_are_handled_once() {
+        let service = ServiceBuilder::new(AppConfig::default())
+            .without_kafka()
+            .build()
+            .service;
+        let csv_jobs = Arc::new(Semaphore::new(1));
+        let processed = Some(Arc::new(ExpiringMap::new(Duration::from_secs(60), 100)));
+        let path = std::env::temp_dir().join(format!("import_{}.csv", Uuid::new_v4()));
+        let write = |n: u32| {
+            let csv = format!(
+                "id,name,email,age,created_at,updated_at\n,User {n},user{n}@example.com,30,,\n"
+            );
+            tokio::fs::write(path.clone(), csv)
+        };
+        let deliver = |id: &str| {
+            KafkaEventConsumer::handle_event(
+                KafkaEvent::ImportCsv {
+                    path: path.to_string_lossy().into_owned(),
+                },
+                Some(id.to_string()),
+                service.clone(),
+                csv_jobs.clone(),
+                processed.clone(),
+            )
+        };
+
+        write(1).await.unwrap();
+        deliver("event-1").await;
+        assert_eq!(service.repo.count().await.unwrap(), 1);
+
+        // A fresh user in the file shows whether the handler ran again.
+        write(2).await.unwrap();
+        deliver("event-1").await;
+        assert_eq!(service.repo.count().await.unwrap(), 1);
+
+        deliver("event-2").await;
+        assert_eq!(service.repo.count().await.unwrap(), 2);
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
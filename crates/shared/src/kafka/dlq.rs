@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rdkafka::{
+    Message, Offset, TopicPartitionList,
+    config::ClientConfig,
+    consumer::{Consumer, StreamConsumer},
+    error::KafkaError,
+    message::{BorrowedMessage, Headers},
+};
+use serde::Serialize;
+use tokio::time::{Instant, timeout_at};
+use uuid::Uuid;
+
+use super::KafkaAuth;
+
+/// A message read back from the dead-letter topic, with enough of its
+/// Kafka coordinates to find it again.
+#[derive(Debug, Clone, Serialize)]
+pub struct DlqEnvelope {
+    pub partition: i32,
+    pub offset: i64,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub key: Option<String>,
+    pub headers: Vec<(String, String)>,
+    /// The payload as JSON when it parses, otherwise as a (lossy) string.
+    pub payload: serde_json::Value,
+}
+
+impl DlqEnvelope {
+    fn from_message(message: &BorrowedMessage<'_>) -> Self {
+        let payload = message.payload().unwrap_or_default();
+        Self {
+            partition: message.partition(),
+            offset: message.offset(),
+            timestamp: message
+                .timestamp()
+                .to_millis()
+                .and_then(DateTime::from_timestamp_millis),
+            key: message
+                .key()
+                .map(|key| String::from_utf8_lossy(key).into_owned()),
+            headers: message
+                .headers()
+                .map(|headers| {
+                    headers
+                        .iter()
+                        .map(|header| {
+                            let value = header.value.unwrap_or_default();
+                            (
+                                header.key.to_string(),
+                                String::from_utf8_lossy(value).into_owned(),
+                            )
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            payload: serde_json::from_slice(payload).unwrap_or_else(|_| {
+                serde_json::Value::String(String::from_utf8_lossy(payload).into_owned())
+            }),
+        }
+    }
+}
+
+/// Reads up to `limit` messages from the start of every partition of
+/// `topic`, stopping early once all partitions are exhausted or `timeout`
+/// passes. Uses a fresh consumer group that never commits, so it does not
+/// move any real consumer's offsets.
+pub async fn peek(
+    brokers: &str,
+    auth: &KafkaAuth,
+    topic: &str,
+    limit: usize,
+    timeout: Duration,
+) -> Result<Vec<DlqEnvelope>, String> {
+    let deadline = Instant::now() + timeout;
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", brokers)
+        .set("group.id", format!("dlq-peek-{}", Uuid::new_v4()))
+        .set("enable.auto.commit", "false")
+        .set("enable.auto.offset.store", "false")
+        .set("enable.partition.eof", "true");
+    auth.apply(&mut config);
+    let consumer: StreamConsumer = config
+        .create()
+        .map_err(|e| format!("Failed to create Kafka consumer: {e}"))?;
+
+    // Same as in `ensure_topics`: the metadata fetch blocks, here for at
+    // most the whole peek timeout.
+    let owned_topic = topic.to_string();
+    let (consumer, partitions) = tokio::task::spawn_blocking(move || {
+        let metadata = consumer
+            .fetch_metadata(Some(&owned_topic), timeout)
+            .map_err(|e| format!("Failed to fetch Kafka metadata: {e}"))?;
+        let partitions: Vec<i32> = metadata
+            .topics()
+            .iter()
+            .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+            .collect();
+        Ok::<_, String>((consumer, partitions))
+    })
+    .await
+    .map_err(|e| format!("Kafka metadata task failed: {e}"))??;
+
+    if partitions.is_empty() {
+        return Err(format!("Kafka topic {topic} has no partitions"));
+    }
+    let mut assignment = TopicPartitionList::new();
+    for partition in &partitions {
+        assignment
+            .add_partition_offset(topic, *partition, Offset::Beginning)
+            .map_err(|e| e.to_string())?;
+    }
+    consumer
+        .assign(&assignment)
+        .map_err(|e| format!("Failed to assign DLQ partitions: {e}"))?;
+
+    let mut envelopes = Vec::new();
+    let mut exhausted = 0;
+    while envelopes.len() < limit && exhausted < partitions.len() {
+        match timeout_at(deadline, consumer.recv()).await {
+            Err(_) => break,
+            Ok(Ok(message)) => envelopes.push(DlqEnvelope::from_message(&message)),
+            Ok(Err(KafkaError::PartitionEOF(_))) => exhausted += 1,
+            Ok(Err(e)) => return Err(format!("Failed to read DLQ: {e}")),
+        }
+    }
+    Ok(envelopes)
+}
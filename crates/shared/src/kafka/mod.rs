@@ -1,2 +1,340 @@
+pub mod admin;
 pub mod consumer;
+pub mod dlq;
 pub mod producer;
+
+use rdkafka::{TopicPartitionList, config::ClientConfig};
+
+use crate::domain::KafkaEvent;
+
+/// Message header carrying a unique id per produced event, so consumers can
+/// recognise a redelivery.
+pub const EVENT_ID_HEADER: &str = "event-id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventCategory {
+    Jobs,
+    Cdc,
+    Dlq,
+}
+
+impl KafkaEvent {
+    pub fn category(&self) -> EventCategory {
+        match self {
+            KafkaEvent::ImportCsv { .. } | KafkaEvent::ExportCsv { .. } => EventCategory::Jobs,
+            KafkaEvent::UserChanged { .. } => EventCategory::Cdc,
+        }
+    }
+
+    /// Message key. Change events are keyed by user so that all changes to
+    /// one user land on the same partition, in order.
+    pub fn key(&self) -> String {
+        match self {
+            KafkaEvent::UserChanged { user_id, .. } => user_id.clone(),
+            other => format!("{:?}", other),
+        }
+    }
+}
+
+/// Maps each event category to a topic. By default every category shares
+/// the jobs topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicRouting {
+    pub jobs: String,
+    pub cdc: String,
+    pub dlq: String,
+}
+
+impl TopicRouting {
+    pub fn single(topic: &str) -> Self {
+        Self {
+            jobs: topic.to_owned(),
+            cdc: topic.to_owned(),
+            dlq: topic.to_owned(),
+        }
+    }
+
+    pub fn topic_for(&self, category: EventCategory) -> &str {
+        match category {
+            EventCategory::Jobs => &self.jobs,
+            EventCategory::Cdc => &self.cdc,
+            EventCategory::Dlq => &self.dlq,
+        }
+    }
+
+    /// The topic dead letters go to, if it is a topic of its own. When it
+    /// falls back to the jobs topic there is nothing separate to read.
+    pub fn dead_letter_topic(&self) -> Option<&str> {
+        (self.dlq != self.jobs).then_some(self.dlq.as_str())
+    }
+
+    /// Topics the job worker consumes. Change events are for downstream
+    /// consumers and dead letters must not be fed back into the worker, so
+    /// only the jobs topic is read; change events that share it are skipped.
+    pub fn worker_topics(&self) -> Vec<&str> {
+        vec![self.jobs.as_str()]
+    }
+
+    /// Every distinct topic, e.g. to make sure they all exist.
+    pub fn topics(&self) -> Vec<&str> {
+        let mut topics = vec![self.jobs.as_str()];
+        for topic in [self.cdc.as_str(), self.dlq.as_str()] {
+            if !topics.contains(&topic) {
+                topics.push(topic);
+            }
+        }
+        topics
+    }
+}
+
+impl Default for TopicRouting {
+    fn default() -> Self {
+        Self::single("user-jobs")
+    }
+}
+
+/// Producer acknowledgment level. `All` waits for every in-sync replica and
+/// enables idempotent delivery, trading throughput for durability; `None`
+/// does not wait at all and may silently drop events on broker failure.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Acks {
+    None,
+    Leader,
+    #[default]
+    All,
+}
+
+impl Acks {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Acks::None => "0",
+            Acks::Leader => "1",
+            Acks::All => "all",
+        }
+    }
+}
+
+impl std::str::FromStr for Acks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "0" | "none" => Ok(Acks::None),
+            "1" | "leader" => Ok(Acks::Leader),
+            "all" | "-1" => Ok(Acks::All),
+            other => Err(format!("Unknown acks level: {other}")),
+        }
+    }
+}
+
+/// How the worker's consumer picks its partitions. `Subscribe` joins the
+/// consumer group and lets the broker balance partitions across members;
+/// `Partitions` reads exactly the listed `(topic, partition)` pairs, for
+/// targeted consumers during a rollout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PartitionAssignment {
+    #[default]
+    Subscribe,
+    Partitions(Vec<(String, i32)>),
+}
+
+impl PartitionAssignment {
+    /// The partitions to `assign`, or `None` in subscribe mode.
+    pub fn partition_list(&self) -> Option<TopicPartitionList> {
+        let PartitionAssignment::Partitions(partitions) = self else {
+            return None;
+        };
+        let mut list = TopicPartitionList::new();
+        for (topic, partition) in partitions {
+            list.add_partition(topic, *partition);
+        }
+        Some(list)
+    }
+}
+
+/// Parses `topic:partition` pairs separated by commas. An empty string
+/// means subscribe mode.
+impl std::str::FromStr for PartitionAssignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut partitions = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (topic, partition) = entry
+                .rsplit_once(':')
+                .ok_or_else(|| format!("Expected topic:partition, got: {entry}"))?;
+            let partition = partition
+                .trim()
+                .parse::<i32>()
+                .ok()
+                .filter(|p| *p >= 0)
+                .ok_or_else(|| format!("Invalid partition in: {entry}"))?;
+            partitions.push((topic.trim().to_string(), partition));
+        }
+        if partitions.is_empty() {
+            Ok(PartitionAssignment::Subscribe)
+        } else {
+            Ok(PartitionAssignment::Partitions(partitions))
+        }
+    }
+}
+
+/// Credentials for a secured cluster, applied to every client the process
+/// creates. On managed clusters the SASL username is the API key and the
+/// password its secret, so `Debug` shows neither.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct KafkaAuth {
+    /// e.g. `SASL_SSL`; librdkafka's `PLAINTEXT` default when unset.
+    pub security_protocol: Option<String>,
+    /// e.g. `PLAIN` or `SCRAM-SHA-512`.
+    pub sasl_mechanism: Option<String>,
+    pub sasl_username: Option<String>,
+    pub sasl_password: Option<String>,
+}
+
+impl KafkaAuth {
+    pub fn apply(&self, config: &mut ClientConfig) {
+        let settings = [
+            ("security.protocol", &self.security_protocol),
+            ("sasl.mechanism", &self.sasl_mechanism),
+            ("sasl.username", &self.sasl_username),
+            ("sasl.password", &self.sasl_password),
+        ];
+        for (key, value) in settings {
+            if let Some(value) = value {
+                config.set(key, value);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for KafkaAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaAuth")
+            .field("security_protocol", &self.security_protocol)
+            .field("sasl_mechanism", &self.sasl_mechanism)
+            .field("sasl_username", &self.sasl_username.as_ref().map(|_| "***"))
+            .field("sasl_password", &self.sasl_password.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        domain::{ChangeOp, CsvDialect},
+        kafka::producer::{KafkaEventProducer, client_config},
+    };
+
+    fn routing() -> TopicRouting {
+        TopicRouting {
+            jobs: "jobs".to_string(),
+            cdc: "changes".to_string(),
+            dlq: "dead".to_string(),
+        }
+    }
+
+    fn export() -> KafkaEvent {
+        KafkaEvent::ExportCsv {
+            path: "out.csv".to_string(),
+            since: None,
+            until: None,
+            dialect: CsvDialect::default(),
+        }
+    }
+
+    fn change() -> KafkaEvent {
+        KafkaEvent::UserChanged {
+            op: ChangeOp::Created,
+            user_id: "u1".to_string(),
+            at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn producer_routes_jobs_and_changes_to_their_topics() {
+        // Creating a producer does not connect, so no broker is needed.
+        let producer = KafkaEventProducer::with_routing("localhost:1", routing());
+
+        assert_eq!(producer.topic_for(&export()), "jobs");
+        assert_eq!(producer.topic_for(&change()), "changes");
+    }
+
+    #[test]
+    fn default_routing_keeps_everything_on_one_topic() {
+        let routing = TopicRouting::default();
+
+        assert_eq!(routing.topic_for(export().category()), "user-jobs");
+        assert_eq!(routing.topic_for(change().category()), "user-jobs");
+        assert_eq!(routing.topics(), vec!["user-jobs"]);
+    }
+
+    #[test]
+    fn worker_does_not_subscribe_to_the_dlq() {
+        let routing = routing();
+
+        assert_eq!(routing.worker_topics(), vec!["jobs"]);
+        assert_eq!(routing.topics(), vec!["jobs", "changes", "dead"]);
+    }
+
+    #[test]
+    fn dead_letter_topic_must_differ_from_jobs() {
+        assert_eq!(routing().dead_letter_topic(), Some("dead"));
+        assert_eq!(TopicRouting::single("jobs").dead_letter_topic(), None);
+    }
+
+    #[test]
+    fn change_events_are_keyed_by_user() {
+        assert_eq!(change().key(), "u1");
+    }
+
+    #[test]
+    fn producer_config_carries_the_acks_level() {
+        let auth = KafkaAuth::default();
+
+        let all = client_config("localhost:1", Acks::All, &auth);
+        assert_eq!(all.get("acks"), Some("all"));
+        assert_eq!(all.get("enable.idempotence"), Some("true"));
+
+        let none = client_config("localhost:1", Acks::None, &auth);
+        assert_eq!(none.get("acks"), Some("0"));
+        assert_eq!(none.get("enable.idempotence"), Some("false"));
+
+        let leader = client_config("localhost:1", Acks::Leader, &auth);
+        assert_eq!(leader.get("acks"), Some("1"));
+    }
+
+    #[test]
+    fn acks_levels_parse_by_number_or_name() {
+        assert_eq!("all".parse::<Acks>(), Ok(Acks::All));
+        assert_eq!("-1".parse::<Acks>(), Ok(Acks::All));
+        assert_eq!("leader".parse::<Acks>(), Ok(Acks::Leader));
+        assert_eq!("0".parse::<Acks>(), Ok(Acks::None));
+        assert!("some".parse::<Acks>().is_err());
+    }
+
+    #[test]
+    fn partition_assignment_parses_topic_partition_pairs() {
+        assert_eq!(
+            "".parse::<PartitionAssignment>(),
+            Ok(PartitionAssignment::Subscribe)
+        );
+        let assignment: PartitionAssignment = "jobs:0, my:topic:2".parse().unwrap();
+        assert_eq!(
+            assignment,
+            PartitionAssignment::Partitions(vec![
+                ("jobs".to_string(), 0),
+                ("my:topic".to_string(), 2),
+            ])
+        );
+        assert_eq!(assignment.partition_list().unwrap().count(), 2);
+        assert!(PartitionAssignment::Subscribe.partition_list().is_none());
+
+        for bad in ["jobs", "jobs:x", "jobs:-1"] {
+            assert!(bad.parse::<PartitionAssignment>().is_err(), "{bad}");
+        }
+    }
+}
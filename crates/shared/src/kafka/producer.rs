@@ -1,40 +1,149 @@
-use crate::domain::KafkaEvent;
+use crate::{
+    domain::KafkaEvent,
+    kafka::{Acks, EVENT_ID_HEADER, KafkaAuth, TopicRouting},
+};
 use rdkafka::{
     config::ClientConfig,
+    message::{Header, OwnedHeaders},
     producer::{FutureProducer, FutureRecord},
     util::Timeout,
 };
-use std::time::Duration;
+use std::{future::Future, time::Duration};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+/// Default cap on produce requests awaiting delivery.
+const DEFAULT_MAX_IN_FLIGHT: usize = 1000;
 
 pub struct KafkaEventProducer {
     producer: FutureProducer,
-    topic: String,
+    routing: TopicRouting,
+    in_flight: InFlightLimit,
+}
+
+/// Caps how many produce futures may be outstanding at once.
+struct InFlightLimit(Semaphore);
+
+impl InFlightLimit {
+    fn new(limit: usize) -> Self {
+        Self(Semaphore::new(limit.max(1)))
+    }
+
+    /// Waits for a free slot, then drives `send` to completion while
+    /// holding it.
+    async fn run<F: Future>(&self, send: F) -> Result<F::Output, String> {
+        let _permit = self.0.acquire().await.map_err(|e| e.to_string())?;
+        Ok(send.await)
+    }
 }
 
 impl KafkaEventProducer {
     pub fn new(brokers: &str, topic: &str) -> Self {
-        let producer = ClientConfig::new()
-            .set("bootstrap.servers", brokers)
-            .set("message.timeout.ms", "5000")
+        Self::with_routing(brokers, TopicRouting::single(topic))
+    }
+
+    pub fn with_routing(brokers: &str, routing: TopicRouting) -> Self {
+        Self::with_acks(brokers, routing, Acks::default(), &KafkaAuth::default())
+    }
+
+    pub fn with_acks(brokers: &str, routing: TopicRouting, acks: Acks, auth: &KafkaAuth) -> Self {
+        let producer = client_config(brokers, acks, auth)
             .create()
             .expect("Failed to create Kafka producer");
 
         Self {
             producer,
-            topic: topic.to_owned(),
+            routing,
+            in_flight: InFlightLimit::new(DEFAULT_MAX_IN_FLIGHT),
         }
     }
 
+    /// Caps how many sends may await delivery at once. Once the cap is
+    /// reached `send` waits for a slot, pushing back on the caller instead
+    /// of queueing unbounded produce futures during a burst.
+    pub fn with_max_in_flight(mut self, limit: usize) -> Self {
+        self.in_flight = InFlightLimit::new(limit);
+        self
+    }
+
+    /// The topic `send` produces `event` to.
+    pub fn topic_for(&self, event: &KafkaEvent) -> &str {
+        self.routing.topic_for(event.category())
+    }
+
     pub async fn send(&self, event: &KafkaEvent) -> Result<(), String> {
         let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
-        let key = format!("{:?}", event);
-        let record = FutureRecord::to(&self.topic).payload(&payload).key(&key);
+        let key = event.key();
+        let topic = self.topic_for(event);
+        let event_id = Uuid::new_v4().to_string();
+        let headers = OwnedHeaders::new().insert(Header {
+            key: EVENT_ID_HEADER,
+            value: Some(&event_id),
+        });
+        let record = FutureRecord::to(topic)
+            .payload(&payload)
+            .key(&key)
+            .headers(headers);
 
-        self.producer
-            .send(record, Timeout::After(Duration::from_secs(2)))
-            .await
+        self.in_flight
+            .run(
+                self.producer
+                    .send(record, Timeout::After(Duration::from_secs(2))),
+            )
+            .await?
             .map_err(|(e, _)| e.to_string())?;
 
         Ok(())
     }
 }
+
+pub fn client_config(brokers: &str, acks: Acks, auth: &KafkaAuth) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", brokers)
+        .set("message.timeout.ms", "5000")
+        .set("acks", acks.as_str())
+        // Idempotence requires acks=all; librdkafka rejects it otherwise.
+        .set("enable.idempotence", (acks == Acks::All).to_string());
+    auth.apply(&mut config);
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn sends_beyond_the_limit_wait_for_a_slot() {
+        let limit = Arc::new(InFlightLimit::new(3));
+        let current = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let sends: Vec<_> = (0..20)
+            .map(|_| {
+                let (limit, current, peak) = (limit.clone(), current.clone(), peak.clone());
+                tokio::spawn(async move {
+                    limit
+                        .run(async {
+                            let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            // A slow broker acknowledgment.
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            current.fetch_sub(1, Ordering::SeqCst);
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for send in sends {
+            send.await.unwrap().unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+}
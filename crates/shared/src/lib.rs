@@ -1,7 +1,25 @@
 pub mod abstract_trait;
+pub mod capacity;
+pub mod clock;
+pub mod config;
+pub mod context;
+pub mod csv_import;
 pub mod database;
 pub mod domain;
+pub mod dual_write;
+pub mod edit_lock;
+pub mod encrypted_email;
 pub mod errors;
+pub mod expiring_map;
+pub mod feature_flags;
 pub mod kafka;
+pub mod metrics;
+pub mod name_index;
+pub mod read_write_split;
 pub mod repository;
+pub mod schema;
 pub mod service;
+pub mod shutdown;
+pub mod similarity;
+pub mod stats;
+pub mod validation;
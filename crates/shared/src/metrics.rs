@@ -0,0 +1,249 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::domain::{RetryCounts, ServiceStats};
+
+/// Upper bounds of the latency buckets, in milliseconds. Anything slower
+/// lands in a final overflow bucket.
+const LATENCY_BUCKETS_MS: [u64; 13] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Fixed-bucket request latency histogram. Percentiles are reported as the
+/// upper bound of the bucket they fall in, so they are accurate to the
+/// bucket width; the overflow bucket reports the slowest request seen.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| micros <= bound * 1000)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// The latency below which a `quantile` (0.0–1.0) of requests finished,
+    /// in milliseconds. `0` before anything was recorded.
+    pub fn percentile(&self, quantile: f64) -> f64 {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let rank = ((total as f64) * quantile.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let max_ms = self.max_micros.load(Ordering::Relaxed) as f64 / 1000.0;
+        let mut seen = 0;
+        for (index, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS_MS
+                    .get(index)
+                    .map_or(max_ms, |&bound| (bound as f64).min(max_ms));
+            }
+        }
+        max_ms
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let count = self.count.load(Ordering::Relaxed);
+        let sum_ms = self.sum_micros.load(Ordering::Relaxed) as f64 / 1000.0;
+        LatencySummary {
+            count,
+            mean_ms: if count == 0 {
+                0.0
+            } else {
+                sum_ms / count as f64
+            },
+            p50_ms: self.percentile(0.50),
+            p95_ms: self.percentile(0.95),
+            p99_ms: self.percentile(0.99),
+            max_ms: self.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Process-wide HTTP request metrics, fed by the server's request
+/// middleware.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+    in_flight: AtomicU64,
+    pub latency: LatencyHistogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            client_errors: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            latency: LatencyHistogram::default(),
+        }
+    }
+}
+
+impl Metrics {
+    /// Counts a request as in flight until the returned guard drops.
+    pub fn start_request(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(&self.in_flight)
+    }
+
+    pub fn record_response(&self, status: u16, elapsed: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match status {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        self.latency.record(elapsed);
+    }
+
+    pub fn snapshot(
+        &self,
+        operations: ServiceStats,
+        retries: BTreeMap<&'static str, RetryCounts>,
+        users: usize,
+    ) -> MetricsSnapshot {
+        MetricsSnapshot {
+            counters: Counters {
+                requests: self.requests.load(Ordering::Relaxed),
+                client_errors: self.client_errors.load(Ordering::Relaxed),
+                server_errors: self.server_errors.load(Ordering::Relaxed),
+                operations,
+                retries,
+            },
+            gauges: Gauges {
+                users,
+                in_flight_requests: self.in_flight.load(Ordering::Relaxed),
+                uptime_secs: self.started.elapsed().as_secs(),
+            },
+            latency: self.latency.summary(),
+        }
+    }
+}
+
+pub struct InFlight<'a>(&'a AtomicU64);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub counters: Counters,
+    pub gauges: Gauges,
+    pub latency: LatencySummary,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Counters {
+    pub requests: u64,
+    /// Responses with a 4xx status.
+    pub client_errors: u64,
+    /// Responses with a 5xx status.
+    pub server_errors: u64,
+    pub operations: ServiceStats,
+    pub retries: BTreeMap<&'static str, RetryCounts>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Gauges {
+    pub users: usize,
+    pub in_flight_requests: u64,
+    pub uptime_secs: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_report_bucket_upper_bounds() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0.5), 0.0);
+
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(800));
+        }
+        for _ in 0..9 {
+            histogram.record(Duration::from_millis(40));
+        }
+        histogram.record(Duration::from_secs(12));
+
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.p50_ms, 1.0);
+        assert_eq!(summary.p95_ms, 50.0);
+        assert_eq!(summary.p99_ms, 50.0);
+        // The overflow bucket reports the slowest request itself.
+        assert_eq!(histogram.percentile(1.0), 12_000.0);
+        assert_eq!(summary.max_ms, 12_000.0);
+    }
+
+    #[test]
+    fn responses_are_counted_by_status_class() {
+        let metrics = Metrics::default();
+        let in_flight = metrics.start_request();
+        for status in [200, 201, 404, 422, 503] {
+            metrics.record_response(status, Duration::from_millis(1));
+        }
+
+        let snapshot = metrics.snapshot(ServiceStats::default(), BTreeMap::new(), 7);
+        assert_eq!(snapshot.counters.requests, 5);
+        assert_eq!(snapshot.counters.client_errors, 2);
+        assert_eq!(snapshot.counters.server_errors, 1);
+        assert_eq!(snapshot.gauges.in_flight_requests, 1);
+        assert_eq!(snapshot.gauges.users, 7);
+
+        drop(in_flight);
+        let snapshot = metrics.snapshot(ServiceStats::default(), BTreeMap::new(), 7);
+        assert_eq!(snapshot.gauges.in_flight_requests, 0);
+    }
+}
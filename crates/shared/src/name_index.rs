@@ -0,0 +1,103 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::RwLock,
+};
+
+/// Inverted index from lowercased name tokens to user ids. A query matches
+/// a user when every query token is a prefix of one of the user's tokens,
+/// so `"smith jo"` finds `"John Smith"` regardless of word order.
+#[derive(Debug, Default)]
+pub struct NameIndex {
+    tokens: RwLock<BTreeMap<String, HashSet<String>>>,
+}
+
+/// Lowercased words of `text`, split on anything that is not alphanumeric.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+impl NameIndex {
+    pub fn insert(&self, id: &str, name: &str) {
+        let mut tokens = self.tokens.write().unwrap();
+        for token in tokenize(name) {
+            tokens.entry(token).or_default().insert(id.to_string());
+        }
+    }
+
+    pub fn remove(&self, id: &str, name: &str) {
+        let mut tokens = self.tokens.write().unwrap();
+        for token in tokenize(name) {
+            if let Some(ids) = tokens.get_mut(&token) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    tokens.remove(&token);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        self.tokens.write().unwrap().clear();
+    }
+
+    /// Ids of users whose name matches every token of `query`. A query
+    /// without tokens matches nobody.
+    pub fn search(&self, query: &str) -> HashSet<String> {
+        let tokens = self.tokens.read().unwrap();
+        let mut matches: Option<HashSet<String>> = None;
+        for prefix in tokenize(query) {
+            let ids: HashSet<String> = tokens
+                .range(prefix.clone()..)
+                .take_while(|(token, _)| token.starts_with(&prefix))
+                .flat_map(|(_, ids)| ids.iter().cloned())
+                .collect();
+            let narrowed = match matches {
+                Some(found) => found.intersection(&ids).cloned().collect(),
+                None => ids,
+            };
+            if narrowed.is_empty() {
+                return narrowed;
+            }
+            matches = Some(narrowed);
+        }
+        matches.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_tokens_match_word_prefixes_in_any_order() {
+        let index = NameIndex::default();
+        index.insert("1", "John Smith");
+        index.insert("2", "Johanna Smithers");
+        index.insert("3", "Mary-Jo Black");
+
+        assert_eq!(index.search("smith jo").len(), 2);
+        assert_eq!(index.search("SMITH john"), HashSet::from(["1".to_string()]));
+        assert_eq!(
+            index.search("jo"),
+            HashSet::from(["1", "2", "3"].map(String::from))
+        );
+        assert!(index.search("ohn").is_empty());
+        assert!(index.search(" - ").is_empty());
+    }
+
+    #[test]
+    fn removed_names_stop_matching() {
+        let index = NameIndex::default();
+        index.insert("1", "Ann Lee");
+        index.insert("2", "Ann Park");
+
+        index.remove("1", "Ann Lee");
+
+        assert_eq!(index.search("ann"), HashSet::from(["2".to_string()]));
+        assert!(index.search("lee").is_empty());
+        assert!(!index.tokens.read().unwrap().contains_key("lee"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+
+use crate::{
+    abstract_trait::UserRepositoryTrait,
+    domain::{
+        CreateUserRequest, MergeField, ReconcileReport, SearchField, UpdateUserRequest, User,
+    },
+    errors::AppError,
+};
+
+/// Sends writes to one repository and spreads reads over replicas in turn,
+/// for backends with read replicas. Replicas may lag, so a read right after
+/// a write can miss it. Reads the service uses to enforce invariants (email
+/// lookups for uniqueness, `count` for capacity) stay on the writer.
+///
+/// With no readers every call goes to the writer. That is the only sensible
+/// setup for the in-memory backend: each `InMemoryUserRepository` keeps its
+/// own email index, so a second one over the same map is not a replica.
+/// Nothing in the config builds this; assemble it and pass it to
+/// `ServiceBuilder::repository`.
+pub struct ReadWriteSplitRepository {
+    writer: Arc<dyn UserRepositoryTrait>,
+    readers: Vec<Arc<dyn UserRepositoryTrait>>,
+    next_reader: AtomicUsize,
+}
+
+impl ReadWriteSplitRepository {
+    pub fn new(
+        writer: Arc<dyn UserRepositoryTrait>,
+        readers: Vec<Arc<dyn UserRepositoryTrait>>,
+    ) -> Self {
+        Self {
+            writer,
+            readers,
+            next_reader: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn writer(&self) -> &Arc<dyn UserRepositoryTrait> {
+        &self.writer
+    }
+
+    /// The replica for the next read, round-robin.
+    pub fn reader(&self) -> &Arc<dyn UserRepositoryTrait> {
+        if self.readers.is_empty() {
+            return &self.writer;
+        }
+        let index = self.next_reader.fetch_add(1, Ordering::Relaxed) % self.readers.len();
+        &self.readers[index]
+    }
+}
+
+#[async_trait::async_trait]
+impl UserRepositoryTrait for ReadWriteSplitRepository {
+    async fn find_all(
+        &self,
+        page: i32,
+        page_size: i32,
+        search: Option<String>,
+        search_field: SearchField,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        self.reader()
+            .find_all(page, page_size, search, search_field)
+            .await
+    }
+
+    async fn find_updated_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        self.reader()
+            .find_updated_between(since, until, page, page_size)
+            .await
+    }
+
+    async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError> {
+        self.writer.find_by_email_exists(email).await
+    }
+
+    async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError> {
+        self.writer.create_user(input).await
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+        self.writer.find_by_email(email).await
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError> {
+        self.reader().find_by_id(id).await
+    }
+
+    async fn find_by_ids(&self, ids: &[String], concurrency: usize) -> Result<Vec<User>, AppError> {
+        self.reader().find_by_ids(ids, concurrency).await
+    }
+
+    async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError> {
+        self.writer.update_user(input, id).await
+    }
+
+    async fn update_if_match(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: &str,
+    ) -> Result<User, AppError> {
+        self.writer.update_if_match(input, id, if_match).await
+    }
+
+    async fn update_by_email(
+        &self,
+        input: &UpdateUserRequest,
+        email: &str,
+    ) -> Result<User, AppError> {
+        self.writer.update_by_email(input, email).await
+    }
+
+    async fn delete_user(&self, email: &str) -> Result<(), AppError> {
+        self.writer.delete_user(email).await
+    }
+
+    async fn delete_by_id(&self, id: &str) -> Result<(), AppError> {
+        self.writer.delete_by_id(id).await
+    }
+
+    async fn clear(&self) -> Result<usize, AppError> {
+        self.writer.clear().await
+    }
+
+    async fn count(&self) -> Result<usize, AppError> {
+        self.writer.count().await
+    }
+
+    async fn stored_count(&self) -> Result<usize, AppError> {
+        self.writer.stored_count().await
+    }
+
+    async fn merge_users(
+        &self,
+        keep: &str,
+        remove: &str,
+        fields: &[MergeField],
+    ) -> Result<User, AppError> {
+        self.writer.merge_users(keep, remove, fields).await
+    }
+
+    async fn purge_expired(&self, batch_size: usize) -> Result<usize, AppError> {
+        self.writer.purge_expired(batch_size).await
+    }
+
+    fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>> {
+        self.reader().stream_all()
+    }
+
+    /// Repairs happen on the writer; replicas pick them up from there.
+    async fn reconcile(&self) -> Result<ReconcileReport, AppError> {
+        self.writer.reconcile().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+
+    fn request(email: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            id: None,
+            name: "Ann".to_string(),
+            email: email.to_string(),
+            age: 30,
+            expires_at: None,
+        }
+    }
+
+    // Separate maps stand in for a lagging replica: whatever the split
+    // returns shows which side served the call.
+    #[tokio::test]
+    async fn writes_and_consistency_reads_go_to_the_writer() {
+        let writer = Arc::new(InMemoryUserRepository::new());
+        let reader = Arc::new(InMemoryUserRepository::new());
+        let split = ReadWriteSplitRepository::new(writer.clone(), vec![reader.clone()]);
+
+        let user = split
+            .create_user(&request("ann@example.com"))
+            .await
+            .unwrap();
+
+        assert!(writer.find_by_id(&user.id).await.unwrap().is_some());
+        assert!(split.find_by_id(&user.id).await.unwrap().is_none());
+        assert!(
+            split
+                .find_by_email("ann@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(split.find_by_email_exists("ann@example.com").await.unwrap());
+        assert_eq!(split.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_rotate_over_readers() {
+        let writer = Arc::new(InMemoryUserRepository::new());
+        let readers: Vec<Arc<InMemoryUserRepository>> = (0..2)
+            .map(|_| Arc::new(InMemoryUserRepository::new()))
+            .collect();
+        let user = readers[1]
+            .create_user(&request("ann@example.com"))
+            .await
+            .unwrap();
+        let split = ReadWriteSplitRepository::new(
+            writer,
+            readers
+                .iter()
+                .map(|r| r.clone() as Arc<dyn UserRepositoryTrait>)
+                .collect(),
+        );
+
+        let hits: Vec<bool> = futures::future::join_all(
+            (0..4).map(|_| async { split.find_by_id(&user.id).await.unwrap().is_some() }),
+        )
+        .await;
+
+        assert_eq!(hits, vec![false, true, false, true]);
+    }
+}
@@ -1,25 +1,234 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use dashmap::DashMap;
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
 use uuid::Uuid;
 
 use crate::{
-    abstract_trait::UserRepositoryTrait,
+    abstract_trait::{UserRepositoryTrait, check_if_match, stale_etag},
+    clock::{Clock, SystemClock},
     database::Database,
-    domain::{CreateUserRequest, UpdateUserRequest, User},
+    domain::{
+        CreateUserRequest, MergeField, ReconcileReport, SearchField, UpdateUserRequest, User,
+    },
     errors::AppError,
+    name_index::NameIndex,
 };
 
+/// Users cloned out of the map per step of `stream_all`.
+const STREAM_PAGE_SIZE: usize = 500;
+
 pub struct InMemoryUserRepository {
     pub db: Database,
+    /// Lowercased email to the id of the user holding it. Writes that set
+    /// an email claim it through this map's entry API, which is what keeps
+    /// two concurrent writers from ending up with the same address. Lock
+    /// order is always this index first, then `db`.
+    emails: DashMap<String, String>,
+    /// Name tokens for `find_all` searches, when enabled.
+    names: Option<NameIndex>,
+    clock: Arc<dyn Clock>,
 }
 
 impl InMemoryUserRepository {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Timestamps and expiry checks read `clock`, so tests can move time.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             db: Arc::new(DashMap::new()),
+            emails: DashMap::new(),
+            names: None,
+            clock,
         }
     }
+
+    /// Keeps an inverted index of name tokens so name searches look up
+    /// matching ids instead of scanning every user. Multi-word queries then
+    /// match whole-word prefixes in any order rather than a substring.
+    pub fn with_name_index(mut self, enabled: bool) -> Self {
+        self.names = enabled.then(|| {
+            let index = NameIndex::default();
+            for user in self.db.iter() {
+                index.insert(user.key(), &user.name);
+            }
+            index
+        });
+        self
+    }
+}
+
+impl InMemoryUserRepository {
+    /// DashMap iteration only locks one shard at a time, so a plain scan can
+    /// interleave with concurrent inserts and removals. Collecting the keys
+    /// first and then fetching each entry is best-effort, not atomic: writes
+    /// that land during the scan may or may not be included. It does
+    /// guarantee every user appears at most once, and sorting by creation
+    /// time keeps pagination stable across calls.
+    fn snapshot(&self) -> Vec<User> {
+        let now = self.clock.now();
+        let keys: Vec<String> = self.db.iter().map(|kv| kv.key().clone()).collect();
+        let mut users: Vec<User> = keys
+            .iter()
+            .filter_map(|key| self.db.get(key).map(|user| user.value().clone()))
+            .filter(|user| !user.is_expired(now))
+            .collect();
+        users.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        users
+    }
+
+    /// Runs `f` against the stored user while holding the entry's write
+    /// guard, so read-modify-write sequences such as counters cannot lose
+    /// updates to concurrent callers. Keep `f` short: it blocks every other
+    /// access to the same DashMap shard.
+    pub fn atomically_update<F, R>(&self, id: &str, f: F) -> Result<R, AppError>
+    where
+        F: FnOnce(&mut User) -> R,
+    {
+        let now = self.clock.now();
+        let mut user = self
+            .db
+            .get_mut(id)
+            .filter(|user| !user.is_expired(now))
+            .ok_or(AppError::UserNotFound)?;
+        Ok(f(user.value_mut()))
+    }
+
+    /// Whether `id` is a live user that still has `email`. An index entry
+    /// failing this is stale, left by a user that expired or changed email.
+    fn holds_email(&self, email: &str, id: &str) -> bool {
+        let now = self.clock.now();
+        self.db
+            .get(id)
+            .is_some_and(|user| user.email == email && !user.is_expired(now))
+    }
+
+    fn release_email(&self, email: &str, id: &str) {
+        self.emails.remove_if(email, |_, holder| holder == id);
+    }
+
+    fn index_name(&self, id: &str, name: &str) {
+        if let Some(names) = &self.names {
+            names.insert(id, name);
+        }
+    }
+
+    fn unindex_name(&self, id: &str, name: &str) {
+        if let Some(names) = &self.names {
+            names.remove(id, name);
+        }
+    }
+
+    /// Users the name index matches for `query`, in `snapshot` order.
+    fn indexed_matches(&self, names: &NameIndex, query: &str) -> Vec<User> {
+        let now = self.clock.now();
+        let mut users: Vec<User> = names
+            .search(query)
+            .iter()
+            .filter_map(|id| self.db.get(id).map(|user| user.value().clone()))
+            .filter(|user| !user.is_expired(now))
+            .collect();
+        users.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        users
+    }
+
+    /// An email change claims the new address before the user is written
+    /// and only then lets go of the old one.
+    fn update_checked(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: Option<&str>,
+    ) -> Result<User, AppError> {
+        let Some(email) = input.email.as_ref().map(|email| email.to_lowercase()) else {
+            return self.apply_update(input, id, if_match).map(|(user, _)| user);
+        };
+
+        let (user, previous_email) = match self.emails.entry(email.clone()) {
+            Entry::Occupied(slot) if slot.get() != id && self.holds_email(&email, slot.get()) => {
+                return Err(AppError::Conflict("Email already exists".to_string()));
+            }
+            slot => {
+                let updated = self.apply_update(input, id, if_match)?;
+                slot.insert(id.to_string());
+                updated
+            }
+        };
+        if previous_email != email {
+            self.release_email(&previous_email, id);
+        }
+        Ok(user)
+    }
+
+    /// Applies `input` and returns the updated user with its previous email
+    /// and name. `if_match` is compared under the same guard as the write.
+    fn apply_update(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: Option<&str>,
+    ) -> Result<(User, String), AppError> {
+        let updated = self.atomically_update(id, |user| {
+            if let Some(if_match) = if_match {
+                check_if_match(Some(user), if_match)?;
+            }
+            let previous_email = user.email.clone();
+            let previous_name = user.name.clone();
+            if let Some(name) = &input.name {
+                user.name = name.clone();
+            }
+            if let Some(email) = &input.email {
+                user.email = email.to_lowercase();
+            }
+            if let Some(age) = input.age {
+                user.age = age;
+            }
+            user.updated_at = self.clock.now();
+            // Clone under the guard: re-reading afterwards could race with a
+            // concurrent delete and report a successful update as not found.
+            Ok((user.clone(), previous_email, previous_name))
+        });
+        if let (Err(AppError::UserNotFound), Some(_)) = (&updated, if_match) {
+            return Err(stale_etag());
+        }
+        let (user, previous_email, previous_name) = updated??;
+        if user.name != previous_name {
+            self.unindex_name(id, &previous_name);
+            self.index_name(id, &user.name);
+        }
+        Ok((user, previous_email))
+    }
+}
+
+/// Cuts one page out of `users` and pairs it with the total count.
+fn paginate(users: Vec<User>, page: i32, page_size: i32) -> (Vec<User>, i64) {
+    let total = users.len() as i64;
+    let page_size = page_size.max(0) as usize;
+    let start = ((page.max(1) - 1) as usize)
+        .saturating_mul(page_size)
+        .min(users.len());
+    let end = start.saturating_add(page_size).min(users.len());
+    (users[start..end].to_vec(), total)
+}
+
+impl Default for InMemoryUserRepository {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[async_trait::async_trait]
@@ -29,96 +238,949 @@ impl UserRepositoryTrait for InMemoryUserRepository {
         page: i32,
         page_size: i32,
         search: Option<String>,
+        search_field: SearchField,
     ) -> Result<(Vec<User>, i64), AppError> {
-        let users: Vec<User> = self
-            .db
-            .iter()
-            .map(|kv| kv.value().clone())
+        let query = search.map(|q| q.to_lowercase());
+        let users: Vec<User> = match (&self.names, &query, search_field) {
+            (Some(names), Some(q), SearchField::Name) => self.indexed_matches(names, q),
+            (Some(names), Some(q), SearchField::All) => {
+                let by_name = names.search(q);
+                self.snapshot()
+                    .into_iter()
+                    .filter(|user| {
+                        by_name.contains(&user.id) || SearchField::Email.matches(user, q)
+                    })
+                    .collect()
+            }
+            _ => self
+                .snapshot()
+                .into_iter()
+                .filter(|user| match &query {
+                    Some(q) => search_field.matches(user, q),
+                    None => true,
+                })
+                .collect(),
+        };
+        Ok(paginate(users, page, page_size))
+    }
+
+    async fn find_updated_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        page: i32,
+        page_size: i32,
+    ) -> Result<(Vec<User>, i64), AppError> {
+        let mut users: Vec<User> = self
+            .snapshot()
+            .into_iter()
             .filter(|user| {
-                if let Some(ref q) = search {
-                    let q = q.to_lowercase();
-                    user.name.to_lowercase().contains(&q) || user.email.to_lowercase().contains(&q)
-                } else {
-                    true
-                }
+                since.is_none_or(|since| user.updated_at >= since)
+                    && until.is_none_or(|until| user.updated_at <= until)
             })
             .collect();
-        let total = users.len() as i64;
-        let start = ((page - 1) * page_size) as usize;
-        let end = (start + page_size as usize).min(users.len());
-        let paginated = users[start..end].to_vec();
-        Ok((paginated, total))
+        users.sort_by(|a, b| {
+            a.updated_at
+                .cmp(&b.updated_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        Ok(paginate(users, page, page_size))
     }
 
     async fn find_by_email_exists(&self, email: &str) -> Result<bool, AppError> {
-        Ok(self.db.iter().any(|u| u.value().email == email))
+        Ok(self.find_by_email(email).await?.is_some())
     }
 
+    /// The email slot stays locked until the user is stored, so a concurrent
+    /// create for the same address waits and then sees it taken.
     async fn create_user(&self, input: &CreateUserRequest) -> Result<User, AppError> {
-        if self.find_by_email_exists(&input.email).await? {
-            return Err(AppError::ValidationError(
-                "Email already exists".to_string(),
-            ));
-        }
+        let now = self.clock.now();
         let user = User {
-            id: Uuid::new_v4().to_string(),
+            id: input
+                .id
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
             name: input.name.clone(),
             email: input.email.to_lowercase(),
             age: input.age,
-            created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
+            created_at: now,
+            updated_at: now,
+            expires_at: input.expires_at,
+        };
+        let email_slot = match self.emails.entry(user.email.clone()) {
+            Entry::Occupied(slot) if self.holds_email(slot.key(), slot.get()) => {
+                return Err(AppError::Conflict("Email already exists".to_string()));
+            }
+            slot => slot,
         };
-        self.db.insert(user.id.clone(), user.clone());
+        match self.db.entry(user.id.clone()) {
+            // An expired user still holds its id until the sweeper runs.
+            Entry::Occupied(mut slot) if slot.get().is_expired(now) => {
+                let expired = slot.insert(user.clone());
+                self.unindex_name(&expired.id, &expired.name);
+            }
+            Entry::Occupied(_) => {
+                return Err(AppError::Conflict("Id already exists".to_string()));
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(user.clone());
+            }
+        }
+        email_slot.insert(user.id.clone());
+        self.index_name(&user.id, &user.name);
         Ok(user)
     }
 
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
-        Ok(self.db.iter().find_map(|u| {
-            if u.value().email == email {
-                Some(u.value().clone())
-            } else {
-                None
-            }
-        }))
+        let email = email.to_lowercase();
+        let Some(id) = self.emails.get(&email).map(|id| id.value().clone()) else {
+            return Ok(None);
+        };
+        Ok(self
+            .holds_email(&email, &id)
+            .then(|| self.db.get(&id).map(|user| user.value().clone()))
+            .flatten())
     }
 
     async fn find_by_id(&self, id: &str) -> Result<Option<User>, AppError> {
-        Ok(self.db.get(id).map(|u| u.value().clone()))
+        let now = self.clock.now();
+        Ok(self
+            .db
+            .get(id)
+            .filter(|u| !u.value().is_expired(now))
+            .map(|u| u.value().clone()))
+    }
+
+    async fn find_by_ids(
+        &self,
+        ids: &[String],
+        _concurrency: usize,
+    ) -> Result<Vec<User>, AppError> {
+        let now = self.clock.now();
+        Ok(ids
+            .iter()
+            .filter_map(|id| self.db.get(id))
+            .filter(|u| !u.value().is_expired(now))
+            .map(|u| u.value().clone())
+            .collect())
     }
 
     async fn update_user(&self, input: &UpdateUserRequest, id: &str) -> Result<User, AppError> {
+        self.update_checked(input, id, None)
+    }
+
+    async fn update_if_match(
+        &self,
+        input: &UpdateUserRequest,
+        id: &str,
+        if_match: &str,
+    ) -> Result<User, AppError> {
+        self.update_checked(input, id, Some(if_match))
+    }
+
+    async fn update_by_email(
+        &self,
+        input: &UpdateUserRequest,
+        email: &str,
+    ) -> Result<User, AppError> {
+        let user = self
+            .find_by_email(&email.to_lowercase())
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+        self.update_user(input, &user.id).await
+    }
+
+    async fn delete_user(&self, email: &str) -> Result<(), AppError> {
+        let email = email.to_lowercase();
+        let key = self.emails.get(&email).map(|id| id.value().clone());
+        // The email may have changed between the lookup and the removal, so
+        // only remove the entry if it still matches.
+        match key.and_then(|k| self.db.remove_if(&k, |_, user| user.email == email)) {
+            Some((id, user)) => {
+                self.release_email(&email, &id);
+                self.unindex_name(&id, &user.name);
+                Ok(())
+            }
+            None => Err(AppError::UserNotFound),
+        }
+    }
+
+    async fn delete_by_id(&self, id: &str) -> Result<(), AppError> {
+        match self.db.remove(id) {
+            Some((_, user)) => {
+                self.release_email(&user.email, id);
+                self.unindex_name(id, &user.name);
+                Ok(())
+            }
+            None => Err(AppError::UserNotFound),
+        }
+    }
+
+    async fn clear(&self) -> Result<usize, AppError> {
+        let removed = self.db.len();
+        self.db.clear();
+        self.emails.clear();
+        if let Some(names) = &self.names {
+            names.clear();
+        }
+        Ok(removed)
+    }
+
+    /// Sorts only `(created_at, key)` pairs up front and clones users one
+    /// page at a time as the stream is polled. Users deleted in the meantime
+    /// are skipped; users created afterwards are not included.
+    fn stream_all(&self) -> BoxStream<'static, Result<User, AppError>> {
+        let db = self.db.clone();
+        let clock = self.clock.clone();
+        let mut order: Vec<(DateTime<Utc>, String)> = db
+            .iter()
+            .map(|kv| (kv.value().created_at, kv.key().clone()))
+            .collect();
+        order.sort();
+
+        stream::iter(order.into_iter().map(|(_, key)| key))
+            .chunks(STREAM_PAGE_SIZE)
+            .flat_map(move |keys| {
+                let page: Vec<Result<User, AppError>> = keys
+                    .iter()
+                    .filter_map(|key| db.get(key).map(|user| user.value().clone()))
+                    .filter(|user| !user.is_expired(clock.now()))
+                    .map(Ok)
+                    .collect();
+                stream::iter(page)
+            })
+            .boxed()
+    }
+
+    async fn count(&self) -> Result<usize, AppError> {
+        let now = self.clock.now();
+        Ok(self
+            .db
+            .iter()
+            .filter(|u| !u.value().is_expired(now))
+            .count())
+    }
+
+    /// Sums the shard lengths DashMap already tracks, so it costs one read
+    /// lock per shard rather than a walk over every user.
+    async fn stored_count(&self) -> Result<usize, AppError> {
+        Ok(self.db.len())
+    }
+
+    /// Holds the removed user's email slot for the whole merge, so nobody
+    /// can claim the address between the delete and the update. If the
+    /// update fails the removed user is put back.
+    async fn merge_users(
+        &self,
+        keep: &str,
+        remove: &str,
+        fields: &[MergeField],
+    ) -> Result<User, AppError> {
+        let removed = self
+            .find_by_id(remove)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+        if self.find_by_id(keep).await?.is_none() {
+            return Err(AppError::UserNotFound);
+        }
+        let update = MergeField::update_from(&removed, fields);
+
+        let slot = self.emails.entry(removed.email.clone());
+        let Some((_, removed)) = self
+            .db
+            .remove_if(remove, |_, user| user.email == removed.email)
+        else {
+            return Err(AppError::Conflict(
+                "User changed during the merge; retry".to_string(),
+            ));
+        };
+        let (user, previous_email) = match self.apply_update(&update, keep, None) {
+            Ok(updated) => updated,
+            Err(e) => {
+                self.db.insert(remove.to_string(), removed);
+                return Err(e);
+            }
+        };
+
+        if update.email.is_some() {
+            slot.insert(keep.to_string());
+        } else if let Entry::Occupied(slot) = slot
+            && slot.get() == remove
         {
-            let mut user = match self.db.get_mut(id) {
-                Some(u) => u,
-                None => return Err(AppError::UserNotFound),
+            slot.remove();
+        }
+        if previous_email != user.email {
+            self.release_email(&previous_email, keep);
+        }
+        self.unindex_name(remove, &removed.name);
+        Ok(user)
+    }
+
+    /// Finds expired ids under per-shard read locks, then removes them in
+    /// batches. Each removal re-checks expiry, so a user whose `expires_at`
+    /// was pushed back in the meantime survives.
+    async fn purge_expired(&self, batch_size: usize) -> Result<usize, AppError> {
+        let now = self.clock.now();
+        let expired: Vec<String> = self
+            .db
+            .iter()
+            .filter(|entry| entry.value().is_expired(now))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let mut purged = 0;
+        for batch in expired.chunks(batch_size.max(1)) {
+            for id in batch {
+                if let Some((id, user)) = self.db.remove_if(id, |_, user| user.is_expired(now)) {
+                    self.release_email(&user.email, &id);
+                    self.unindex_name(&id, &user.name);
+                    purged += 1;
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+        Ok(purged)
+    }
+
+    /// Re-keys entries whose key no longer matches the user's id, brings the
+    /// email and name indexes back in line with the live users, and reports
+    /// emails that ended up on more than one user.
+    async fn reconcile(&self) -> Result<ReconcileReport, AppError> {
+        let mut report = ReconcileReport::default();
+        let mut emails: HashMap<String, usize> = HashMap::new();
+        let now = self.clock.now();
+
+        let entries: Vec<(String, User)> = self
+            .db
+            .iter()
+            .map(|kv| (kv.key().clone(), kv.value().clone()))
+            .collect();
+
+        for (key, user) in entries {
+            report.checked += 1;
+            if !user.is_expired(now) {
+                *emails.entry(user.email.clone()).or_insert(0) += 1;
+            }
+
+            if key != user.id
+                && let Some((_, user)) = self.db.remove_if(&key, |_, u| u.id != key)
+            {
+                // Holding the target entry while removing `key` could
+                // deadlock on a shared shard, hence remove first and put the
+                // user back if the id turns out to be taken.
+                let id = user.id.clone();
+                let collided = match self.db.entry(id.clone()) {
+                    Entry::Vacant(slot) => {
+                        slot.insert(user);
+                        None
+                    }
+                    Entry::Occupied(_) => Some(user),
+                };
+                match collided {
+                    None => {
+                        eprintln!("🔧 Re-keyed user {} stored under {}", id, key);
+                        report.fixed += 1;
+                    }
+                    Some(user) => {
+                        eprintln!(
+                            "⚠️ Cannot re-key user stored under {}: id {} is taken",
+                            key, id
+                        );
+                        self.db.insert(key.clone(), user);
+                        report.id_collisions.push(key);
+                    }
+                }
+            }
+        }
+
+        // Drop claims that no live user backs, then index any user the
+        // index does not know about, e.g. one written straight to `db`.
+        let before = self.emails.len();
+        self.emails.retain(|email, id| self.holds_email(email, id));
+        report.fixed += before - self.emails.len();
+        let users: Vec<(String, String)> = self
+            .db
+            .iter()
+            .filter(|kv| !kv.value().is_expired(now))
+            .map(|kv| (kv.value().email.clone(), kv.key().clone()))
+            .collect();
+        for (email, id) in users {
+            if let Entry::Vacant(slot) = self.emails.entry(email) {
+                slot.insert(id);
+                report.fixed += 1;
+            }
+        }
+
+        // The name index only serves searches, so it is simply rebuilt.
+        if let Some(names) = &self.names {
+            names.clear();
+            for user in self.db.iter().filter(|u| !u.value().is_expired(now)) {
+                names.insert(user.key(), &user.name);
+            }
+        }
+
+        report.duplicate_emails = emails
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(email, _)| email)
+            .collect();
+        report.duplicate_emails.sort();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn request(name: &str, email: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            id: None,
+            name: name.to_string(),
+            email: email.to_string(),
+            age: 30,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_creates_with_one_email_admit_exactly_one() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+
+        let tasks: Vec<_> = (0..50)
+            .map(|i| {
+                let repo = repo.clone();
+                tokio::spawn(async move {
+                    repo.create_user(&request(&format!("User {i}"), "same@example.com"))
+                        .await
+                })
+            })
+            .collect();
+        let mut created = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(_) => created += 1,
+                Err(AppError::Conflict(_)) => {}
+                Err(e) => panic!("unexpected error: {e:?}"),
+            }
+        }
+
+        assert_eq!(created, 1);
+        assert_eq!(repo.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn racing_creates_and_updates_leave_one_holder_of_an_email() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        let mut ids = Vec::new();
+        for i in 0..25 {
+            let user = repo
+                .create_user(&request("Mover", &format!("mover{i}@example.com")))
+                .await
+                .unwrap();
+            ids.push(user.id);
+        }
+
+        let mut tasks = Vec::new();
+        for (i, id) in ids.into_iter().enumerate() {
+            let creator = repo.clone();
+            tasks.push(tokio::spawn(async move {
+                creator
+                    .create_user(&request(&format!("New {i}"), "wanted@example.com"))
+                    .await
+                    .map(|_| ())
+            }));
+            let updater = repo.clone();
+            tasks.push(tokio::spawn(async move {
+                let update = UpdateUserRequest {
+                    name: None,
+                    email: Some("wanted@example.com".to_string()),
+                    age: None,
+                };
+                updater.update_user(&update, &id).await.map(|_| ())
+            }));
+        }
+        let mut succeeded = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(()) => succeeded += 1,
+                Err(AppError::Conflict(_)) => {}
+                Err(e) => panic!("unexpected error: {e:?}"),
+            }
+        }
+
+        assert_eq!(succeeded, 1);
+        let (users, _) = repo.find_all(1, 100, None, SearchField::All).await.unwrap();
+        let holders = users
+            .iter()
+            .filter(|u| u.email == "wanted@example.com")
+            .count();
+        assert_eq!(holders, 1);
+        assert!(
+            repo.find_by_email("wanted@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_increments_lose_no_updates() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        let mut start = request("Counter", "counter@example.com");
+        start.age = 0;
+        let id = repo.create_user(&start).await.unwrap().id;
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let repo = repo.clone();
+                let id = id.clone();
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        repo.atomically_update(&id, |user| user.age += 1).unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let user = repo.find_by_id(&id).await.unwrap().unwrap();
+        assert_eq!(user.age, 200);
+    }
+
+    #[tokio::test]
+    async fn reconcile_fixes_drift() {
+        let repo = InMemoryUserRepository::new();
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        // Stored under the wrong key, and an index entry nobody backs.
+        let (_, moved) = repo.db.remove(&ann.id).unwrap();
+        repo.db.insert("stale-key".to_string(), moved);
+        repo.emails
+            .insert("ghost@example.com".to_string(), "ghost".to_string());
+        // Written straight to the map, so the email index misses it.
+        let mut bob = ann.clone();
+        bob.id = "bob".to_string();
+        bob.email = "bob@example.com".to_string();
+        repo.db.insert(bob.id.clone(), bob);
+
+        let report = repo.reconcile().await.unwrap();
+
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.fixed, 3);
+        assert!(report.id_collisions.is_empty());
+        assert!(repo.db.contains_key(&ann.id));
+        assert!(!repo.db.contains_key("stale-key"));
+        assert!(!repo.emails.contains_key("ghost@example.com"));
+        assert!(
+            repo.find_by_email("bob@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(repo.reconcile().await.unwrap().fixed, 0);
+    }
+
+    #[tokio::test]
+    async fn reconcile_keeps_both_users_on_an_id_collision() {
+        let repo = InMemoryUserRepository::new();
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        let mut copy = ann.clone();
+        copy.email = "copy@example.com".to_string();
+        repo.db.insert("stale-key".to_string(), copy);
+
+        let report = repo.reconcile().await.unwrap();
+
+        assert_eq!(report.id_collisions, vec!["stale-key".to_string()]);
+        assert_eq!(repo.db.len(), 2);
+        assert_eq!(repo.db.get(&ann.id).unwrap().email, "ann@example.com");
+        assert_eq!(repo.db.get("stale-key").unwrap().email, "copy@example.com");
+    }
+
+    #[tokio::test]
+    async fn reconcile_leaves_expired_users_out_of_the_name_index() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let repo = InMemoryUserRepository::with_clock(clock.clone()).with_name_index(true);
+        let mut expiring = request("Ann", "ann@example.com");
+        expiring.expires_at = Some(clock.now() + chrono::Duration::minutes(1));
+        repo.create_user(&expiring).await.unwrap();
+        clock.advance(chrono::Duration::minutes(2));
+
+        repo.reconcile().await.unwrap();
+
+        assert!(repo.names.as_ref().unwrap().search("ann").is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn listing_stays_consistent_under_concurrent_writes() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        let mut seeded = HashSet::new();
+        for i in 0..100 {
+            let user = repo
+                .create_user(&request("Seed", &format!("seed{i}@example.com")))
+                .await
+                .unwrap();
+            seeded.insert(user.id);
+        }
+
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let repo = repo.clone();
+                tokio::spawn(async move {
+                    for i in 0..200 {
+                        let email = format!("churn{w}-{i}@example.com");
+                        repo.create_user(&request("Churn", &email)).await.unwrap();
+                        repo.delete_user(&email).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let repo = repo.clone();
+                let seeded = seeded.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        let (users, total) = repo
+                            .find_all(1, 10_000, None, SearchField::All)
+                            .await
+                            .unwrap();
+                        let ids: HashSet<&String> = users.iter().map(|u| &u.id).collect();
+                        assert_eq!(ids.len(), users.len(), "a user was listed twice");
+                        assert_eq!(total as usize, users.len());
+                        // Each writer holds at most one churn user at a time.
+                        assert!(users.len() <= seeded.len() + 4);
+                        assert!(seeded.iter().all(|id| ids.contains(id)));
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        for task in writers.into_iter().chain(readers) {
+            task.await.unwrap();
+        }
+        assert_eq!(repo.count().await.unwrap(), seeded.len());
+    }
+
+    #[tokio::test]
+    async fn search_field_restricts_where_the_term_may_match() {
+        for indexed in [false, true] {
+            let repo = InMemoryUserRepository::new().with_name_index(indexed);
+            repo.create_user(&request("Ann Smith", "jdoe@corp.example"))
+                .await
+                .unwrap();
+            let total = |term: &str, field| {
+                let repo = &repo;
+                let term = term.to_string();
+                async move { repo.find_all(1, 10, Some(term), field).await.unwrap().1 }
             };
-            if let Some(name) = &input.name {
-                user.name = name.clone();
+
+            assert_eq!(total("jdoe", SearchField::Name).await, 0, "{indexed}");
+            assert_eq!(total("jdoe", SearchField::Email).await, 1, "{indexed}");
+            assert_eq!(total("jdoe", SearchField::All).await, 1, "{indexed}");
+            assert_eq!(total("smith", SearchField::Email).await, 0, "{indexed}");
+            assert_eq!(total("smith", SearchField::Name).await, 1, "{indexed}");
+        }
+    }
+
+    fn rename(name: &str) -> UpdateUserRequest {
+        UpdateUserRequest {
+            name: Some(name.to_string()),
+            email: None,
+            age: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn each_update_returns_its_own_write() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        let id = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap()
+            .id;
+
+        let tasks: Vec<_> = (0..200)
+            .map(|i| {
+                let (repo, id) = (repo.clone(), id.clone());
+                tokio::spawn(async move {
+                    let name = format!("Writer {i}");
+                    let user = repo.update_user(&rename(&name), &id).await.unwrap();
+                    (name, user.name)
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let (written, returned) = task.await.unwrap();
+            assert_eq!(returned, written);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn racing_updates_with_one_etag_admit_exactly_one() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        let user = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        let etag = user.etag();
+
+        let tasks: Vec<_> = (0..50)
+            .map(|i| {
+                let (repo, id, etag) = (repo.clone(), user.id.clone(), etag.clone());
+                tokio::spawn(async move {
+                    repo.update_if_match(&rename(&format!("Writer {i}")), &id, &etag)
+                        .await
+                })
+            })
+            .collect();
+        let mut applied = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(_) => applied += 1,
+                Err(AppError::PreconditionFailed(_)) => {}
+                Err(e) => panic!("unexpected error: {e:?}"),
             }
-            if let Some(email) = &input.email {
-                user.email = email.to_lowercase();
+        }
+
+        assert_eq!(applied, 1);
+    }
+
+    #[tokio::test]
+    async fn if_match_on_a_missing_user_is_a_failed_precondition() {
+        let repo = InMemoryUserRepository::new();
+
+        let err = repo
+            .update_if_match(&rename("Ann"), "missing", "*")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AppError::PreconditionFailed(_)), "{err:?}");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn update_racing_a_delete_either_applies_or_finds_nothing() {
+        let repo = Arc::new(InMemoryUserRepository::new());
+        for i in 0..100 {
+            let email = format!("user{i}@example.com");
+            let id = repo.create_user(&request("Ann", &email)).await.unwrap().id;
+
+            let update = tokio::spawn({
+                let (repo, id) = (repo.clone(), id.clone());
+                async move { repo.update_user(&rename("Ann B"), &id).await }
+            });
+            let delete = tokio::spawn({
+                let repo = repo.clone();
+                async move { repo.delete_by_id(&id).await }
+            });
+
+            match update.await.unwrap() {
+                Ok(user) => assert_eq!(user.name, "Ann B"),
+                Err(AppError::UserNotFound) => {}
+                Err(e) => panic!("unexpected error: {e:?}"),
             }
-            if let Some(age) = input.age {
-                user.age = age;
+            delete.await.unwrap().unwrap();
+            assert!(repo.find_by_email(&email).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_all_yields_what_find_all_lists() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let repo = InMemoryUserRepository::with_clock(clock.clone());
+        for i in 0..STREAM_PAGE_SIZE + 3 {
+            repo.create_user(&request("User", &format!("user{i}@example.com")))
+                .await
+                .unwrap();
+            // Every other user shares a timestamp, so ties are ordered too.
+            if i % 2 == 1 {
+                clock.advance(chrono::Duration::seconds(1));
             }
-            user.updated_at = chrono::Utc::now();
         }
-        self.find_by_id(id).await?.ok_or(AppError::UserNotFound)
+        let mut expiring = request("Gone", "gone@example.com");
+        expiring.expires_at = Some(clock.now() + chrono::Duration::minutes(1));
+        repo.create_user(&expiring).await.unwrap();
+        clock.advance(chrono::Duration::minutes(2));
+
+        let streamed: Vec<User> = repo
+            .stream_all()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let (listed, total) = repo
+            .find_all(1, i32::MAX, None, SearchField::All)
+            .await
+            .unwrap();
+
+        assert_eq!(streamed.len(), STREAM_PAGE_SIZE + 3);
+        assert_eq!(total, streamed.len() as i64);
+        let ids = |users: &[User]| users.iter().map(|u| u.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&streamed), ids(&listed));
     }
 
-    async fn delete_user(&self, email: &str) -> Result<(), AppError> {
-        let key = self.db.iter().find_map(|entry| {
-            if entry.value().email == email {
-                Some(entry.key().clone())
-            } else {
-                None
+    #[tokio::test]
+    async fn updated_between_filters_by_window_and_orders_by_update() {
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let repo = InMemoryUserRepository::with_clock(clock.clone());
+        let at = |minutes| start + chrono::Duration::minutes(minutes);
+        let mut ids = Vec::new();
+        for name in ["Ann", "Bob", "Cid"] {
+            let user = repo
+                .create_user(&request(
+                    name,
+                    &format!("{}@example.com", name.to_lowercase()),
+                ))
+                .await
+                .unwrap();
+            ids.push(user.id);
+            clock.advance(chrono::Duration::minutes(1));
+        }
+        // Ann, created first, becomes the most recent change.
+        clock.set(at(3));
+        repo.update_user(&rename("Annie"), &ids[0]).await.unwrap();
+        let names = |users: Vec<User>| users.into_iter().map(|u| u.name).collect::<Vec<_>>();
+
+        let (window, total) = repo
+            .find_updated_between(Some(at(1)), Some(at(3)), 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(names(window), vec!["Bob", "Cid", "Annie"]);
+
+        let (closed, _) = repo
+            .find_updated_between(Some(at(1)), Some(at(2)), 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(names(closed), vec!["Bob", "Cid"]);
+
+        let (open_start, _) = repo
+            .find_updated_between(None, Some(at(1)), 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(names(open_start), vec!["Bob"]);
+
+        let (second_page, total) = repo
+            .find_updated_between(Some(at(1)), None, 2, 2)
+            .await
+            .unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(names(second_page), vec!["Annie"]);
+    }
+
+    #[tokio::test]
+    async fn name_index_follows_renames_and_deletes() {
+        let repo = InMemoryUserRepository::new().with_name_index(true);
+        let ann = repo
+            .create_user(&request("Ann Lee", "ann@example.com"))
+            .await
+            .unwrap();
+        let names = |q: &str| {
+            let repo = &repo;
+            let q = q.to_string();
+            async move {
+                let (users, _) = repo
+                    .find_all(1, 10, Some(q), SearchField::Name)
+                    .await
+                    .unwrap();
+                users.into_iter().map(|u| u.name).collect::<Vec<_>>()
             }
-        });
-        if let Some(k) = key {
-            self.db.remove(&k);
-            Ok(())
-        } else {
-            Err(AppError::UserNotFound)
+        };
+
+        repo.update_user(&rename("Ann Park"), &ann.id)
+            .await
+            .unwrap();
+        assert!(names("lee").await.is_empty());
+        assert_eq!(names("park ann").await, vec!["Ann Park"]);
+
+        repo.delete_by_id(&ann.id).await.unwrap();
+        assert!(names("ann").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn purge_removes_every_expired_user_across_batches() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let repo = Arc::new(InMemoryUserRepository::with_clock(clock.clone()));
+        for i in 0..7 {
+            let mut expiring = request("Temp", &format!("temp{i}@example.com"));
+            expiring.expires_at = Some(clock.now() + chrono::Duration::minutes(1));
+            repo.create_user(&expiring).await.unwrap();
         }
+        repo.create_user(&request("Kept", "kept@example.com"))
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(2));
+
+        // On the single-threaded test runtime this reader only runs when the
+        // purge yields, so it sees the store shrink one batch at a time.
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = tokio::spawn({
+            let (repo, done) = (repo.clone(), done.clone());
+            async move {
+                let mut seen = Vec::new();
+                while !done.load(Ordering::Acquire) {
+                    seen.push(repo.db.len());
+                    tokio::task::yield_now().await;
+                }
+                seen
+            }
+        });
+        assert_eq!(repo.purge_expired(3).await.unwrap(), 7);
+        done.store(true, Ordering::Release);
+        let mut seen = reader.await.unwrap();
+        seen.dedup();
+        assert_eq!(seen, [5, 2, 1]);
+        assert_eq!(repo.db.len(), 1);
+        // Freed emails can be taken again.
+        repo.create_user(&request("New", "temp0@example.com"))
+            .await
+            .unwrap();
+
+        // A zero batch size still makes progress.
+        let mut expiring = request("Temp", "late@example.com");
+        expiring.expires_at = Some(clock.now() + chrono::Duration::minutes(1));
+        repo.create_user(&expiring).await.unwrap();
+        clock.advance(chrono::Duration::minutes(2));
+        assert_eq!(repo.purge_expired(0).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn stored_count_follows_writes_and_keeps_expired_users_until_purged() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let repo = InMemoryUserRepository::with_clock(clock.clone());
+        let ann = repo
+            .create_user(&request("Ann", "ann@example.com"))
+            .await
+            .unwrap();
+        let mut expiring = request("Temp", "temp@example.com");
+        expiring.expires_at = Some(clock.now() + chrono::Duration::minutes(1));
+        repo.create_user(&expiring).await.unwrap();
+        assert_eq!(repo.stored_count().await.unwrap(), 2);
+
+        clock.advance(chrono::Duration::minutes(2));
+        assert_eq!(repo.count().await.unwrap(), 1);
+        assert_eq!(repo.stored_count().await.unwrap(), 2);
+
+        repo.purge_expired(10).await.unwrap();
+        repo.delete_by_id(&ann.id).await.unwrap();
+        assert_eq!(repo.stored_count().await.unwrap(), 0);
     }
 }
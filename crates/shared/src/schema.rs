@@ -0,0 +1,88 @@
+use schemars::{JsonSchema, schema_for};
+use serde_json::{Value, json};
+
+use crate::{
+    domain::{CreateUserRequest, UpdateUserRequest},
+    validation::{FieldLimits, MAX_AGE},
+};
+
+/// Names accepted by `GET /schema/{name}`.
+pub const REQUEST_SCHEMAS: [&str; 2] = ["create-user", "update-user"];
+
+/// JSON Schema for a request body, or `None` for an unknown name. The
+/// configurable length limits and the age range are filled in from the
+/// running config, since the derive only knows the Rust types.
+pub fn request_schema(name: &str) -> Option<Value> {
+    match name {
+        "create-user" => Some(with_limits::<CreateUserRequest>()),
+        "update-user" => Some(with_limits::<UpdateUserRequest>()),
+        _ => None,
+    }
+}
+
+fn with_limits<T: JsonSchema>() -> Value {
+    let mut schema = schema_for!(T).to_value();
+    let limits = FieldLimits::current();
+    let constraints = [
+        (
+            "name",
+            json!({ "minLength": 1, "maxLength": limits.max_name_len }),
+        ),
+        (
+            "email",
+            json!({ "format": "email", "minLength": 1, "maxLength": limits.max_email_len }),
+        ),
+        ("age", json!({ "minimum": 0, "maximum": MAX_AGE })),
+    ];
+    if let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) {
+        for (field, extra) in constraints {
+            if let (Some(Value::Object(property)), Value::Object(extra)) =
+                (properties.get_mut(field), extra)
+            {
+                property.extend(extra);
+            }
+        }
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_schema_types_every_field() {
+        let schema = request_schema("create-user").unwrap();
+        let properties = &schema["properties"];
+
+        assert_eq!(properties["name"]["type"], "string");
+        assert_eq!(properties["email"]["type"], "string");
+        assert_eq!(properties["email"]["format"], "email");
+        assert_eq!(properties["age"]["type"], "integer");
+        assert_eq!(properties["age"]["maximum"], MAX_AGE);
+        let required = schema["required"].as_array().unwrap();
+        for field in ["name", "email", "age"] {
+            assert!(required.contains(&json!(field)), "{field} not required");
+        }
+    }
+
+    #[test]
+    fn update_schema_has_optional_fields() {
+        let schema = request_schema("update-user").unwrap();
+
+        for field in ["name", "email", "age"] {
+            assert!(schema["properties"].get(field).is_some(), "{field} missing");
+        }
+        assert!(
+            schema
+                .get("required")
+                .and_then(Value::as_array)
+                .is_none_or(|required| required.is_empty())
+        );
+    }
+
+    #[test]
+    fn unknown_schema_is_none() {
+        assert!(request_schema("delete-user").is_none());
+    }
+}
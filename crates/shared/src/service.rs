@@ -1,27 +1,181 @@
-use csv::WriterBuilder;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use csv::{QuoteStyle, Terminator, WriterBuilder};
 use dashmap::DashMap;
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
 use rayon::prelude::*;
-use std::sync::Arc;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, hash_map::Entry},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncWriteExt},
+    sync::broadcast,
+    task::JoinHandle,
 };
+use uuid::Uuid;
 
 use crate::{
-    abstract_trait::{UserRepositoryTrait, UserServiceTrait},
+    abstract_trait::{UserRepositoryTrait, UserServiceTrait, stale_etag},
+    capacity::UserCapacity,
+    clock::{Clock, SystemClock},
+    config::{AppConfig, EmptySearch},
+    csv_import::{CsvImportOptions, ImportReport, decode_csv, parse_csv_requests, validate_csv},
     domain::{
-        ApiResponse, ApiResponsePagination, CreateUserRequest, FindAllUserRequest, KafkaEvent,
-        ServiceStats, UpdateUserRequest, User, UserResponse,
+        AgeValue, ApiResponse, ApiResponsePagination, ApiResponseSearch, BulkCreateReport,
+        BulkItemResult, BulkUpsertResult, ChangeOp, ChangesQuery, CreateUserRequest, CsvDialect,
+        CsvQuoteStyle, CsvTerminator, EmailChangeRequest, EmailChangeToken, EmailDomainCount,
+        ExportFilter, ExportJob, FindAllUserRequest, ImportProgress, KafkaEvent, MergeUsersRequest,
+        ReconcileReport, RetryCounts, SearchField, SearchQuery, ServiceStats, SimilarUser,
+        SimilarityReason, UpdateUserRequest, User, UserResponse,
     },
+    edit_lock::{EditLock, EditLocks},
     errors::AppError,
-    kafka::producer::KafkaEventProducer,
+    expiring_map::ExpiringMap,
+    kafka::{
+        dlq::{self, DlqEnvelope},
+        producer::KafkaEventProducer,
+    },
+    metrics::{Metrics, MetricsSnapshot},
+    shutdown::ShutdownSignal,
+    similarity::{email_local_part, levenshtein},
+    stats::{StatsBucket, StatsTimeseries},
+    validation::{FieldError, Validate, normalize_domain},
 };
 
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Operation name for the repository reads behind an export, as reported
+/// in the retry counters.
+const EXPORT_READ: &str = "export_read";
+
+/// One CSV export cell. Serialized untagged so each keeps the formatting
+/// the `csv` crate gives its type, e.g. numbers stay unquoted under
+/// `non_numeric`.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum CsvField<'a> {
+    Text(&'a str),
+    Number(u8),
+    Timestamp(DateTime<Utc>),
+}
+
+/// The value of one of [`CsvDialect::COLUMNS`] for `user`. Optional fields
+/// such as `expires_at` are not columns, so every row has the same width.
+fn csv_field<'a>(user: &'a User, column: &str) -> Result<CsvField<'a>, AppError> {
+    Ok(match column {
+        "id" => CsvField::Text(&user.id),
+        "name" => CsvField::Text(&user.name),
+        "email" => CsvField::Text(&user.email),
+        "age" => CsvField::Number(user.age),
+        "created_at" => CsvField::Timestamp(user.created_at),
+        "updated_at" => CsvField::Timestamp(user.updated_at),
+        other => return Err(AppError::CsvError(format!("Unknown column: {other}"))),
+    })
+}
+
+pub fn write_csv(
+    users: &[User],
+    has_headers: bool,
+    dialect: &CsvDialect,
+) -> Result<Vec<u8>, AppError> {
+    let quote = u8::try_from(dialect.quote)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| AppError::CsvError("Quote character must be ASCII".to_string()))?;
+    let quote_style = match dialect.quote_style {
+        CsvQuoteStyle::Necessary => QuoteStyle::Necessary,
+        CsvQuoteStyle::Always => QuoteStyle::Always,
+        CsvQuoteStyle::NonNumeric => QuoteStyle::NonNumeric,
+        CsvQuoteStyle::Never => QuoteStyle::Never,
+    };
+    let terminator = match dialect.terminator {
+        CsvTerminator::Lf => Terminator::Any(b'\n'),
+        CsvTerminator::Crlf => Terminator::CRLF,
+    };
+    let columns = dialect.columns().map_err(AppError::CsvError)?;
+
+    let mut buffer = Vec::with_capacity(1024 * 1024);
+    // The BOM belongs at the very start of the file, which is where the
+    // header row goes; chunks written without headers continue a file.
+    if has_headers && dialect.bom == Some(true) {
+        buffer.extend_from_slice(UTF8_BOM);
+    }
+    {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(false)
+            .quote_style(quote_style)
+            .quote(quote)
+            .terminator(terminator)
+            .from_writer(&mut buffer);
+
+        if has_headers {
+            wtr.write_record(&columns)
+                .map_err(|e| AppError::CsvError(e.to_string()))?;
+        }
+        for user in users {
+            let row = columns
+                .iter()
+                .map(|column| csv_field(user, column))
+                .collect::<Result<Vec<_>, _>>()?;
+            wtr.serialize(row)
+                .map_err(|e| AppError::CsvError(format!("Failed to serialize user: {}", e)))?;
+        }
+
+        wtr.flush().map_err(|e| AppError::CsvError(e.to_string()))?;
+    }
+
+    Ok(buffer)
+}
+
+fn email_change_store(
+    config: &AppConfig,
+    clock: Arc<dyn Clock>,
+) -> ExpiringMap<String, PendingEmailChange> {
+    ExpiringMap::new(
+        Duration::from_secs(config.email_change_ttl_secs),
+        config.cache_max_entries,
+    )
+    .with_clock(clock)
+}
+
+fn download_store(config: &AppConfig, clock: Arc<dyn Clock>) -> ExpiringMap<String, String> {
+    ExpiringMap::new(
+        Duration::from_secs(config.download_token_ttl_secs),
+        config.cache_max_entries,
+    )
+    .with_clock(clock)
+}
+
 #[derive(Clone)]
 pub struct UserServiceImpl {
     pub repo: Arc<dyn UserRepositoryTrait>,
     pub stats: Arc<DashMap<(), ServiceStats>>,
     pub kafka_producer: Option<Arc<KafkaEventProducer>>,
+    pub config: AppConfig,
+    pub clock: Arc<dyn Clock>,
+    pub timeseries: Arc<StatsTimeseries>,
+    pub edit_locks: Arc<EditLocks>,
+    /// Pending email changes by token, awaiting confirmation.
+    pub email_changes: Arc<ExpiringMap<String, PendingEmailChange>>,
+    /// Finished export files by one-time download token.
+    pub downloads: Arc<ExpiringMap<String, String>>,
+    /// Retry counters by operation name.
+    pub retries: Arc<DashMap<&'static str, RetryCounts>>,
+    pub capacity: Arc<UserCapacity>,
+    pub metrics: Arc<Metrics>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingEmailChange {
+    pub user_id: String,
+    pub email: String,
 }
 
 impl std::fmt::Debug for UserServiceImpl {
@@ -29,6 +183,7 @@ impl std::fmt::Debug for UserServiceImpl {
         f.debug_struct("UserServiceImpl")
             .field("repo", &"Arc<dyn UserRepositoryTrait>")
             .field("stats", &self.stats)
+            .field("config", &self.config)
             .finish()
     }
 }
@@ -38,20 +193,236 @@ impl UserServiceImpl {
         repo: Arc<dyn UserRepositoryTrait>,
         kafka_producer: Option<Arc<KafkaEventProducer>>,
     ) -> Self {
+        let defaults = AppConfig::default();
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
         Self {
             repo,
             stats: Arc::new(DashMap::new()),
-            kafka_producer: kafka_producer,
+            kafka_producer,
+            config: AppConfig::default(),
+            timeseries: Arc::new(StatsTimeseries::new(
+                defaults.stats_bucket_secs,
+                defaults.stats_retention_buckets,
+            )),
+            edit_locks: Arc::new(EditLocks::new(defaults.edit_lock_ttl_secs, clock.clone())),
+            email_changes: Arc::new(email_change_store(&defaults, clock.clone())),
+            downloads: Arc::new(download_store(&defaults, clock.clone())),
+            retries: Arc::new(DashMap::new()),
+            capacity: Arc::new(UserCapacity::new(defaults.max_users)),
+            metrics: Arc::new(Metrics::default()),
+            clock,
         }
     }
 
+    pub fn with_config(mut self, config: AppConfig) -> Self {
+        self.timeseries = Arc::new(StatsTimeseries::new(
+            config.stats_bucket_secs,
+            config.stats_retention_buckets,
+        ));
+        self.edit_locks = Arc::new(EditLocks::new(
+            config.edit_lock_ttl_secs,
+            self.clock.clone(),
+        ));
+        self.email_changes = Arc::new(email_change_store(&config, self.clock.clone()));
+        self.downloads = Arc::new(download_store(&config, self.clock.clone()));
+        self.capacity = Arc::new(UserCapacity::new(config.max_users));
+        self.config = config;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.edit_locks = Arc::new(EditLocks::new(
+            self.config.edit_lock_ttl_secs,
+            clock.clone(),
+        ));
+        self.email_changes = Arc::new(email_change_store(&self.config, clock.clone()));
+        self.downloads = Arc::new(download_store(&self.config, clock.clone()));
+        self.clock = clock;
+        self
+    }
+
+    /// Like `export_users`, but yields users as the repository produces them
+    /// instead of collecting the whole export first.
+    pub fn stream_users(&self, filter: ExportFilter) -> BoxStream<'static, Result<User, AppError>> {
+        self.repo
+            .stream_all()
+            .filter(move |user| {
+                let keep = user.as_ref().map_or(true, |user| filter.matches(user));
+                std::future::ready(keep)
+            })
+            .boxed()
+    }
+
     async fn increment_stat<F>(&self, f: F)
     where
-        F: FnOnce(&mut ServiceStats),
+        F: Fn(&mut ServiceStats),
     {
-        let mut stats = self.stats.entry(()).or_default();
-        stats.total_operations += 1;
-        f(&mut stats);
+        {
+            let mut stats = self.stats.entry(()).or_default();
+            stats.total_operations += 1;
+            f(&mut stats);
+        }
+        self.timeseries.record(self.clock.now(), f);
+    }
+
+    fn check_client_id(&self, input: &CreateUserRequest) -> Result<(), AppError> {
+        if input.id.is_some() && !self.config.allow_client_ids {
+            return Err(AppError::ValidationError(
+                "Client-supplied ids are not enabled".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn check_email_available(&self, id: &str, email: &str) -> Result<(), AppError> {
+        match self.repo.find_by_email(email).await? {
+            Some(owner) if owner.id != id => {
+                Err(AppError::Conflict("Email already exists".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_expiry(&self, input: &CreateUserRequest) -> Result<(), AppError> {
+        if let Some(expires_at) = input.expires_at
+            && expires_at <= self.clock.now()
+        {
+            return Err(AppError::FieldErrors(vec![FieldError::new(
+                "expires_at",
+                "Expiry must be in the future",
+            )]));
+        }
+        Ok(())
+    }
+
+    /// Checks every item of a bulk create before anything is inserted and
+    /// reports all problems at once, with fields named `items[i].field`.
+    /// Emails repeated within the batch are flagged on each later copy.
+    fn validate_batch(&self, inputs: &[CreateUserRequest]) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        let mut seen = HashMap::new();
+        for (index, input) in inputs.iter().enumerate() {
+            let mut item_errors = input.validate().err().unwrap_or_default();
+            for (field, check) in [
+                ("id", self.check_client_id(input)),
+                ("expires_at", self.check_expiry(input)),
+                ("email", self.check_email_domain(&input.email)),
+            ] {
+                match check {
+                    Ok(()) => {}
+                    Err(AppError::FieldErrors(found)) => item_errors.extend(found),
+                    Err(AppError::ValidationError(message)) => {
+                        item_errors.push(FieldError::new(field, message));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            match seen.entry(input.email.to_lowercase()) {
+                Entry::Occupied(first) => {
+                    item_errors.push(FieldError::new(
+                        "email",
+                        format!("Duplicate of item {} in this batch", first.get()),
+                    ));
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(index);
+                }
+            }
+            errors.extend(item_errors.into_iter().map(|e| FieldError {
+                field: format!("items[{index}].{}", e.field),
+                message: e.message,
+            }));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::FieldErrors(errors))
+        }
+    }
+
+    fn csv_options(&self) -> CsvImportOptions {
+        CsvImportOptions {
+            keep_ids: self.config.allow_client_ids,
+            ..self.config.csv_import.clone()
+        }
+    }
+
+    fn check_email_domain(&self, email: &str) -> Result<(), AppError> {
+        self.config
+            .email_domain_policy
+            .check(email)
+            .map_err(AppError::ValidationError)
+    }
+
+    /// Creates `input` without checking `max_users`; callers reserve room
+    /// first.
+    async fn insert_user(
+        &self,
+        input: &CreateUserRequest,
+    ) -> Result<ApiResponse<UserResponse>, AppError> {
+        input.validate().map_err(AppError::FieldErrors)?;
+        self.check_client_id(input)?;
+        self.check_expiry(input)?;
+        self.check_email_domain(&input.email)?;
+        let user = self.repo.create_user(input).await?;
+        self.increment_stat(|s| s.create_count += 1).await;
+        self.publish_change(ChangeOp::Created, &user.id);
+        Ok(ApiResponse {
+            success: true,
+            data: self.to_response(user),
+        })
+    }
+
+    /// Returns `true` when a new user was created, `false` when an existing
+    /// user with the same email was updated. Runs the same checks as a
+    /// create, and an expired user counts as absent.
+    async fn upsert_user(
+        &self,
+        input: CreateUserRequest,
+        owner: Option<&str>,
+    ) -> Result<bool, AppError> {
+        input.validate().map_err(AppError::FieldErrors)?;
+        self.check_client_id(&input)?;
+        self.check_expiry(&input)?;
+        self.check_email_domain(&input.email)?;
+        let email = input.email.to_lowercase();
+        let now = self.clock.now();
+        let existing = self.repo.find_by_email(&email).await?;
+        match existing.filter(|user| !user.is_expired(now)) {
+            Some(existing) => {
+                self.check_edit_lock(&existing.id, owner)?;
+                let update = UpdateUserRequest {
+                    name: Some(input.name),
+                    email: None,
+                    age: Some(input.age),
+                };
+                self.repo.update_user(&update, &existing.id).await?;
+                self.increment_stat(|s| s.update_count += 1).await;
+                self.publish_change(ChangeOp::Updated, &existing.id);
+                Ok(false)
+            }
+            None => {
+                let reservation = self.capacity.reserve(self.repo.as_ref(), 1).await?;
+                if reservation.granted() == 0 {
+                    return Err(self.capacity.limit_error());
+                }
+                let user = self.repo.create_user(&input).await?;
+                self.increment_stat(|s| s.create_count += 1).await;
+                self.publish_change(ChangeOp::Created, &user.id);
+                Ok(true)
+            }
+        }
+    }
+
+    fn to_response(&self, user: User) -> UserResponse {
+        UserResponse {
+            etag: user.etag(),
+            id: user.id,
+            name: user.name,
+            email: user.email,
+            age: AgeValue::new(user.age, self.config.age_format),
+            expires_at: user.expires_at,
+        }
     }
 
     pub async fn get_stats(&self) -> ServiceStats {
@@ -61,12 +432,266 @@ impl UserServiceImpl {
             .unwrap_or_default()
     }
 
-    pub async fn send_kafka_event(&self, event: &KafkaEvent) -> Result<(), String> {
-        if let Some(producer) = &self.kafka_producer {
-            producer.send(event).await
+    /// Counts a retry of `operation`, or with `exhausted` a call that failed
+    /// after using up its retries.
+    fn record_retry(&self, operation: &'static str, exhausted: bool) {
+        let mut counts = self.retries.entry(operation).or_default();
+        if exhausted {
+            counts.exhausted += 1;
         } else {
-            Err("Kafka producer not enabled".to_string())
+            counts.attempted += 1;
+        }
+    }
+
+    pub fn get_retry_counts(&self) -> BTreeMap<&'static str, RetryCounts> {
+        self.retries
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Request, operation and retry counters with gauges and latency
+    /// percentiles, for `/stats/json`.
+    pub async fn metrics_snapshot(&self) -> Result<MetricsSnapshot, AppError> {
+        let users = self.repo.count().await?;
+        Ok(self
+            .metrics
+            .snapshot(self.get_stats().await, self.get_retry_counts(), users))
+    }
+
+    pub async fn get_stats_timeseries(&self, window: chrono::Duration) -> Vec<StatsBucket> {
+        self.timeseries.window(self.clock.now(), window)
+    }
+
+    /// The stored user as-is, including the fields `UserResponse` leaves out.
+    pub async fn find_raw(&self, id: &str) -> Result<Option<User>, AppError> {
+        self.repo.find_by_id(id).await
+    }
+
+    /// One maintenance pass: reconcile the repository, drop expired edit
+    /// locks and trim stats buckets past their retention.
+    pub async fn run_maintenance(&self) -> Result<ReconcileReport, AppError> {
+        let report = self.repo.reconcile().await?;
+        let purged = self.edit_locks.purge_expired();
+        let trimmed = self.timeseries.trim(self.clock.now());
+        if report.fixed > 0
+            || !report.duplicate_emails.is_empty()
+            || !report.id_collisions.is_empty()
+            || purged > 0
+            || trimmed > 0
+        {
+            println!(
+                "🧽 Maintenance: checked {}, fixed {}, duplicate emails {:?}, id collisions {:?}, expired locks {}, trimmed buckets {}",
+                report.checked,
+                report.fixed,
+                report.duplicate_emails,
+                report.id_collisions,
+                purged,
+                trimmed
+            );
+        }
+        Ok(report)
+    }
+
+    /// Runs [`run_maintenance`](Self::run_maintenance) every
+    /// `maintenance_interval_secs` until shutdown. Returns `None` when the
+    /// interval is `0`.
+    pub fn spawn_maintenance(self: &Arc<Self>, shutdown: ShutdownSignal) -> Option<JoinHandle<()>> {
+        self.spawn_every(
+            self.config.maintenance_interval_secs,
+            shutdown,
+            |service| async move {
+                if let Err(e) = service.run_maintenance().await {
+                    eprintln!("⚠️ Maintenance failed: {}", e);
+                }
+            },
+        )
+    }
+
+    /// Queues a Kafka export job writing to a file named after the job id,
+    /// so concurrent jobs do not overwrite each other. The download token
+    /// is registered here rather than by the worker, so the link works when
+    /// the worker runs in another process, as long as both share the export
+    /// directory.
+    pub async fn queue_export(
+        &self,
+        filter: &ExportFilter,
+        dialect: CsvDialect,
+    ) -> Result<ExportJob, AppError> {
+        let job_id = Uuid::new_v4().to_string();
+        let path = format!("users_export_{job_id}.csv");
+        let token = Uuid::new_v4().simple().to_string();
+        let event = KafkaEvent::ExportCsv {
+            path: path.clone(),
+            since: filter.since,
+            until: filter.until,
+            dialect,
+        };
+        self.downloads.insert(token.clone(), path.clone());
+        if let Err(e) = self.send_kafka_event(&event).await {
+            self.downloads.remove(&token);
+            return Err(e);
+        }
+        println!("📨 Queued export job {} to {}", job_id, path);
+        Ok(ExportJob {
+            job_id,
+            path,
+            download_url: format!("/downloads/{token}"),
+        })
+    }
+
+    /// Hands out the file behind a download token and invalidates the
+    /// token, so each link works exactly once. Until the export has been
+    /// written the token is left alone and the link answers `404`. Exports
+    /// are renamed into place when complete, so an existing file is final.
+    pub async fn take_download(&self, token: &str) -> Result<String, AppError> {
+        let invalid =
+            || AppError::NotFound("Download link is invalid, expired or already used".to_string());
+        let token = token.to_string();
+        let path = self.downloads.get(&token).ok_or_else(invalid)?;
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Err(AppError::NotFound(
+                "Export is not ready yet; try again later".to_string(),
+            ));
+        }
+        self.downloads.take(&token).ok_or_else(invalid)
+    }
+
+    /// Whether an inline export would exceed `max_sync_export_rows`.
+    pub async fn export_too_large(&self) -> Result<bool, AppError> {
+        let limit = self.config.max_sync_export_rows;
+        Ok(limit > 0 && self.repo.count().await? > limit)
+    }
+
+    /// Users matching the listing's search filter, without fetching a page.
+    pub async fn count_users(&self, req: &FindAllUserRequest) -> Result<i64, AppError> {
+        let (_, total) = self
+            .repo
+            .find_all(1, 0, req.search.clone(), req.search_field)
+            .await?;
+        Ok(total)
+    }
+
+    pub async fn purge_expired_users(&self) -> Result<usize, AppError> {
+        let batch_size = self.config.expiry_sweep_batch_size.max(1);
+        let purged = self.repo.purge_expired(batch_size).await?;
+        if purged > 0 {
+            println!(
+                "⏳ Deleted {} expired users in {} batches of up to {}",
+                purged,
+                purged.div_ceil(batch_size),
+                batch_size
+            );
+        }
+        Ok(purged)
+    }
+
+    /// Runs [`purge_expired_users`](Self::purge_expired_users) every
+    /// `expiry_sweep_interval_secs` until shutdown. Returns `None` when the
+    /// interval is `0`.
+    pub fn spawn_expiry_sweeper(
+        self: &Arc<Self>,
+        shutdown: ShutdownSignal,
+    ) -> Option<JoinHandle<()>> {
+        self.spawn_every(
+            self.config.expiry_sweep_interval_secs,
+            shutdown,
+            |service| async move {
+                if let Err(e) = service.purge_expired_users().await {
+                    eprintln!("⚠️ Expiry sweep failed: {}", e);
+                }
+            },
+        )
+    }
+
+    fn spawn_every<F, Fut>(
+        self: &Arc<Self>,
+        secs: u64,
+        shutdown: ShutdownSignal,
+        task: F,
+    ) -> Option<JoinHandle<()>>
+    where
+        F: Fn(Arc<Self>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        if secs == 0 {
+            return None;
+        }
+        let service = Arc::clone(self);
+        let every = Duration::from_secs(secs);
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.clone().recv() => break,
+                }
+                task(Arc::clone(&service)).await;
+            }
+        }))
+    }
+
+    /// No-op unless `enforce_edit_locks` is set.
+    pub fn check_edit_lock(&self, id: &str, owner: Option<&str>) -> Result<(), AppError> {
+        if !self.config.enforce_edit_locks {
+            return Ok(());
+        }
+        self.edit_locks.check(id, owner)
+    }
+
+    /// Reads up to `limit` dead-lettered events without committing offsets.
+    /// Only needs the broker address, so it also works on a server that
+    /// does not produce to Kafka itself.
+    pub async fn peek_dlq(&self, limit: usize) -> Result<Vec<DlqEnvelope>, AppError> {
+        let Some(topic) = self.config.kafka_topics.dead_letter_topic() else {
+            return Err(AppError::ServiceUnavailable(
+                "No dead-letter topic is configured; set KAFKA_DLQ_TOPIC".to_string(),
+            ));
+        };
+        dlq::peek(
+            &self.config.kafka_brokers,
+            &self.config.kafka_auth,
+            topic,
+            limit,
+            Duration::from_millis(self.config.dlq_peek_timeout_ms),
+        )
+        .await
+        .map_err(AppError::ServiceUnavailable)
+    }
+
+    /// Publishes a change event in the background when `publish_changes` is
+    /// on. Delivery failures are logged and never fail the write itself.
+    fn publish_change(&self, op: ChangeOp, user_id: &str) {
+        if !self.config.publish_changes {
+            return;
         }
+        let Some(producer) = self.kafka_producer.clone() else {
+            return;
+        };
+        let event = KafkaEvent::UserChanged {
+            op,
+            user_id: user_id.to_string(),
+            at: self.clock.now(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = producer.send(&event).await {
+                eprintln!("⚠️ Failed to publish {:?}: {}", event, e);
+            }
+        });
+    }
+
+    /// A missing producer means Kafka is switched off for this process,
+    /// which is reported as unavailable rather than as a delivery failure.
+    pub async fn send_kafka_event(&self, event: &KafkaEvent) -> Result<(), AppError> {
+        let Some(producer) = &self.kafka_producer else {
+            return Err(AppError::ServiceUnavailable(
+                "Kafka producer not enabled".to_string(),
+            ));
+        };
+        producer
+            .send(event)
+            .await
+            .map_err(|e| AppError::Internal(format!("Kafka send failed: {}", e)))
     }
 }
 
@@ -78,17 +703,14 @@ impl UserServiceTrait for UserServiceImpl {
     ) -> Result<ApiResponsePagination<Vec<UserResponse>>, AppError> {
         let (users, total) = self
             .repo
-            .find_all(req.page, req.page_size, req.search.clone())
+            .find_all(
+                req.page,
+                req.page_size,
+                req.search.clone(),
+                req.search_field,
+            )
             .await?;
-        let data = users
-            .into_iter()
-            .map(|u| UserResponse {
-                id: u.id,
-                name: u.name,
-                email: u.email,
-                age: u.age,
-            })
-            .collect();
+        let data = users.into_iter().map(|u| self.to_response(u)).collect();
         Ok(ApiResponsePagination {
             success: true,
             data,
@@ -98,21 +720,85 @@ impl UserServiceTrait for UserServiceImpl {
         })
     }
 
+    async fn get_changes(
+        &self,
+        query: ChangesQuery,
+    ) -> Result<ApiResponsePagination<Vec<UserResponse>>, AppError> {
+        if let (Some(since), Some(until)) = (query.since, query.until)
+            && since > until
+        {
+            return Err(AppError::ValidationError(
+                "`since` must not be after `until`".to_string(),
+            ));
+        }
+        let (users, total) = self
+            .repo
+            .find_updated_between(query.since, query.until, query.page, query.page_size)
+            .await?;
+        let data = users.into_iter().map(|u| self.to_response(u)).collect();
+        Ok(ApiResponsePagination {
+            success: true,
+            data,
+            page: query.page,
+            page_size: query.page_size,
+            total,
+        })
+    }
+
+    async fn search_users(
+        &self,
+        query: SearchQuery,
+    ) -> Result<ApiResponseSearch<Vec<UserResponse>>, AppError> {
+        let term = match query.q.trim() {
+            "" if self.config.empty_search == EmptySearch::MatchAll => None,
+            "" => {
+                return Err(AppError::ValidationError(
+                    "Search query must not be empty".to_string(),
+                ));
+            }
+            q if q.chars().count() < self.config.min_search_len => {
+                return Err(AppError::ValidationError(
+                    "Search query too short".to_string(),
+                ));
+            }
+            q => Some(q.to_string()),
+        };
+        let page = query.page.unwrap_or(1);
+        let page_size = query
+            .page_size
+            .unwrap_or_else(|| self.config.max_search_page_size());
+        let (mut users, total) = self
+            .repo
+            .find_all(page, page_size, term, query.search_field)
+            .await?;
+        // Only the first `max_search_results` matches can be paged through;
+        // pages past them come back empty.
+        let offset = i64::from(page - 1) * i64::from(page_size);
+        let remaining = (self.config.max_search_results as i64 - offset).max(0);
+        users.truncate(usize::try_from(remaining).unwrap_or(usize::MAX));
+        // Set only when the cap hid matches, not merely because later pages
+        // exist.
+        let truncated = total > self.config.max_search_results as i64;
+        let data = users.into_iter().map(|u| self.to_response(u)).collect();
+        Ok(ApiResponseSearch {
+            success: true,
+            data,
+            page,
+            page_size,
+            total,
+            truncated,
+        })
+    }
+
     async fn create_user(
         &self,
         input: &CreateUserRequest,
     ) -> Result<ApiResponse<UserResponse>, AppError> {
-        let user = self.repo.create_user(input).await?;
-        self.increment_stat(|s| s.create_count += 1).await;
-        Ok(ApiResponse {
-            success: true,
-            data: UserResponse {
-                id: user.id,
-                name: user.name,
-                email: user.email,
-                age: user.age,
-            },
-        })
+        let reservation = self.capacity.reserve(self.repo.as_ref(), 1).await?;
+        if reservation.granted() == 0 {
+            return Err(self.capacity.limit_error());
+        }
+        self.insert_user(input).await
     }
 
     async fn find_by_id(&self, id: &str) -> Result<Option<ApiResponse<UserResponse>>, AppError> {
@@ -121,34 +807,110 @@ impl UserServiceTrait for UserServiceImpl {
                 self.increment_stat(|s| s.read_count += 1).await;
                 Ok(Some(ApiResponse {
                     success: true,
-                    data: UserResponse {
-                        id: user.id,
-                        name: user.name,
-                        email: user.email,
-                        age: user.age,
-                    },
+                    data: self.to_response(user),
                 }))
             }
             None => Ok(None),
         }
     }
+    /// Duplicate ids are looked up once; unknown ids are left out.
+    async fn find_by_ids(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<ApiResponse<Vec<UserResponse>>, AppError> {
+        let mut seen = HashSet::new();
+        let ids: Vec<String> = ids
+            .into_iter()
+            .filter(|id| seen.insert(id.clone()))
+            .collect();
+        let users = self
+            .repo
+            .find_by_ids(&ids, self.config.bulk_get_concurrency)
+            .await?;
+        let found = users.len();
+        self.increment_stat(|s| s.read_count += found as u64).await;
+        Ok(ApiResponse {
+            success: true,
+            data: users
+                .into_iter()
+                .map(|user| self.to_response(user))
+                .collect(),
+        })
+    }
+
+    async fn find_similar(
+        &self,
+        id: &str,
+    ) -> Result<Option<ApiResponse<Vec<SimilarUser>>>, AppError> {
+        let Some(target) = self.repo.find_by_id(id).await? else {
+            return Ok(None);
+        };
+        let (users, _) = self
+            .repo
+            .find_all(1, 1_000_000, None, SearchField::All)
+            .await?;
+
+        let name = target.name.to_lowercase();
+        let local_part = email_local_part(&target.email);
+        let threshold = self.config.similar_name_threshold;
+
+        let mut matches: Vec<(User, SimilarityReason, usize)> = users
+            .into_par_iter()
+            .filter(|user| user.id != target.id)
+            .filter_map(|user| {
+                let distance = levenshtein(&name, &user.name.to_lowercase());
+                if distance <= threshold {
+                    return Some((user, SimilarityReason::Name, distance));
+                }
+                if email_local_part(&user.email) == local_part {
+                    return Some((user, SimilarityReason::EmailLocalPart, 0));
+                }
+                None
+            })
+            .collect();
+        matches.sort_by(|a, b| {
+            a.2.cmp(&b.2)
+                .then_with(|| a.0.created_at.cmp(&b.0.created_at))
+        });
+        matches.truncate(self.config.similar_limit);
+
+        let data = matches
+            .into_iter()
+            .map(|(user, reason, distance)| SimilarUser {
+                user: self.to_response(user),
+                reason,
+                distance,
+            })
+            .collect();
+        Ok(Some(ApiResponse {
+            success: true,
+            data,
+        }))
+    }
 
     async fn update_user(
         &self,
         id: &str,
         input: &UpdateUserRequest,
+        owner: Option<&str>,
+        if_match: Option<&str>,
     ) -> Result<Option<ApiResponse<UserResponse>>, AppError> {
-        match self.repo.update_user(input, id).await {
+        self.check_edit_lock(id, owner)?;
+        input.validate().map_err(AppError::FieldErrors)?;
+        if let Some(email) = &input.email {
+            self.check_email_domain(email)?;
+        }
+        let updated = match if_match {
+            Some(if_match) => self.repo.update_if_match(input, id, if_match).await,
+            None => self.repo.update_user(input, id).await,
+        };
+        match updated {
             Ok(user) => {
                 self.increment_stat(|s| s.update_count += 1).await;
+                self.publish_change(ChangeOp::Updated, &user.id);
                 Ok(Some(ApiResponse {
                     success: true,
-                    data: UserResponse {
-                        id: user.id,
-                        name: user.name,
-                        email: user.email,
-                        age: user.age,
-                    },
+                    data: self.to_response(user),
                 }))
             }
             Err(AppError::UserNotFound) => Ok(None),
@@ -156,63 +918,412 @@ impl UserServiceTrait for UserServiceImpl {
         }
     }
 
-    async fn delete_user(&self, email: &str) -> Result<ApiResponse<()>, AppError> {
-        self.repo.delete_user(email).await?;
-        self.increment_stat(|s| s.delete_count += 1).await;
-        Ok(ApiResponse {
-            success: true,
-            data: (),
-        })
-    }
-
-    async fn bulk_create_users(&self, inputs: Vec<CreateUserRequest>) -> Result<(), AppError> {
-        println!("🎯 Processing {} users in bulk...", inputs.len());
-
-        let futures: Vec<_> = inputs
-            .into_par_iter()
-            .map(|mut req| {
-                req.name = req.name.to_uppercase();
-                let service = self.clone();
-                async move { service.create_user(&req).await }
-            })
-            .collect();
-
-        let results = futures::future::join_all(futures).await;
-
-        for result in results {
-            if let Err(e) = result {
-                eprintln!("Failed to create user: {}", e);
+    async fn update_user_by_email(
+        &self,
+        email: &str,
+        input: &UpdateUserRequest,
+        owner: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<ApiResponse<UserResponse>>, AppError> {
+        // Locks are keyed by id, so the user is only looked up when they
+        // are enforced or the ETag check needs the id.
+        let user = if self.config.enforce_edit_locks || if_match.is_some() {
+            self.repo.find_by_email(email).await?
+        } else {
+            None
+        };
+        if let Some(user) = &user {
+            self.check_edit_lock(&user.id, owner)?;
+        }
+        input.validate().map_err(AppError::FieldErrors)?;
+        if let Some(new_email) = &input.email {
+            self.check_email_domain(new_email)?;
+        }
+        let updated = match (if_match, &user) {
+            (Some(if_match), Some(user)) => {
+                self.repo.update_if_match(input, &user.id, if_match).await
+            }
+            (Some(_), None) => Err(stale_etag()),
+            (None, _) => self.repo.update_by_email(input, email).await,
+        };
+        match updated {
+            Ok(user) => {
+                self.increment_stat(|s| s.update_count += 1).await;
+                self.publish_change(ChangeOp::Updated, &user.id);
+                Ok(Some(ApiResponse {
+                    success: true,
+                    data: self.to_response(user),
+                }))
             }
+            Err(AppError::UserNotFound) => Ok(None),
+            Err(e) => Err(e),
         }
-
-        Ok(())
     }
 
-    async fn export_to_csv(&self, path: &str) -> Result<(), AppError> {
-        println!("📦 Preparing to export users to CSV: {}", path);
-
-        let users = self.repo.find_all(1, 1_000_000, None).await?.0;
-        println!("📊 Retrieved {} users to export", users.len());
-
-        let mut buffer = Vec::with_capacity(1024 * 1024);
-        {
-            let mut wtr = WriterBuilder::new()
-                .has_headers(true)
-                .from_writer(&mut buffer);
-
-            for user in &users {
-                wtr.serialize(user)
-                    .map_err(|e| AppError::CsvError(format!("Failed to serialize user: {}", e)))?;
+    async fn delete_user(
+        &self,
+        email: &str,
+        owner: Option<&str>,
+    ) -> Result<Option<ApiResponse<()>>, AppError> {
+        // The id is only needed for the change event and the lock check.
+        let id = if self.config.publish_changes || self.config.enforce_edit_locks {
+            self.repo.find_by_email(email).await?.map(|user| user.id)
+        } else {
+            None
+        };
+        if let Some(id) = &id {
+            self.check_edit_lock(id, owner)?;
+        }
+        match self.repo.delete_user(email).await {
+            Ok(()) => {
+                self.increment_stat(|s| s.delete_count += 1).await;
+                if let Some(id) = id
+                    && self.config.publish_changes
+                {
+                    self.publish_change(ChangeOp::Deleted, &id);
+                }
+                Ok(Some(ApiResponse {
+                    success: true,
+                    data: (),
+                }))
             }
-
-            wtr.flush().map_err(|e| AppError::CsvError(e.to_string()))?;
+            Err(AppError::UserNotFound) => Ok(None),
+            Err(e) => Err(e),
         }
+    }
 
-        let mut file = File::create(path)
-            .await
-            .map_err(|e| AppError::Internal(e.to_string()))?;
-
-        file.write_all(&buffer)
+    async fn delete_user_by_id(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<Option<ApiResponse<()>>, AppError> {
+        self.check_edit_lock(id, owner)?;
+        match self.repo.delete_by_id(id).await {
+            Ok(()) => {
+                self.increment_stat(|s| s.delete_count += 1).await;
+                self.publish_change(ChangeOp::Deleted, id);
+                Ok(Some(ApiResponse {
+                    success: true,
+                    data: (),
+                }))
+            }
+            Err(AppError::UserNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn lock_user(
+        &self,
+        id: &str,
+        owner: &str,
+    ) -> Result<Option<ApiResponse<EditLock>>, AppError> {
+        if self.repo.find_by_id(id).await?.is_none() {
+            return Ok(None);
+        }
+        let lock = self.edit_locks.acquire(id, owner)?;
+        Ok(Some(ApiResponse {
+            success: true,
+            data: lock,
+        }))
+    }
+
+    async fn unlock_user(&self, id: &str, owner: &str) -> Result<ApiResponse<()>, AppError> {
+        self.edit_locks.release(id, owner)?;
+        Ok(ApiResponse {
+            success: true,
+            data: (),
+        })
+    }
+
+    async fn request_email_change(
+        &self,
+        id: &str,
+        input: &EmailChangeRequest,
+    ) -> Result<Option<ApiResponse<EmailChangeToken>>, AppError> {
+        let email = input.email.trim().to_lowercase();
+        self.check_email_domain(&email)?;
+        if self.repo.find_by_id(id).await?.is_none() {
+            return Ok(None);
+        }
+        self.check_email_available(id, &email).await?;
+
+        let token = Uuid::new_v4().simple().to_string();
+        let expires_at =
+            self.clock.now() + chrono::Duration::seconds(self.config.email_change_ttl_secs as i64);
+        self.email_changes.insert(
+            token.clone(),
+            PendingEmailChange {
+                user_id: id.to_string(),
+                email,
+            },
+        );
+        Ok(Some(ApiResponse {
+            success: true,
+            data: EmailChangeToken { token, expires_at },
+        }))
+    }
+
+    /// Tokens are single-use: a confirmation that fails after the token was
+    /// consumed needs a fresh request.
+    async fn confirm_email_change(
+        &self,
+        token: &str,
+        owner: Option<&str>,
+    ) -> Result<ApiResponse<UserResponse>, AppError> {
+        let invalid =
+            || AppError::ValidationError("Invalid or expired email change token".to_string());
+        // A locked user keeps the token, so it can be confirmed once the
+        // lock is gone.
+        let pending = self
+            .email_changes
+            .get(&token.to_string())
+            .ok_or_else(invalid)?;
+        self.check_edit_lock(&pending.user_id, owner)?;
+        let change = self
+            .email_changes
+            .take(&token.to_string())
+            .ok_or_else(invalid)?;
+        // Another user may have taken the address since the request.
+        self.check_email_available(&change.user_id, &change.email)
+            .await?;
+
+        let input = UpdateUserRequest {
+            name: None,
+            email: Some(change.email),
+            age: None,
+        };
+        self.update_user(&change.user_id, &input, owner, None)
+            .await?
+            .ok_or(AppError::UserNotFound)
+    }
+
+    async fn clear_users(&self) -> Result<ApiResponse<usize>, AppError> {
+        let removed = self.repo.clear().await?;
+        println!("🧹 Cleared {} users", removed);
+        Ok(ApiResponse {
+            success: true,
+            data: removed,
+        })
+    }
+
+    async fn merge_users(
+        &self,
+        req: &MergeUsersRequest,
+        owner: Option<&str>,
+    ) -> Result<ApiResponse<UserResponse>, AppError> {
+        req.validate().map_err(AppError::FieldErrors)?;
+        self.check_edit_lock(&req.keep, owner)?;
+        self.check_edit_lock(&req.remove, owner)?;
+        let user = self
+            .repo
+            .merge_users(&req.keep, &req.remove, &req.copy)
+            .await?;
+        self.increment_stat(|s| {
+            s.update_count += 1;
+            s.delete_count += 1;
+        })
+        .await;
+        self.publish_change(ChangeOp::Updated, &req.keep);
+        self.publish_change(ChangeOp::Deleted, &req.remove);
+        println!("🔗 Merged user {} into {}", req.remove, req.keep);
+        Ok(ApiResponse {
+            success: true,
+            data: self.to_response(user),
+        })
+    }
+
+    async fn email_domain_counts(&self) -> Result<ApiResponse<Vec<EmailDomainCount>>, AppError> {
+        let (users, _) = self
+            .repo
+            .find_all(1, 1_000_000, None, SearchField::All)
+            .await?;
+
+        let counts = users
+            .par_iter()
+            .filter_map(|user| user.email.rsplit_once('@'))
+            .fold(HashMap::new, |mut counts, (_, domain)| {
+                *counts.entry(normalize_domain(domain)).or_insert(0) += 1;
+                counts
+            })
+            .reduce(HashMap::new, |mut acc, counts| {
+                for (domain, count) in counts {
+                    *acc.entry(domain).or_insert(0) += count;
+                }
+                acc
+            });
+
+        let mut domains: Vec<EmailDomainCount> = counts
+            .into_iter()
+            .map(|(domain, count)| EmailDomainCount { domain, count })
+            .collect();
+        domains.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.domain.cmp(&b.domain)));
+
+        Ok(ApiResponse {
+            success: true,
+            data: domains,
+        })
+    }
+
+    async fn bulk_create_users(
+        &self,
+        inputs: Vec<CreateUserRequest>,
+    ) -> Result<BulkCreateReport, AppError> {
+        println!("🎯 Processing {} users in bulk...", inputs.len());
+        if self.config.bulk_validate_first {
+            self.validate_batch(&inputs)?;
+        }
+
+        // Each item reserves its own room right before inserting, so an item
+        // that fails for another reason leaves its slot to the rest and a
+        // 507 only appears once the store is actually full.
+        let futures: Vec<_> = inputs
+            .into_par_iter()
+            .enumerate()
+            .map(|(index, mut req)| {
+                req.name = req.name.to_uppercase();
+                let service = self.clone();
+                async move {
+                    let reservation = match service.capacity.reserve(service.repo.as_ref(), 1).await
+                    {
+                        Ok(reservation) => reservation,
+                        Err(e) => return (index, Err(e)),
+                    };
+                    if reservation.granted() == 0 {
+                        return (index, Err(service.capacity.limit_error()));
+                    }
+                    (index, service.insert_user(&req).await)
+                }
+            })
+            .collect();
+
+        // Completion order is arbitrary, so each result carries its input
+        // index and the report is put back in input order.
+        let mut results: Vec<(usize, Result<ApiResponse<UserResponse>, AppError>)> =
+            stream::iter(futures)
+                .buffer_unordered(self.config.bulk_concurrency.max(1))
+                .collect()
+                .await;
+        results.sort_unstable_by_key(|(index, _)| *index);
+
+        let mut report = BulkCreateReport::default();
+        for (index, result) in results {
+            let item = match result {
+                Ok(resp) => {
+                    report.created += 1;
+                    BulkItemResult {
+                        index,
+                        status: StatusCode::CREATED.as_u16(),
+                        id: Some(resp.data.id),
+                        error: None,
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to create user: {}", e);
+                    report.failed += 1;
+                    BulkItemResult {
+                        index,
+                        status: e.status().as_u16(),
+                        id: None,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+            report.items.push(item);
+        }
+
+        Ok(report)
+    }
+
+    async fn export_users(&self, filter: &ExportFilter) -> Result<Vec<User>, AppError> {
+        let mut attempt = 0;
+        let users = loop {
+            match self
+                .repo
+                .find_all(1, 1_000_000, None, SearchField::All)
+                .await
+            {
+                Ok((users, _)) => break users,
+                Err(AppError::Internal(e)) if attempt < self.config.export_read_retries => {
+                    attempt += 1;
+                    self.record_retry(EXPORT_READ, false);
+                    eprintln!("⚠️ Export read failed (attempt {}): {}", attempt, e);
+                    tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+                }
+                Err(e) => {
+                    if attempt > 0 {
+                        self.record_retry(EXPORT_READ, true);
+                    }
+                    return Err(e);
+                }
+            }
+        };
+
+        let users: Vec<User> = users
+            .into_iter()
+            .filter(|user| filter.matches(user))
+            .collect();
+        println!("📊 Retrieved {} users to export", users.len());
+        Ok(users)
+    }
+
+    async fn render_csv(
+        &self,
+        filter: &ExportFilter,
+        dialect: &CsvDialect,
+    ) -> Result<Vec<u8>, AppError> {
+        let users = self.export_users(filter).await?;
+        write_csv(&users, true, dialect)
+    }
+
+    async fn bulk_upsert_users(
+        &self,
+        inputs: Vec<CreateUserRequest>,
+        owner: Option<&str>,
+    ) -> Result<ApiResponse<BulkUpsertResult>, AppError> {
+        println!("🎯 Upserting {} users in bulk...", inputs.len());
+
+        let outcomes: Vec<Result<bool, AppError>> = stream::iter(inputs)
+            .map(|input| self.upsert_user(input, owner))
+            .buffer_unordered(self.config.bulk_concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut result = BulkUpsertResult::default();
+        for outcome in outcomes {
+            match outcome {
+                Ok(true) => result.created += 1,
+                Ok(false) => result.updated += 1,
+                Err(e) => {
+                    eprintln!("Failed to upsert user: {}", e);
+                    result.failed += 1;
+                }
+            }
+        }
+
+        Ok(ApiResponse {
+            success: true,
+            data: result,
+        })
+    }
+
+    async fn export_to_csv(
+        &self,
+        path: &str,
+        filter: &ExportFilter,
+        dialect: &CsvDialect,
+    ) -> Result<(), AppError> {
+        println!("📦 Preparing to export users to CSV: {}", path);
+
+        let buffer = self.render_csv(filter, dialect).await?;
+
+        // Write beside the target and rename, so a download never sees a
+        // half-written file.
+        let partial = format!("{path}.part");
+        let mut file = File::create(&partial)
+            .await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+
+        file.write_all(&buffer)
             .await
             .map_err(|e| AppError::Internal(e.to_string()))?;
 
@@ -220,7 +1331,11 @@ impl UserServiceTrait for UserServiceImpl {
             .await
             .map_err(|e| AppError::Internal(e.to_string()))?;
 
-        println!("✅ Successfully exported {} users to {}", users.len(), path);
+        tokio::fs::rename(&partial, path)
+            .await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+
+        println!("✅ Successfully exported users to {}", path);
         Ok(())
     }
 
@@ -235,88 +1350,1337 @@ impl UserServiceTrait for UserServiceImpl {
             .await
             .map_err(|e| AppError::Internal(e.to_string()))?;
 
-        let cursor = std::io::Cursor::new(contents);
-        let mut rdr = csv::Reader::from_reader(cursor);
-
-        let expected_headers = csv::StringRecord::from(vec![
-            "id",
-            "name",
-            "email",
-            "age",
-            "created_at",
-            "updated_at",
-        ]);
-        let actual_headers = rdr
-            .headers()
-            .map_err(|e| AppError::CsvError(e.to_string()))?;
-
-        if actual_headers != &expected_headers {
-            return Err(AppError::CsvError(
-                "Invalid CSV header. Expected: id,name,email,age,created_at,updated_at".to_string(),
-            ));
+        let imported = self.import_csv_bytes(contents, None).await?;
+
+        println!("✅ Successfully imported {} users from {}", imported, path);
+
+        Ok(())
+    }
+
+    async fn validate_import(&self, contents: Vec<u8>) -> Result<ImportReport, AppError> {
+        let contents = decode_csv(&contents, &self.csv_options())?;
+        let report = validate_csv(
+            contents.as_bytes(),
+            &self.csv_options(),
+            &self.config.email_domain_policy,
+        );
+        println!(
+            "🔎 Validated import: {} valid, {} invalid rows",
+            report.valid, report.invalid
+        );
+        Ok(report)
+    }
+
+    async fn import_csv_bytes(
+        &self,
+        contents: Vec<u8>,
+        progress: Option<broadcast::Sender<ImportProgress>>,
+    ) -> Result<usize, AppError> {
+        let contents = decode_csv(&contents, &self.csv_options())?;
+        let requests = parse_csv_requests(contents.as_bytes(), &self.csv_options())?;
+        for request in &requests {
+            self.check_email_domain(&request.email)?;
         }
 
-        let mut requests = Vec::new();
+        println!(
+            "📦 Found {} records, starting bulk insert...",
+            requests.len()
+        );
 
-        let mut iter = rdr.into_records();
-        for result in &mut iter {
-            let record = result.map_err(|e| AppError::CsvError(e.to_string()))?;
+        let total = requests.len();
+        let batch_size = self.config.import_progress_every.max(1);
+        let mut processed = 0;
 
-            if record.len() < 3 {
-                return Err(AppError::CsvError(
-                    "CSV row has too few fields (need at least name, email, age)".to_string(),
-                ));
-            }
+        let mut imported = 0;
 
-            let name = record[1].trim();
-            let email = record[2].trim();
-            let age_str = record[3].trim();
+        for batch in requests.chunks(batch_size) {
+            let report = self.bulk_create_users(batch.to_vec()).await.map_err(|e| {
+                eprintln!("❌ Bulk create failed: {}", e);
+                e
+            })?;
+            imported += report.created;
+            processed += batch.len();
 
-            if name.is_empty() {
-                return Err(AppError::ValidationError("Name is empty".to_string()));
+            let limit = StatusCode::INSUFFICIENT_STORAGE.as_u16();
+            if report.items.iter().any(|item| item.status == limit) {
+                return Err(AppError::InsufficientStorage(format!(
+                    "User limit of {} reached after importing {imported} of {total} rows",
+                    self.capacity.max()
+                )));
             }
-            if email.is_empty() {
-                return Err(AppError::ValidationError("Email is empty".to_string()));
+
+            if let Some(tx) = &progress {
+                let _ = tx.send(ImportProgress::Running { processed, total });
             }
-            if age_str.is_empty() {
-                return Err(AppError::ValidationError("Age is empty".to_string()));
+        }
+
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::MockClock, context::ServiceBuilder, database::SharedState, domain::MergeField,
+        repository::InMemoryUserRepository, validation::EmailDomainPolicy,
+    };
+
+    fn service(config: AppConfig) -> SharedState {
+        ServiceBuilder::new(config).without_kafka().build().service
+    }
+
+    fn clocked_service(config: AppConfig) -> (SharedState, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let service = ServiceBuilder::new(config)
+            .without_kafka()
+            .clock(clock.clone())
+            .build()
+            .service;
+        (service, clock)
+    }
+
+    fn request(name: &str, email: &str, age: u8) -> CreateUserRequest {
+        CreateUserRequest {
+            id: None,
+            name: name.to_string(),
+            email: email.to_string(),
+            age,
+            expires_at: None,
+        }
+    }
+
+    fn import_csv(rows: &[(&str, &str, u8)]) -> Vec<u8> {
+        let mut csv = String::from("id,name,email,age,created_at,updated_at\n");
+        for (name, email, age) in rows {
+            csv.push_str(&format!(",{name},{email},{age},,\n"));
+        }
+        csv.into_bytes()
+    }
+
+    #[tokio::test]
+    async fn bulk_upsert_splits_created_and_updated() {
+        let service = service(AppConfig::default());
+        service
+            .create_user(&request("Old Name", "old@example.com", 30))
+            .await
+            .unwrap();
+
+        let result = service
+            .bulk_upsert_users(
+                vec![
+                    request("New Name", "OLD@example.com", 31),
+                    request("Fresh", "fresh@example.com", 20),
+                    request("Fresh Two", "fresh2@example.com", 21),
+                ],
+                None,
+            )
+            .await
+            .unwrap()
+            .data;
+
+        assert_eq!((result.created, result.updated, result.failed), (2, 1, 0));
+        let updated = service
+            .repo
+            .find_by_email("old@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((updated.name.as_str(), updated.age), ("New Name", 31));
+    }
+
+    #[tokio::test]
+    async fn upsert_treats_expired_user_as_absent_and_checks_expiry() {
+        let (service, clock) = clocked_service(AppConfig::default());
+        service
+            .create_user(&CreateUserRequest {
+                expires_at: Some(clock.now() + chrono::Duration::hours(1)),
+                ..request("Ephemeral", "temp@example.com", 30)
+            })
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::hours(2));
+
+        let past = CreateUserRequest {
+            expires_at: Some(clock.now() - chrono::Duration::hours(1)),
+            ..request("Late", "late@example.com", 30)
+        };
+        let result = service
+            .bulk_upsert_users(vec![request("Back", "temp@example.com", 40), past], None)
+            .await
+            .unwrap()
+            .data;
+
+        assert_eq!((result.created, result.updated, result.failed), (1, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn import_reports_progress_per_batch() {
+        let service = service(AppConfig {
+            import_progress_every: 2,
+            ..AppConfig::default()
+        });
+        let (tx, mut rx) = broadcast::channel(16);
+        let rows: Vec<(String, String, u8)> = (0..5)
+            .map(|i| (format!("User {i}"), format!("user{i}@example.com"), 20))
+            .collect();
+        let rows: Vec<(&str, &str, u8)> = rows
+            .iter()
+            .map(|(name, email, age)| (name.as_str(), email.as_str(), *age))
+            .collect();
+
+        let imported = service
+            .import_csv_bytes(import_csv(&rows), Some(tx))
+            .await
+            .unwrap();
+
+        assert_eq!(imported, 5);
+        let mut processed = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let ImportProgress::Running {
+                processed: done,
+                total,
+            } = event
+            {
+                assert_eq!(total, 5);
+                processed.push(done);
             }
+        }
+        assert_eq!(processed, vec![2, 4, 5]);
+    }
 
-            if !email.contains('@') {
-                return Err(AppError::ValidationError(
-                    "Invalid email format".to_string(),
-                ));
+    #[tokio::test]
+    async fn import_counts_only_inserted_rows() {
+        let service = service(AppConfig::default());
+        service
+            .create_user(&request("Taken", "taken@example.com", 30))
+            .await
+            .unwrap();
+
+        let imported = service
+            .import_csv_bytes(
+                import_csv(&[
+                    ("New", "new@example.com", 20),
+                    ("Dup", "taken@example.com", 21),
+                ]),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(imported, 1);
+    }
+
+    #[tokio::test]
+    async fn dlq_peek_needs_a_separate_dlq_topic() {
+        // The default routing sends dead letters to the jobs topic.
+        let service = service(AppConfig::default());
+
+        let err = service.peek_dlq(10).await.unwrap_err();
+
+        assert!(matches!(err, AppError::ServiceUnavailable(m) if m.contains("KAFKA_DLQ_TOPIC")));
+    }
+
+    fn locking_service() -> SharedState {
+        service(AppConfig {
+            enforce_edit_locks: true,
+            ..AppConfig::default()
+        })
+    }
+
+    fn rename(name: &str) -> UpdateUserRequest {
+        UpdateUserRequest {
+            name: Some(name.to_string()),
+            email: None,
+            age: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn foreign_edit_lock_blocks_every_write() {
+        let service = locking_service();
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+        let bob = service
+            .create_user(&request("Bob", "bob@example.com", 40))
+            .await
+            .unwrap()
+            .data;
+        let token = service
+            .request_email_change(
+                &ann.id,
+                &EmailChangeRequest {
+                    email: "ann2@example.com".to_string(),
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap()
+            .data
+            .token;
+        service.lock_user(&ann.id, "alice").await.unwrap();
+        fn locked<T>(result: Result<T, AppError>) -> bool {
+            matches!(result, Err(AppError::Locked(_)))
+        }
+
+        assert!(locked(
+            service
+                .update_user(&ann.id, &rename("X"), Some("bob"), None)
+                .await
+        ));
+        assert!(locked(
+            service
+                .update_user_by_email("ann@example.com", &rename("X"), None, None)
+                .await
+        ));
+        assert!(locked(service.confirm_email_change(&token, None).await));
+        let merge = MergeUsersRequest {
+            keep: bob.id.clone(),
+            remove: ann.id.clone(),
+            copy: Vec::new(),
+        };
+        assert!(locked(service.merge_users(&merge, Some("bob")).await));
+        let upsert = service
+            .bulk_upsert_users(vec![request("X", "ann@example.com", 31)], None)
+            .await
+            .unwrap()
+            .data;
+        assert_eq!((upsert.updated, upsert.failed), (0, 1));
+        assert!(locked(
+            service.delete_user_by_id(&ann.id, Some("bob")).await
+        ));
+        assert!(locked(service.delete_user("ann@example.com", None).await));
+
+        // The owner gets through, and the token survived the refusal.
+        service
+            .update_user(&ann.id, &rename("Ann B"), Some("alice"), None)
+            .await
+            .unwrap()
+            .unwrap();
+        let confirmed = service
+            .confirm_email_change(&token, Some("alice"))
+            .await
+            .unwrap();
+        assert_eq!(confirmed.data.email, "ann2@example.com");
+        service
+            .delete_user("ann2@example.com", Some("alice"))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn edit_locks_are_advisory_unless_enforced() {
+        let service = service(AppConfig::default());
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+        service.lock_user(&ann.id, "alice").await.unwrap();
+
+        let updated = service
+            .update_user(&ann.id, &rename("Ann B"), Some("bob"), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(updated.data.name, "Ann B");
+    }
+
+    #[tokio::test]
+    async fn download_link_waits_for_the_file_and_works_once() {
+        let service = service(AppConfig::default());
+        let path = std::env::temp_dir().join(format!("export_{}.csv", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        service.downloads.insert("token".to_string(), path.clone());
+
+        let pending = service.take_download("token").await.unwrap_err();
+        assert!(matches!(pending, AppError::NotFound(m) if m.contains("not ready")));
+
+        tokio::fs::write(&path, "id\n").await.unwrap();
+        assert_eq!(service.take_download("token").await.unwrap(), path);
+        assert!(matches!(
+            service.take_download("token").await,
+            Err(AppError::NotFound(_))
+        ));
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_queueing_leaves_no_download_link() {
+        let service = service(AppConfig::default());
+
+        let err = service
+            .queue_export(&ExportFilter::default(), CsvDialect::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AppError::ServiceUnavailable(_)));
+        assert!(service.downloads.is_empty());
+    }
+
+    #[tokio::test]
+    async fn export_since_leaves_out_older_users() {
+        let (service, clock) = clocked_service(AppConfig::default());
+        service
+            .create_user(&request("Old", "old@example.com", 30))
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::days(2));
+        let since = clock.now() - chrono::Duration::hours(1);
+        service
+            .create_user(&request("New", "new@example.com", 30))
+            .await
+            .unwrap();
+        let filter = ExportFilter {
+            since: Some(since),
+            until: None,
+        };
+
+        let users = service.export_users(&filter).await.unwrap();
+        let emails: Vec<&str> = users.iter().map(|user| user.email.as_str()).collect();
+        assert_eq!(emails, vec!["new@example.com"]);
+
+        let csv = service
+            .render_csv(&filter, &CsvDialect::default())
+            .await
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains("new@example.com"));
+        assert!(!csv.contains("old@example.com"));
+    }
+
+    #[tokio::test]
+    async fn export_since_keeps_older_users_updated_since() {
+        let (service, clock) = clocked_service(AppConfig::default());
+        let old = service
+            .create_user(&request("Old", "old@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+        clock.advance(chrono::Duration::days(2));
+        let since = clock.now() - chrono::Duration::hours(1);
+        service
+            .update_user(&old.id, &rename("Old Renamed"), None, None)
+            .await
+            .unwrap();
+
+        let users = service
+            .export_users(&ExportFilter {
+                since: Some(since),
+                until: None,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "Old Renamed");
+    }
+
+    #[tokio::test]
+    async fn timeseries_buckets_follow_the_clock() {
+        use chrono::TimeZone;
+
+        let (service, clock) = clocked_service(AppConfig {
+            stats_bucket_secs: 60,
+            ..AppConfig::default()
+        });
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 10).unwrap();
+        clock.set(start);
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+        service
+            .create_user(&request("Bob", "bob@example.com", 30))
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::seconds(30));
+        service.find_by_id(&ann.id).await.unwrap();
+        clock.advance(chrono::Duration::seconds(30));
+        service
+            .update_user(&ann.id, &rename("Ann B"), None, None)
+            .await
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(2));
+        service.delete_user("bob@example.com", None).await.unwrap();
+
+        let buckets = service
+            .get_stats_timeseries(chrono::Duration::hours(1))
+            .await;
+
+        let minute = |m| Utc.with_ymd_and_hms(2024, 1, 1, 0, m, 0).unwrap();
+        let summary: Vec<_> = buckets
+            .iter()
+            .map(|b| {
+                let s = &b.stats;
+                (
+                    b.start,
+                    s.total_operations,
+                    s.create_count,
+                    s.read_count,
+                    s.update_count,
+                    s.delete_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (minute(0), 3, 2, 1, 0, 0),
+                (minute(1), 1, 0, 0, 1, 0),
+                (minute(3), 1, 0, 0, 0, 1),
+            ]
+        );
+
+        let recent = service
+            .get_stats_timeseries(chrono::Duration::minutes(1))
+            .await;
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].start, minute(3));
+    }
+
+    #[tokio::test]
+    async fn blocked_domains_are_refused_on_create_update_and_import() {
+        let service = service(AppConfig {
+            email_domain_policy: EmailDomainPolicy::new(
+                Vec::new(),
+                vec!["spam.example".to_string()],
+            ),
+            ..AppConfig::default()
+        });
+        fn blocked<T>(result: Result<T, AppError>) -> bool {
+            matches!(result, Err(AppError::ValidationError(m)) if m.contains("blocked by policy"))
+        }
+
+        assert!(blocked(
+            service
+                .create_user(&request("Ann", "ann@spam.example", 30))
+                .await
+        ));
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+        let update = UpdateUserRequest {
+            email: Some("ann@spam.example".to_string()),
+            ..rename("Ann")
+        };
+        assert!(blocked(
+            service.update_user(&ann.id, &update, None, None).await
+        ));
+        assert!(blocked(
+            service
+                .import_csv_bytes(import_csv(&[("Bob", "bob@spam.example", 30)]), None)
+                .await
+        ));
+    }
+
+    #[tokio::test]
+    async fn import_with_a_three_field_row_fails_without_inserting() {
+        let service = service(AppConfig::default());
+        let csv = b"id,name,email,age,created_at,updated_at\n,Ann,ann@example.com,30,,\n,Bob,bob@example.com\n";
+
+        let err = service
+            .import_csv_bytes(csv.to_vec(), None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AppError::CsvError(m) if m.starts_with("Line 3:")));
+        assert_eq!(service.repo.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn email_domains_are_counted_and_sorted_by_count() {
+        let service = service(AppConfig::default());
+        for email in [
+            "a@corp.example",
+            "b@Corp.Example",
+            "c@corp.example",
+            "d@mail.example",
+            "e@mail.example",
+            "f@solo.example",
+            "g@alpha.example",
+        ] {
+            service
+                .create_user(&request("User", email, 30))
+                .await
+                .unwrap();
+        }
+
+        let counts: Vec<(String, usize)> = service
+            .email_domain_counts()
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .map(|c| (c.domain, c.count))
+            .collect();
+
+        assert_eq!(
+            counts,
+            vec![
+                ("corp.example".to_string(), 3),
+                ("mail.example".to_string(), 2),
+                ("alpha.example".to_string(), 1),
+                ("solo.example".to_string(), 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn broad_search_is_truncated_at_max_search_results() {
+        let service = service(AppConfig {
+            max_search_results: 3,
+            ..AppConfig::default()
+        });
+        for i in 0..5 {
+            service
+                .create_user(&request("Ann", &format!("ann{i}@example.com"), 30))
+                .await
+                .unwrap();
+        }
+
+        let found = service
+            .search_users(SearchQuery {
+                q: "ann".to_string(),
+                search_field: SearchField::default(),
+                page: None,
+                page_size: None,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(found.data.len(), 3);
+        assert_eq!(found.total, 5);
+        assert!(found.truncated);
+    }
+
+    #[tokio::test]
+    async fn truncated_only_when_the_cap_drops_matches() {
+        let service = service(AppConfig {
+            max_search_results: 3,
+            ..AppConfig::default()
+        });
+        let page = |page| SearchQuery {
+            q: "ann".to_string(),
+            search_field: SearchField::default(),
+            page: Some(page),
+            page_size: Some(2),
+        };
+        for i in 0..3 {
+            service
+                .create_user(&request("Ann", &format!("ann{i}@example.com"), 30))
+                .await
+                .unwrap();
+        }
+
+        let first = service.search_users(page(1)).await.unwrap();
+        assert_eq!(first.data.len(), 2);
+        assert!(!first.truncated, "a later page is not truncation");
+
+        service
+            .create_user(&request("Ann", "ann3@example.com", 30))
+            .await
+            .unwrap();
+        let last = service.search_users(page(2)).await.unwrap();
+        assert_eq!(last.data.len(), 1);
+        assert_eq!(last.total, 4);
+        assert!(last.truncated);
+    }
+
+    #[tokio::test]
+    async fn similar_finds_near_duplicate_names_and_shared_local_parts() {
+        let service = service(AppConfig::default());
+        let mut ids = Vec::new();
+        for (name, email) in [
+            ("Jonathan Smith", "jonathan@example.com"),
+            ("Jonathon Smith", "jsmith@example.com"),
+            ("Someone Else", "jonathan@other.example"),
+            ("Mary Jones", "mary@example.com"),
+        ] {
+            let created = service
+                .create_user(&request(name, email, 30))
+                .await
+                .unwrap();
+            ids.push(created.data.id);
+        }
+
+        let similar = service.find_similar(&ids[0]).await.unwrap().unwrap().data;
+
+        let found: Vec<_> = similar
+            .iter()
+            .map(|s| (s.user.id.as_str(), s.reason, s.distance))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ids[2].as_str(), SimilarityReason::EmailLocalPart, 0),
+                (ids[1].as_str(), SimilarityReason::Name, 1),
+            ]
+        );
+    }
+
+    fn with_id(id: &str, name: &str, email: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            id: Some(id.to_string()),
+            ..request(name, email, 30)
+        }
+    }
+
+    fn client_id_service() -> SharedState {
+        service(AppConfig {
+            allow_client_ids: true,
+            ..AppConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn client_supplied_id_is_kept_when_allowed() {
+        let service = client_id_service();
+        let id = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+
+        let created = service
+            .create_user(&with_id(id, "Ann", "ann@example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(created.data.id, id);
+        assert!(service.find_by_id(id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn colliding_client_id_is_rejected() {
+        let service = client_id_service();
+        let id = Uuid::new_v4().to_string();
+        service
+            .create_user(&with_id(&id, "Ann", "ann@example.com"))
+            .await
+            .unwrap();
+
+        let Err(err) = service
+            .create_user(&with_id(&id, "Bob", "bob@example.com"))
+            .await
+        else {
+            panic!("create with a client id succeeded");
+        };
+
+        assert!(matches!(err, AppError::Conflict(m) if m == "Id already exists"));
+        let kept = service.find_by_id(&id).await.unwrap().unwrap();
+        assert_eq!(kept.data.name, "Ann");
+        assert_eq!(service.repo.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn ids_are_server_generated_by_default() {
+        let service = service(AppConfig::default());
+
+        let created = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap();
+        assert!(Uuid::parse_str(&created.data.id).is_ok());
+
+        let id = Uuid::new_v4().to_string();
+        let Err(err) = service
+            .create_user(&with_id(&id, "Bob", "bob@example.com"))
+            .await
+        else {
+            panic!("create with a client id succeeded");
+        };
+        assert!(matches!(err, AppError::ValidationError(_)));
+    }
+
+    #[tokio::test]
+    async fn import_keeps_ids_from_the_file_when_allowed() {
+        let id = Uuid::new_v4().to_string();
+        let csv = format!(
+            "id,name,email,age,created_at,updated_at\n{id},Ann,ann@example.com,30,,\n,Bob,bob@example.com,40,,\n"
+        );
+
+        let allowed = client_id_service();
+        allowed
+            .import_csv_bytes(csv.clone().into_bytes(), None)
+            .await
+            .unwrap();
+        let kept = allowed.find_by_id(&id).await.unwrap().unwrap();
+        assert_eq!(kept.data.email, "ann@example.com");
+        assert_eq!(allowed.repo.count().await.unwrap(), 2);
+
+        let default = service(AppConfig::default());
+        default
+            .import_csv_bytes(csv.into_bytes(), None)
+            .await
+            .unwrap();
+        assert!(default.find_by_id(&id).await.unwrap().is_none());
+        assert_eq!(default.repo.count().await.unwrap(), 2);
+    }
+
+    fn csv_user() -> User {
+        let at = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        User {
+            id: "u1".to_string(),
+            name: "Ann".to_string(),
+            email: "ann@example.com".to_string(),
+            age: 30,
+            created_at: at,
+            updated_at: at,
+            expires_at: None,
+        }
+    }
+
+    fn csv_text(dialect: &CsvDialect) -> String {
+        String::from_utf8(write_csv(&[csv_user()], true, dialect).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn default_dialect_quotes_only_when_needed() {
+        assert_eq!(
+            csv_text(&CsvDialect::default()),
+            "id,name,email,age,created_at,updated_at\n\
+             u1,Ann,ann@example.com,30,2024-01-01T00:00:00Z,2024-01-01T00:00:00Z\n"
+        );
+    }
+
+    #[test]
+    fn always_quote_mode_quotes_every_field() {
+        let text = csv_text(&CsvDialect {
+            quote_style: CsvQuoteStyle::Always,
+            quote: '\'',
+            ..CsvDialect::default()
+        });
+
+        for line in text.lines() {
+            for field in line.split(',') {
+                assert!(field.starts_with('\'') && field.ends_with('\''), "{field}");
             }
+        }
+    }
 
-            let age: u8 = age_str
-                .parse()
-                .map_err(|_| AppError::ValidationError("Invalid age: not a number".to_string()))?;
+    #[test]
+    fn custom_terminator_ends_every_row() {
+        let text = csv_text(&CsvDialect {
+            terminator: CsvTerminator::Crlf,
+            ..CsvDialect::default()
+        });
 
-            requests.push(CreateUserRequest {
-                name: name.to_string(),
-                email: email.to_lowercase(),
-                age,
-            });
+        assert_eq!(text.matches("\r\n").count(), 2);
+        assert_eq!(text.matches('\n').count(), 2);
+        assert!(text.ends_with("\r\n"));
+    }
+
+    #[test]
+    fn chosen_columns_are_written_in_order() {
+        let text = csv_text(&CsvDialect {
+            quote_style: CsvQuoteStyle::NonNumeric,
+            columns: Some("age,email,id".to_string()),
+            ..CsvDialect::default()
+        });
+
+        assert_eq!(
+            text,
+            "\"age\",\"email\",\"id\"\n30,\"ann@example.com\",\"u1\"\n"
+        );
+    }
+
+    #[test]
+    fn unknown_export_column_is_rejected() {
+        let dialect = CsvDialect {
+            columns: Some("name,password".to_string()),
+            ..CsvDialect::default()
+        };
+
+        assert!(dialect.columns().is_err());
+        assert!(matches!(
+            write_csv(&[csv_user()], true, &dialect),
+            Err(AppError::CsvError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_user_disappears_before_and_after_the_sweep() {
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let repo = Arc::new(InMemoryUserRepository::with_clock(clock.clone()));
+        let service = ServiceBuilder::new(AppConfig::default())
+            .repository(repo.clone())
+            .without_kafka()
+            .clock(clock.clone())
+            .build()
+            .service;
+        let ephemeral = service
+            .create_user(&CreateUserRequest {
+                expires_at: Some(clock.now() + chrono::Duration::minutes(1)),
+                ..request("Ephemeral", "temp@example.com", 30)
+            })
+            .await
+            .unwrap()
+            .data;
+        assert!(service.find_by_id(&ephemeral.id).await.unwrap().is_some());
+
+        clock.advance(chrono::Duration::minutes(2));
+
+        assert!(service.find_by_id(&ephemeral.id).await.unwrap().is_none());
+        let listing = service
+            .get_users(FindAllUserRequest {
+                page: 1,
+                page_size: 10,
+                search: None,
+                search_field: SearchField::All,
+            })
+            .await
+            .unwrap();
+        assert_eq!(listing.total, 0);
+        // Still stored until the sweeper runs.
+        assert_eq!(repo.db.len(), 1);
+
+        assert_eq!(service.purge_expired_users().await.unwrap(), 1);
+        assert!(repo.db.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expiry_must_be_in_the_future() {
+        let (service, clock) = clocked_service(AppConfig::default());
+
+        let Err(err) = service
+            .create_user(&CreateUserRequest {
+                expires_at: Some(clock.now()),
+                ..request("Late", "late@example.com", 30)
+            })
+            .await
+        else {
+            panic!("created a user that is already expired");
+        };
+
+        assert!(matches!(err, AppError::FieldErrors(errors) if errors[0].field == "expires_at"));
+    }
+
+    fn search(q: &str) -> SearchQuery {
+        SearchQuery {
+            q: q.to_string(),
+            search_field: SearchField::default(),
+            page: None,
+            page_size: None,
         }
+    }
 
-        println!(
-            "📦 Found {} records, starting bulk insert...",
-            requests.len()
+    #[tokio::test]
+    async fn search_terms_below_the_minimum_length_are_rejected() {
+        let service = service(AppConfig::default());
+        service
+            .create_user(&request("Éa Ann", "ann@example.com", 30))
+            .await
+            .unwrap();
+
+        for q in ["a", "é", " a "] {
+            let Err(err) = service.search_users(search(q)).await else {
+                panic!("{q:?} was accepted");
+            };
+            assert!(
+                matches!(&err, AppError::ValidationError(m) if m == "Search query too short"),
+                "{q}: {err}"
+            );
+        }
+        // The term is searched for as validated, without its padding.
+        for q in ["an", "éa", " an "] {
+            let found = service.search_users(search(q)).await.unwrap();
+            assert_eq!(found.total, 1, "{q}");
+        }
+    }
+
+    #[tokio::test]
+    async fn minimum_search_length_is_configurable() {
+        let service = service(AppConfig {
+            min_search_len: 4,
+            ..AppConfig::default()
+        });
+
+        assert!(service.search_users(search("ann")).await.is_err());
+        assert!(service.search_users(search("anne")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn bulk_report_follows_input_order() {
+        let service = service(AppConfig {
+            bulk_concurrency: 3,
+            ..AppConfig::default()
+        });
+        service
+            .create_user(&request("Taken", "taken@example.com", 30))
+            .await
+            .unwrap();
+        let inputs: Vec<_> = (0..20)
+            .map(|i| match i % 7 {
+                3 => request("Dup", "taken@example.com", 30),
+                _ => request("User", &format!("user{i}@example.com"), 30),
+            })
+            .collect();
+
+        let report = service.bulk_create_users(inputs).await.unwrap();
+
+        assert_eq!((report.created, report.failed), (17, 3));
+        for (position, item) in report.items.iter().enumerate() {
+            assert_eq!(item.index, position);
+            assert_eq!(item.error.is_some(), position % 7 == 3, "{position}");
+        }
+    }
+
+    #[tokio::test]
+    async fn bulk_create_rejects_an_invalid_item_without_validate_first() {
+        let service = service(AppConfig::default());
+
+        let report = service
+            .bulk_create_users(vec![
+                request("Ann", "ann@example.com", 30),
+                request("Old", "old@example.com", 200),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!((report.created, report.failed), (1, 1));
+        assert_eq!(report.items[1].status, 422);
+        assert_eq!(service.repo.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn validate_first_rejects_the_whole_batch() {
+        let service = service(AppConfig {
+            bulk_validate_first: true,
+            ..AppConfig::default()
+        });
+
+        let Err(AppError::FieldErrors(errors)) = service
+            .bulk_create_users(vec![
+                request("Ann", "ann@example.com", 30),
+                request("Bob", "not-an-email", 30),
+                request("Ann Again", "ANN@example.com", 30),
+            ])
+            .await
+        else {
+            panic!("invalid batch accepted");
+        };
+
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["items[1].email", "items[2].email"]);
+        assert!(
+            errors[1].message.contains("item 0"),
+            "{}",
+            errors[1].message
         );
+        assert_eq!(service.repo.count().await.unwrap(), 0);
+    }
 
-        self.bulk_create_users(requests.clone())
+    fn merge(keep: &str, remove: &str, copy: Vec<MergeField>) -> MergeUsersRequest {
+        MergeUsersRequest {
+            keep: keep.to_string(),
+            remove: remove.to_string(),
+            copy,
+        }
+    }
+
+    #[tokio::test]
+    async fn merge_moves_the_chosen_fields_and_deletes_the_duplicate() {
+        let service = service(AppConfig::default());
+        let keep = service
+            .create_user(&request("Ann", "ann.old@example.com", 30))
             .await
-            .map_err(|e| {
-                eprintln!("❌ Bulk create failed: {}", e);
-                e
-            })?;
+            .unwrap()
+            .data;
+        let dup = service
+            .create_user(&request("Ann Lee", "ann@example.com", 31))
+            .await
+            .unwrap()
+            .data;
 
-        println!(
-            "✅ Successfully imported {} users from {}",
-            requests.len(),
-            path
+        let merged = service
+            .merge_users(&merge(&keep.id, &dup.id, vec![MergeField::Email]), None)
+            .await
+            .unwrap()
+            .data;
+
+        assert_eq!(merged.id, keep.id);
+        assert_eq!(
+            (merged.name.as_str(), merged.email.as_str()),
+            ("Ann", "ann@example.com")
+        );
+        assert!(service.find_by_id(&dup.id).await.unwrap().is_none());
+        let by_email = service.repo.find_by_email("ann@example.com").await.unwrap();
+        assert_eq!(by_email.unwrap().id, keep.id);
+        // The kept user's old address is free again.
+        assert!(
+            service
+                .create_user(&request("Other", "ann.old@example.com", 40))
+                .await
+                .is_ok()
         );
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn merge_rejects_self_merges_and_missing_users() {
+        let service = service(AppConfig::default());
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+
+        let Err(err) = service
+            .merge_users(&merge(&ann.id, &ann.id, vec![]), None)
+            .await
+        else {
+            panic!("self-merge accepted");
+        };
+        assert!(matches!(err, AppError::FieldErrors(_)));
+        let Err(err) = service
+            .merge_users(&merge(&ann.id, "missing", vec![]), None)
+            .await
+        else {
+            panic!("merge of a missing user accepted");
+        };
+        assert!(matches!(err, AppError::UserNotFound));
+        assert!(service.find_by_id(&ann.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn max_users_caps_single_and_bulk_creates() {
+        let service = service(AppConfig {
+            max_users: 3,
+            ..AppConfig::default()
+        });
+        for (name, email) in [("Ann", "ann@example.com"), ("Bob", "bob@example.com")] {
+            service
+                .create_user(&request(name, email, 30))
+                .await
+                .unwrap();
+        }
+
+        let report = service
+            .bulk_create_users(vec![
+                request("Cat", "cat@example.com", 30),
+                request("Dan", "dan@example.com", 30),
+                request("Eve", "eve@example.com", 30),
+            ])
+            .await
+            .unwrap();
+        assert_eq!((report.created, report.failed), (1, 2));
+        let statuses: Vec<_> = report.items.iter().map(|item| item.status).collect();
+        assert_eq!(statuses, vec![201, 507, 507]);
+
+        let Err(err) = service
+            .create_user(&request("Fay", "fay@example.com", 30))
+            .await
+        else {
+            panic!("create past max_users accepted");
+        };
+        assert!(matches!(err, AppError::InsufficientStorage(_)));
+        assert_eq!(service.repo.count().await.unwrap(), 3);
+
+        // Deleting frees room again.
+        service.delete_user("ann@example.com", None).await.unwrap();
+        assert!(
+            service
+                .create_user(&request("Fay", "fay@example.com", 30))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_bulk_items_leave_their_room_to_the_rest() {
+        let service = service(AppConfig {
+            max_users: 3,
+            ..AppConfig::default()
+        });
+        service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap();
+
+        let report = service
+            .bulk_create_users(vec![
+                request("Ann Again", "ann@example.com", 30),
+                request("Bob", "bob@example.com", 30),
+                request("Cat", "cat@example.com", 30),
+                request("Dan", "dan@example.com", 30),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!((report.created, report.failed), (2, 2));
+        let statuses: Vec<_> = report.items.iter().map(|item| item.status).collect();
+        assert_eq!(statuses, vec![409, 201, 201, 507]);
+        assert_eq!(service.repo.count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn import_stops_at_max_users() {
+        let service = service(AppConfig {
+            max_users: 3,
+            import_progress_every: 2,
+            ..AppConfig::default()
+        });
+        service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap();
+        let mut csv = String::from("id,name,email,age,created_at,updated_at\n");
+        for i in 0..5 {
+            csv.push_str(&format!(",User {i},user{i}@example.com,30,,\n"));
+        }
+
+        let Err(err) = service.import_csv_bytes(csv.into_bytes(), None).await else {
+            panic!("import past max_users accepted");
+        };
+
+        let AppError::InsufficientStorage(message) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert!(message.contains("after importing 2 of 5 rows"), "{message}");
+        assert_eq!(service.repo.count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn blank_search_is_rejected_or_matches_all_per_config() {
+        let rejecting = service(AppConfig::default());
+        let Err(err) = rejecting.search_users(search("  ")).await else {
+            panic!("blank search accepted");
+        };
+        assert!(
+            matches!(&err, AppError::ValidationError(m) if m == "Search query must not be empty")
+        );
+
+        let matching = service(AppConfig {
+            empty_search: EmptySearch::MatchAll,
+            ..AppConfig::default()
+        });
+        for (name, email) in [("Ann", "ann@example.com"), ("Bob", "bob@example.com")] {
+            matching
+                .create_user(&request(name, email, 30))
+                .await
+                .unwrap();
+        }
+        let found = matching.search_users(search("")).await.unwrap();
+        assert_eq!((found.total, found.data.len()), (2, 2));
+        // A non-blank term still has to clear the minimum length.
+        assert!(matching.search_users(search("a")).await.is_err());
+    }
+
+    async fn email_change_token(service: &SharedState, id: &str, email: &str) -> String {
+        service
+            .request_email_change(
+                id,
+                &EmailChangeRequest {
+                    email: email.to_string(),
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap()
+            .data
+            .token
+    }
+
+    fn invalid_token<T>(result: Result<T, AppError>) -> bool {
+        matches!(result, Err(AppError::ValidationError(m)) if m == "Invalid or expired email change token")
+    }
+
+    #[tokio::test]
+    async fn email_change_applies_only_once_confirmed() {
+        let service = service(AppConfig::default());
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+
+        let token = email_change_token(&service, &ann.id, "ann@new.example").await;
+        let pending = service.find_by_id(&ann.id).await.unwrap().unwrap();
+        assert_eq!(pending.data.email, "ann@example.com");
+
+        let confirmed = service.confirm_email_change(&token, None).await.unwrap();
+        assert_eq!(confirmed.data.email, "ann@new.example");
+        assert!(
+            service
+                .repo
+                .find_by_email("ann@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        // Tokens are single-use.
+        assert!(invalid_token(
+            service.confirm_email_change(&token, None).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_email_change_token_is_rejected() {
+        let (service, clock) = clocked_service(AppConfig {
+            email_change_ttl_secs: 60,
+            ..AppConfig::default()
+        });
+        let ann = service
+            .create_user(&request("Ann", "ann@example.com", 30))
+            .await
+            .unwrap()
+            .data;
+        let token = email_change_token(&service, &ann.id, "ann@new.example").await;
+
+        clock.advance(chrono::Duration::seconds(61));
+
+        assert!(invalid_token(
+            service.confirm_email_change(&token, None).await
+        ));
+        let kept = service.find_by_id(&ann.id).await.unwrap().unwrap();
+        assert_eq!(kept.data.email, "ann@example.com");
+    }
+
+    #[tokio::test]
+    async fn unknown_email_change_token_is_rejected() {
+        let service = service(AppConfig::default());
+
+        assert!(invalid_token(
+            service.confirm_email_change("not-a-token", None).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn latin1_import_keeps_accented_names() {
+        let service = service(AppConfig::default());
+        let csv =
+            b"id,name,email,age,created_at,updated_at\n,Jos\xE9,jose@example.com,30,,\n".to_vec();
+
+        assert_eq!(service.import_csv_bytes(csv, None).await.unwrap(), 1);
+
+        let jose = service
+            .repo
+            .find_by_email("jose@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(jose.name.to_lowercase(), "josé");
+    }
+
+    #[tokio::test]
+    async fn changes_window_must_not_be_inverted() {
+        let service = service(AppConfig::default());
+        let now = Utc::now();
+        let query = ChangesQuery {
+            since: Some(now),
+            until: Some(now - chrono::Duration::seconds(1)),
+            page: 1,
+            page_size: 10,
+        };
+        let Err(err) = service.get_changes(query).await else {
+            panic!("inverted window accepted");
+        };
+        assert!(matches!(err, AppError::ValidationError(_)));
     }
 }
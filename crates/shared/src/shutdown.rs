@@ -0,0 +1,153 @@
+use std::{future::Future, time::Duration};
+
+use tokio::sync::watch;
+
+pub fn channel() -> (ShutdownTrigger, ShutdownSignal) {
+    let (tx, rx) = watch::channel(false);
+    (ShutdownTrigger(tx), ShutdownSignal(rx))
+}
+
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        let _ = self.0.send(true);
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn recv(mut self) {
+        let _ = self.0.wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+pub async fn wait_for_os_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Drives `fut` to completion, but once shutdown is signalled only waits up
+/// to `grace` for it to finish. Returns `None` if the grace period ran out.
+pub async fn run_with_grace<F: Future>(
+    fut: F,
+    shutdown: ShutdownSignal,
+    grace: Duration,
+) -> Option<F::Output> {
+    tokio::pin!(fut);
+
+    tokio::select! {
+        out = &mut fut => return Some(out),
+        _ = shutdown.recv() => {}
+    }
+
+    match tokio::time::timeout(grace, fut).await {
+        Ok(out) => Some(out),
+        Err(_) => {
+            eprintln!(
+                "⏱️ Shutdown grace period of {:?} elapsed, aborting remaining work",
+                grace
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::IntoFuture, sync::Arc};
+
+    use axum::{Router, routing::get};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        sync::Notify,
+        time::Instant,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn grace_period_cuts_off_a_hanging_handler() {
+        let entered = Arc::new(Notify::new());
+        let router = Router::new().route(
+            "/hang",
+            get({
+                let entered = entered.clone();
+                move || async move {
+                    entered.notify_one();
+                    std::future::pending::<&str>().await
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, signal) = channel();
+        let server = axum::serve(listener, router)
+            .with_graceful_shutdown(signal.clone().recv())
+            .into_future();
+        let grace = Duration::from_millis(200);
+        let server = tokio::spawn(run_with_grace(server, signal, grace));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /hang HTTP/1.1\r\nhost: test\r\n\r\n")
+            .await
+            .unwrap();
+        entered.notified().await;
+        let started = Instant::now();
+        trigger.trigger();
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("shutdown did not finish")
+            .unwrap();
+        assert!(outcome.is_none());
+        assert!(started.elapsed() >= grace);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn work_finishing_within_the_grace_period_completes() {
+        let (trigger, signal) = channel();
+        trigger.trigger();
+
+        let outcome = run_with_grace(
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                42
+            },
+            signal,
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert_eq!(outcome, Some(42));
+    }
+}
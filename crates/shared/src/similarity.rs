@@ -0,0 +1,47 @@
+/// Edit distance between two strings, counted in characters.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// The part of an email before the last `@`, lowercased.
+pub fn email_local_part(email: &str) -> String {
+    email
+        .rsplit_once('@')
+        .map(|(local, _)| local)
+        .unwrap_or(email)
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levenshtein_counts_single_character_edits() {
+        assert_eq!(levenshtein("ann", "ann"), 0);
+        assert_eq!(levenshtein("jonathan", "jonathon"), 1);
+        assert_eq!(levenshtein("ann", "anne"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("zoë", "zoe"), 1);
+    }
+
+    #[test]
+    fn local_part_ignores_domain_and_case() {
+        assert_eq!(email_local_part("Ann.Lee@Example.com"), "ann.lee");
+        assert_eq!(email_local_part("odd@name@example.com"), "odd@name");
+        assert_eq!(email_local_part("no-at-sign"), "no-at-sign");
+    }
+}
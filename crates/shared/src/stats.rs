@@ -0,0 +1,134 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::{domain::ServiceStats, errors::AppError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsBucket {
+    pub start: DateTime<Utc>,
+    #[serde(flatten)]
+    pub stats: ServiceStats,
+}
+
+/// Fixed-size ring buffer of per-bucket operation counts.
+#[derive(Debug)]
+pub struct StatsTimeseries {
+    bucket_secs: i64,
+    retention: usize,
+    buckets: Mutex<VecDeque<StatsBucket>>,
+}
+
+impl StatsTimeseries {
+    pub fn new(bucket_secs: u64, retention: usize) -> Self {
+        Self {
+            bucket_secs: bucket_secs.max(1) as i64,
+            retention: retention.max(1),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record<F>(&self, now: DateTime<Utc>, f: F)
+    where
+        F: FnOnce(&mut ServiceStats),
+    {
+        let start = self.bucket_start(now);
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.back().is_none_or(|b| b.start != start) {
+            buckets.push_back(StatsBucket {
+                start,
+                stats: ServiceStats::default(),
+            });
+        }
+        if let Some(bucket) = buckets.back_mut() {
+            bucket.stats.total_operations += 1;
+            f(&mut bucket.stats);
+        }
+
+        while buckets.len() > self.retention {
+            buckets.pop_front();
+        }
+    }
+
+    /// Drops buckets that fell out of the retention window, which `record`
+    /// only does when traffic arrives. Returns how many were dropped.
+    pub fn trim(&self, now: DateTime<Utc>) -> usize {
+        let oldest = self.bucket_start(now)
+            - Duration::seconds(self.bucket_secs * (self.retention as i64 - 1));
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|b| b.start >= oldest);
+        before - buckets.len()
+    }
+
+    /// Buckets that started within `window` of `now`, oldest first.
+    pub fn window(&self, now: DateTime<Utc>, window: Duration) -> Vec<StatsBucket> {
+        let since = self.bucket_start(now - window);
+        self.buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|b| b.start >= since)
+            .cloned()
+            .collect()
+    }
+
+    fn bucket_start(&self, ts: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = ts.timestamp().div_euclid(self.bucket_secs) * self.bucket_secs;
+        Utc.timestamp_opt(secs, 0).single().unwrap_or(ts)
+    }
+}
+
+/// Parses windows such as `90s`, `15m`, `1h` or `2d`.
+pub fn parse_window(input: &str) -> Result<Duration, AppError> {
+    let input = input.trim();
+    let invalid = || AppError::ValidationError(format!("Invalid window: {input}"));
+
+    let (split, _) = input.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = input.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    if amount <= 0 {
+        return Err(invalid());
+    }
+
+    match unit {
+        "s" => Ok(Duration::seconds(amount)),
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_drops_buckets_past_retention_without_new_traffic() {
+        let series = StatsTimeseries::new(60, 3);
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for minute in 0..3 {
+            series.record(start + Duration::minutes(minute), |s| s.create_count += 1);
+        }
+
+        assert_eq!(series.trim(start + Duration::minutes(2)), 0);
+        assert_eq!(series.trim(start + Duration::minutes(4)), 2);
+        let left = series.window(start + Duration::minutes(4), Duration::hours(1));
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].start, start + Duration::minutes(2));
+    }
+
+    #[test]
+    fn windows_parse_with_a_unit() {
+        assert_eq!(parse_window("90s").unwrap(), Duration::seconds(90));
+        assert_eq!(parse_window("15m").unwrap(), Duration::minutes(15));
+        assert_eq!(parse_window("1h").unwrap(), Duration::hours(1));
+        assert_eq!(parse_window("2d").unwrap(), Duration::days(2));
+        for bad in ["", "h", "0h", "-1h", "1w", "abc"] {
+            assert!(parse_window(bad).is_err(), "{bad}");
+        }
+    }
+}
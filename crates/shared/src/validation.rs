@@ -0,0 +1,448 @@
+use std::cell::{Cell, RefCell};
+
+use serde::{
+    Deserializer, Serialize,
+    de::{self, Visitor},
+};
+
+use crate::domain::{CreateUserRequest, EmailChangeRequest, MergeUsersRequest, UpdateUserRequest};
+
+pub const MAX_AGE: u8 = 150;
+
+/// Converts an age from the wire, rejecting anything outside `0..=MAX_AGE`
+/// with the same message `validate_age` uses.
+pub fn age_from_wire(age: i64) -> Result<u8, String> {
+    u8::try_from(age)
+        .ok()
+        .filter(|age| *age <= MAX_AGE)
+        .ok_or_else(|| format!("Age must be between 0 and {MAX_AGE}"))
+}
+
+/// Length caps on free-text fields, checked while the JSON body is parsed so
+/// an oversized string is rejected before it is copied into the DTO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldLimits {
+    pub max_name_len: usize,
+    pub max_email_len: usize,
+}
+
+impl Default for FieldLimits {
+    fn default() -> Self {
+        Self {
+            max_name_len: 100,
+            max_email_len: 254,
+        }
+    }
+}
+
+thread_local! {
+    static FIELD_LIMITS: Cell<FieldLimits> = Cell::new(FieldLimits::default());
+    static REJECTED_FIELD: RefCell<Option<FieldError>> = const { RefCell::new(None) };
+}
+
+impl FieldLimits {
+    /// Makes these limits visible to the DTO deserializers while `f` runs.
+    /// Deserialization is synchronous, so a thread-local is enough to carry
+    /// request config into serde without a custom seed per DTO.
+    pub fn scope<R>(self, f: impl FnOnce() -> R) -> R {
+        REJECTED_FIELD.take();
+        let previous = FIELD_LIMITS.replace(self);
+        let result = f();
+        FIELD_LIMITS.set(previous);
+        result
+    }
+
+    pub(crate) fn current() -> Self {
+        FIELD_LIMITS.get()
+    }
+
+    /// The field error a DTO deserializer raised during the last `scope`, so
+    /// the caller can answer with a 422 naming the field rather than a
+    /// generic parse failure.
+    pub fn take_rejected_field() -> Option<FieldError> {
+        REJECTED_FIELD.take()
+    }
+}
+
+/// Fails deserialization with `message` and remembers it as a field error
+/// for `FieldLimits::take_rejected_field`.
+pub(crate) fn reject_field<E: de::Error>(field: &str, message: String) -> E {
+    let error = E::custom(&message);
+    REJECTED_FIELD.set(Some(FieldError::new(field, message)));
+    error
+}
+
+struct BoundedString {
+    field: &'static str,
+    max: usize,
+}
+
+impl<'de> Visitor<'de> for BoundedString {
+    type Value = String;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a string of at most {} characters", self.max)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value.chars().count() > self.max {
+            return Err(reject_field(
+                self.field,
+                format!("{} must be at most {} characters", self.field, self.max),
+            ));
+        }
+        Ok(value.to_owned())
+    }
+}
+
+struct OptionalBoundedString(BoundedString);
+
+impl<'de> Visitor<'de> for OptionalBoundedString {
+    type Value = Option<String>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self.0).map(Some)
+    }
+}
+
+pub(crate) fn bounded_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    field: &'static str,
+    max: usize,
+) -> Result<String, D::Error> {
+    deserializer.deserialize_str(BoundedString { field, max })
+}
+
+pub(crate) fn bounded_optional_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    field: &'static str,
+    max: usize,
+) -> Result<Option<String>, D::Error> {
+    deserializer.deserialize_option(OptionalBoundedString(BoundedString { field, max }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+pub trait Validate {
+    /// Checks every field and returns all problems at once rather than
+    /// stopping at the first one.
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+impl Validate for CreateUserRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if let Some(id) = &self.id {
+            check(&mut errors, "id", validate_id(id));
+        }
+        check(&mut errors, "name", validate_name(&self.name));
+        check(&mut errors, "email", validate_email(&self.email));
+        check(&mut errors, "age", validate_age(self.age));
+        finish(errors)
+    }
+}
+
+impl Validate for EmailChangeRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check(&mut errors, "email", validate_email(&self.email));
+        finish(errors)
+    }
+}
+
+impl Validate for MergeUsersRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if self.keep == self.remove {
+            errors.push(FieldError::new("remove", "Cannot merge a user into itself"));
+        }
+        finish(errors)
+    }
+}
+
+impl Validate for UpdateUserRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if let Some(name) = &self.name {
+            check(
+                &mut errors,
+                "name",
+                reject_clear("name", name).and(validate_name(name)),
+            );
+        }
+        if let Some(email) = &self.email {
+            check(
+                &mut errors,
+                "email",
+                reject_clear("email", email).and(validate_email(email)),
+            );
+        }
+        if let Some(age) = self.age {
+            check(&mut errors, "age", validate_age(age));
+        }
+        finish(errors)
+    }
+}
+
+/// `Some("")` on an update reads like an attempt to clear the field, which
+/// name and email do not support. Say so instead of a generic format error.
+fn reject_clear(field: &str, value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        return Err(format!(
+            "{field} cannot be cleared; omit the field to leave it unchanged"
+        ));
+    }
+    Ok(())
+}
+
+const CROCKFORD_BASE32: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Accepts a UUID in any of its usual textual forms, or a 26-character ULID.
+pub fn validate_id(id: &str) -> Result<(), String> {
+    if uuid::Uuid::parse_str(id).is_ok() {
+        return Ok(());
+    }
+    let upper = id.to_ascii_uppercase();
+    let is_ulid = upper.len() == 26
+        && upper.starts_with(|c: char| ('0'..='7').contains(&c))
+        && upper.chars().all(|c| CROCKFORD_BASE32.contains(c));
+    if is_ulid {
+        Ok(())
+    } else {
+        Err("Id must be a UUID or ULID".to_string())
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Name is empty".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_email(email: &str) -> Result<(), String> {
+    let email = email.trim();
+    if email.is_empty() {
+        return Err("Email is empty".to_string());
+    }
+    match email.split_once('@') {
+        Some((local, domain))
+            if !local.is_empty() && domain.contains('.') && !domain.contains('@') =>
+        {
+            Ok(())
+        }
+        _ => Err("Invalid email format".to_string()),
+    }
+}
+
+pub fn validate_age(age: u8) -> Result<(), String> {
+    if age > MAX_AGE {
+        return Err(format!("Age must be between 0 and {MAX_AGE}"));
+    }
+    Ok(())
+}
+
+/// Allow/deny lists for email domains. An empty allow list permits every
+/// domain; the deny list always wins when a domain appears in both.
+#[derive(Debug, Clone, Default)]
+pub struct EmailDomainPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl EmailDomainPolicy {
+    pub fn new(allow: Vec<String>, deny: Vec<String>) -> Self {
+        Self {
+            allow: allow.iter().map(|d| normalize_domain(d)).collect(),
+            deny: deny.iter().map(|d| normalize_domain(d)).collect(),
+        }
+    }
+
+    pub fn check(&self, email: &str) -> Result<(), String> {
+        let domain = email
+            .rsplit_once('@')
+            .map(|(_, domain)| normalize_domain(domain))
+            .unwrap_or_default();
+
+        if self.deny.contains(&domain) {
+            return Err(format!("Email domain '{domain}' is blocked by policy"));
+        }
+        if !self.allow.is_empty() && !self.allow.contains(&domain) {
+            return Err(format!(
+                "Email domain '{domain}' is not in the allowed list"
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+fn check(errors: &mut Vec<FieldError>, field: &str, result: Result<(), String>) {
+    if let Err(message) = result {
+        errors.push(FieldError::new(field, message));
+    }
+}
+
+fn finish(errors: Vec<FieldError>) -> Result<(), Vec<FieldError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> EmailDomainPolicy {
+        let list = |domains: &[&str]| domains.iter().map(|d| d.to_string()).collect();
+        EmailDomainPolicy::new(list(allow), list(deny))
+    }
+
+    #[test]
+    fn allow_list_admits_only_listed_domains() {
+        let policy = policy(&["corp.example"], &[]);
+
+        assert!(policy.check("ann@corp.example").is_ok());
+        assert!(policy.check("ann@CORP.example.").is_ok());
+        let err = policy.check("ann@gmail.example").unwrap_err();
+        assert!(err.contains("not in the allowed list"), "{err}");
+    }
+
+    #[test]
+    fn deny_list_blocks_listed_domains() {
+        let policy = policy(&[], &["Mailinator.example"]);
+
+        assert!(policy.check("ann@corp.example").is_ok());
+        let err = policy.check("ann@mailinator.example").unwrap_err();
+        assert!(err.contains("blocked by policy"), "{err}");
+    }
+
+    #[test]
+    fn deny_list_wins_over_allow_list() {
+        let policy = policy(&["corp.example", "partner.example"], &["partner.example"]);
+
+        assert!(policy.check("ann@corp.example").is_ok());
+        assert!(
+            policy
+                .check("ann@partner.example")
+                .unwrap_err()
+                .contains("blocked")
+        );
+        assert!(policy.check("ann@other.example").is_err());
+    }
+
+    #[test]
+    fn empty_policy_allows_everything() {
+        assert!(policy(&[], &[]).check("ann@anything.example").is_ok());
+    }
+
+    fn update(name: Option<&str>, email: Option<&str>) -> UpdateUserRequest {
+        UpdateUserRequest {
+            name: name.map(str::to_string),
+            email: email.map(str::to_string),
+            age: None,
+        }
+    }
+
+    #[test]
+    fn empty_strings_on_update_are_rejected_as_clear_attempts() {
+        let errors = update(Some(""), Some("  ")).validate().unwrap_err();
+
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["name", "email"]);
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.message.contains("cannot be cleared"))
+        );
+    }
+
+    #[test]
+    fn omitted_fields_leave_the_update_valid() {
+        assert!(update(None, None).validate().is_ok());
+        assert!(update(Some("Ann"), None).validate().is_ok());
+    }
+
+    #[test]
+    fn field_limits_apply_only_within_their_scope() {
+        let body = r#"{"name":"Annabelle","email":"ann@example.com","age":30}"#;
+        let tight = FieldLimits {
+            max_name_len: 5,
+            ..FieldLimits::default()
+        };
+
+        let err = tight
+            .scope(|| serde_json::from_str::<CreateUserRequest>(body))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("name must be at most 5 characters")
+        );
+        let rejected = FieldLimits::take_rejected_field().unwrap();
+        assert_eq!(rejected.field, "name");
+        assert_eq!(rejected.message, "name must be at most 5 characters");
+
+        let request: CreateUserRequest = serde_json::from_str(body).unwrap();
+        assert_eq!(request.name, "Annabelle");
+    }
+
+    #[test]
+    fn over_length_email_is_rejected_on_update() {
+        let tight = FieldLimits {
+            max_email_len: 10,
+            ..FieldLimits::default()
+        };
+
+        let err = tight
+            .scope(|| serde_json::from_str::<UpdateUserRequest>(r#"{"email":"ann@example.com"}"#))
+            .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("email must be at most 10 characters")
+        );
+        assert_eq!(FieldLimits::take_rejected_field().unwrap().field, "email");
+    }
+
+    #[test]
+    fn ids_must_be_uuids_or_ulids() {
+        assert!(validate_id("67e55044-10b1-426f-9247-bb680e5fe0c8").is_ok());
+        assert!(validate_id("67e5504410b1426f9247bb680e5fe0c8").is_ok());
+        assert!(validate_id("01ARZ3NDEKTSV4RRFFQ69G5FAV").is_ok());
+        assert!(validate_id("01arz3ndektsv4rrffq69g5fav").is_ok());
+        // Larger than 128 bits, and `U` is not in the ULID alphabet.
+        assert!(validate_id("81ARZ3NDEKTSV4RRFFQ69G5FAV").is_err());
+        assert!(validate_id("01ARZ3NDEKTSV4RRFFQ69G5FAU").is_err());
+        assert!(validate_id("user-1").is_err());
+    }
+}
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shared::csv_import::{CsvImportOptions, parse_csv_requests};
+
+// Any input must produce `Ok` or `Err`, never a panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_csv_requests(data, &CsvImportOptions::default());
+});